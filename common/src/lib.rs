@@ -12,6 +12,100 @@ pub type WASMPtr = u32;
 pub type WASMLen = u32;
 pub type APIResult = i64;
 
+/// The version of the API offered by the host to the plugins. It is bumped on each change
+/// breaking the compatibility with already compiled plugins.
+#[macro_export]
+macro_rules! api_version {
+    () => {
+        1
+    };
+}
+
+/// The version of the API offered by the host to the plugins, see [`api_version`].
+pub const API_VERSION: u32 = api_version!();
+
+/// The errors returned by the host API functions. These functions return a non-negative value
+/// on success, and the code of one of these errors otherwise.
+///
+/// The codes are split in two ranges:
+/// - from -1 to -99, the call could not be served, because of the state of the host or of the
+///   values exchanged with the plugin;
+/// - from -100 downwards, the host refused the call as it breaks the policy set for the plugin,
+///   i.e., its permissions, its resource limits or the bounds of its memory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[repr(i64)]
+pub enum ApiErrorCode {
+    /// The instance of the calling plugin is not available.
+    NoInstance = -1,
+    /// The calling plugin does not export its memory.
+    NoMemory = -2,
+    /// The calling plugin is not attached to a plugin handler.
+    NoHandler = -3,
+    /// The plugin handler is not attached to a connection.
+    NoConnection = -4,
+    /// A value provided by the plugin, or to be returned to it, cannot be (de)serialized.
+    Serialize = -5,
+    /// The buffer provided by the plugin is too short to hold the result. The length of the
+    /// result is written in its first four bytes, as a little-endian `u32`.
+    BufferTooShort = -6,
+    /// The host refused to perform the operation.
+    HostRejected = -7,
+    /// The requested element, e.g., an input, a timer or a file, does not exist.
+    NotFound = -8,
+    /// An argument has an invalid value or type.
+    InvalidArgument = -9,
+    /// The `Bytes` cannot be accessed as requested.
+    BadBytes = -10,
+    /// The result is larger than what the host returns to plugins.
+    ValueTooLarge = -11,
+    /// The buffer provided by the plugin is too short to hold the result, and even its length.
+    BufferTooShortForLength = -12,
+    /// The calling plugin lacks the permission required by the call.
+    PermissionDenied = -100,
+    /// The calling plugin would exceed its maximum number of pending timers.
+    TooManyTimers = -101,
+    /// A pointer provided by the calling plugin, with its length, does not lie within the memory
+    /// of the plugin.
+    BadPointer = -102,
+}
+
+impl ApiErrorCode {
+    /// All the error codes.
+    pub const ALL: [ApiErrorCode; 15] = [
+        ApiErrorCode::NoInstance,
+        ApiErrorCode::NoMemory,
+        ApiErrorCode::NoHandler,
+        ApiErrorCode::NoConnection,
+        ApiErrorCode::Serialize,
+        ApiErrorCode::BufferTooShort,
+        ApiErrorCode::HostRejected,
+        ApiErrorCode::NotFound,
+        ApiErrorCode::InvalidArgument,
+        ApiErrorCode::BadBytes,
+        ApiErrorCode::ValueTooLarge,
+        ApiErrorCode::BufferTooShortForLength,
+        ApiErrorCode::PermissionDenied,
+        ApiErrorCode::TooManyTimers,
+        ApiErrorCode::BadPointer,
+    ];
+
+    /// The value returned by the host API functions for this error.
+    pub const fn code(self) -> APIResult {
+        self as APIResult
+    }
+
+    /// The error whose code is `code`, if any.
+    pub fn from_code(code: APIResult) -> Option<ApiErrorCode> {
+        Self::ALL.into_iter().find(|e| e.code() == code)
+    }
+}
+
+impl From<ApiErrorCode> for APIResult {
+    fn from(e: ApiErrorCode) -> Self {
+        e.code()
+    }
+}
+
 /// The different conversion errors that may arise with plugin-processable structures.
 #[derive(Clone, Debug)]
 pub enum ConversionError {
@@ -32,6 +126,10 @@ pub enum ConversionError {
     InvalidSentPacket,
     InvalidSocketAddr,
     InvalidQVal,
+    InvalidString,
+    InvalidByteVec,
+    InvalidList,
+    InvalidOption,
 }
 
 /// The actual plugin operations.
@@ -84,14 +182,18 @@ pub enum PluginOp {
 }
 
 /// The different anchors where plugin bytecodes can be attached.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Anchor {
     /// Execute just before calling the operation. Cannot modify the running context.
     Before,
     /// Execute in place of the operation. Can modify the running context.
     Define,
     /// Execute just after returning from the operation. Cannot modify the running context.
+    /// Receives the outputs of the operation, if it succeeded, after its inputs.
     After,
+    /// Execute once the operation returned, before the `After` anchors. Receives the outputs of
+    /// the operation after its inputs, and replaces them by its own outputs, if any.
+    Wrap,
 }
 
 impl Anchor {
@@ -101,20 +203,175 @@ impl Anchor {
             Anchor::Before => 0,
             Anchor::Define => 1,
             Anchor::After => 2,
+            Anchor::Wrap => 3,
         }
     }
 }
 
+/// Permission that can be granted to plugins.
+#[derive(Clone, Copy, Debug, Ord, PartialOrd, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum Permission {
+    /// Permission to save output (should be always granted)
+    Output,
+    /// Permission to read the opaque values given as inputs (should be always granted)
+    Opaque,
+    /// Permission to access the Connection state
+    ConnectionAccess,
+    /// Permission to access the write byte buffer
+    WriteBuffer,
+    /// Permission to access the read byte buffer
+    ReadBuffer,
+    /// Permission to create and write files on the host
+    FileSystem,
+    /// Permission to arm and cancel timers
+    Timers,
+    /// Permission to run operations from the plugin, through `poctl` or `call_default`
+    NestedCalls,
+}
+
+impl Permission {
+    /// All the permissions that can be granted.
+    pub const ALL: [Permission; 8] = [
+        Permission::Output,
+        Permission::Opaque,
+        Permission::ConnectionAccess,
+        Permission::WriteBuffer,
+        Permission::ReadBuffer,
+        Permission::FileSystem,
+        Permission::Timers,
+        Permission::NestedCalls,
+    ];
+
+    /// The name of the permission, as written in plugin manifests.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Permission::Output => "output",
+            Permission::Opaque => "opaque",
+            Permission::ConnectionAccess => "connection_access",
+            Permission::WriteBuffer => "write_buffer",
+            Permission::ReadBuffer => "read_buffer",
+            Permission::FileSystem => "file_system",
+            Permission::Timers => "timers",
+            Permission::NestedCalls => "nested_calls",
+        }
+    }
+
+    /// Get the permission having the provided name, if any.
+    pub fn from_name(name: &str) -> Option<Permission> {
+        Permission::ALL.into_iter().find(|p| p.name() == name)
+    }
+}
+
+/// The severity of a message logged by a plugin, following the levels of the `log` crate.
+#[derive(Clone, Copy, Debug, Ord, PartialOrd, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[repr(u32)]
+pub enum LogLevel {
+    /// Serious errors.
+    Error = 1,
+    /// Hazardous situations.
+    Warn,
+    /// Useful information.
+    Info,
+    /// Lower priority information.
+    Debug,
+    /// Very low priority, often extremely verbose, information.
+    Trace,
+}
+
+impl LogLevel {
+    /// Get the level having the provided numeric value, if any.
+    pub fn from_u32(v: u32) -> Option<LogLevel> {
+        match v {
+            1 => Some(LogLevel::Error),
+            2 => Some(LogLevel::Warn),
+            3 => Some(LogLevel::Info),
+            4 => Some(LogLevel::Debug),
+            5 => Some(LogLevel::Trace),
+            _ => None,
+        }
+    }
+}
+
+/// A message logged by a plugin, along with its structured fields.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogRecord {
+    /// The part of the plugin the message relates to. It can be empty.
+    pub target: String,
+    /// The message itself.
+    pub message: String,
+    /// Key-value pairs giving context to the message.
+    pub fields: Vec<(String, String)>,
+}
+
 fn extract_po_param(name: &str) -> Result<u64, ParseIntError> {
     let end_num = name.rfind('_').map(|i| &name[i + 1..]).unwrap_or("");
     u64::from_str_radix(end_num, 16)
 }
 
+/// The errors that may arise when converting an export name into a protocol operation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PluginOpNameError {
+    /// The parameter of the named operation is not a valid hexadecimal number.
+    InvalidParameter(String),
+    /// The name does not fit in a [`PluginOp::Other`].
+    NameTooLong(String),
+}
+
+impl std::fmt::Display for PluginOpNameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PluginOpNameError::InvalidParameter(n) => {
+                write!(f, "invalid protocol operation parameter in \"{n}\"")
+            }
+            PluginOpNameError::NameTooLong(n) => {
+                write!(f, "protocol operation name \"{n}\" is too long")
+            }
+        }
+    }
+}
+
+/// Builds a protocol operation from its parameter.
+type ParametrizedOp = fn(u64) -> PluginOp;
+
+/// The protocol operations taking a parameter, with the prefix of their name.
+const PARAMETRIZED_OPS: [(&str, ParametrizedOp); 13] = [
+    (
+        "decode_transport_parameter_",
+        PluginOp::DecodeTransportParameter,
+    ),
+    (
+        "write_transport_parameter_",
+        PluginOp::WriteTransportParameter,
+    ),
+    ("log_frame_", PluginOp::LogFrame),
+    ("notify_frame_", PluginOp::NotifyFrame),
+    ("on_frame_reserved_", PluginOp::OnFrameReserved),
+    ("parse_frame_", PluginOp::ParseFrame),
+    ("prepare_frame_", PluginOp::PrepareFrame),
+    ("process_frame_", PluginOp::ProcessFrame),
+    ("should_send_frame_", PluginOp::ShouldSendFrame),
+    ("wire_len_", PluginOp::WireLen),
+    ("write_frame_", PluginOp::WriteFrame),
+    ("plugin_control_", PluginOp::PluginControl),
+    ("on_plugin_timeout_", PluginOp::OnPluginTimeout),
+];
+
 impl PluginOp {
     /// Convert a string into the corresponding protocol operation and anchor.
     ///
-    /// FIXME find a more idiomatic way
+    /// # Panics
+    ///
+    /// Panics if the name is invalid, see [`PluginOp::try_from_name`] for a fallible version.
     pub fn from_name(name: &str) -> (PluginOp, Anchor) {
+        match PluginOp::try_from_name(name) {
+            Ok(res) => res,
+            Err(e) => panic!("Invalid protocol operation name: {e}"),
+        }
+    }
+
+    /// Convert a string into the corresponding protocol operation and anchor, or return why
+    /// the name cannot be converted.
+    pub fn try_from_name(name: &str) -> Result<(PluginOp, Anchor), PluginOpNameError> {
         let (name, anchor) = if let Some(po_name) = name.strip_prefix("pre_") {
             (po_name, Anchor::Before)
         } else if let Some(po_name) = name.strip_prefix("before_") {
@@ -123,84 +380,33 @@ impl PluginOp {
             (po_name, Anchor::After)
         } else if let Some(po_name) = name.strip_prefix("after_") {
             (po_name, Anchor::After)
+        } else if let Some(po_name) = name.strip_prefix("wrap_") {
+            (po_name, Anchor::Wrap)
         } else {
             (name, Anchor::Define)
         };
 
         if name == "init" {
-            (PluginOp::Init, anchor)
-        } else if name.starts_with("decode_transport_parameter_") {
-            match extract_po_param(name) {
-                Ok(frame_type) => (PluginOp::DecodeTransportParameter(frame_type), anchor),
-                Err(_) => panic!("Invalid protocol operation name"),
-            }
-        } else if name.starts_with("write_transport_parameter_") {
-            match extract_po_param(name) {
-                Ok(frame_type) => (PluginOp::WriteTransportParameter(frame_type), anchor),
-                Err(_) => panic!("Invalid protocol operation name"),
-            }
-        } else if name.starts_with("log_frame_") {
-            match extract_po_param(name) {
-                Ok(frame_type) => (PluginOp::LogFrame(frame_type), anchor),
-                Err(_) => panic!("Invalid protocol operation name"),
-            }
-        } else if name.starts_with("notify_frame_") {
-            match extract_po_param(name) {
-                Ok(frame_type) => (PluginOp::NotifyFrame(frame_type), anchor),
-                Err(_) => panic!("Invalid protocol operation name"),
-            }
-        } else if name.starts_with("on_frame_reserved_") {
-            match extract_po_param(name) {
-                Ok(frame_type) => (PluginOp::OnFrameReserved(frame_type), anchor),
-                Err(_) => panic!("Invalid protocol operation name"),
-            }
-        } else if name.starts_with("parse_frame_") {
-            match extract_po_param(name) {
-                Ok(frame_type) => (PluginOp::ParseFrame(frame_type), anchor),
-                Err(_) => panic!("Invalid protocol operation name"),
-            }
-        } else if name.starts_with("prepare_frame_") {
-            match extract_po_param(name) {
-                Ok(frame_type) => (PluginOp::PrepareFrame(frame_type), anchor),
-                Err(_) => panic!("Invalid protocol operation name"),
-            }
-        } else if name.starts_with("process_frame_") {
-            match extract_po_param(name) {
-                Ok(frame_type) => (PluginOp::ProcessFrame(frame_type), anchor),
-                Err(_) => panic!("Invalid protocol operation name"),
-            }
-        } else if name.starts_with("should_send_frame_") {
-            match extract_po_param(name) {
-                Ok(frame_type) => (PluginOp::ShouldSendFrame(frame_type), anchor),
-                Err(_) => panic!("Invalid protocol operation name"),
-            }
-        } else if name.starts_with("wire_len_") {
-            match extract_po_param(name) {
-                Ok(frame_type) => (PluginOp::WireLen(frame_type), anchor),
-                Err(e) => panic!("Invalid protocol operation name: {e}"),
-            }
-        } else if name.starts_with("write_frame_") {
-            match extract_po_param(name) {
-                Ok(frame_type) => (PluginOp::WriteFrame(frame_type), anchor),
-                Err(e) => panic!("Invalid protocol operation name: {e}"),
-            }
-        } else if name.starts_with("plugin_control_") {
-            match extract_po_param(name) {
-                Ok(val) => (PluginOp::PluginControl(val), anchor),
-                Err(e) => panic!("Invalid protocol operation name: {e}"),
-            }
-        } else if name.starts_with("on_plugin_timeout_") {
-            match extract_po_param(name) {
-                Ok(val) => (PluginOp::OnPluginTimeout(val), anchor),
-                Err(e) => panic!("Invalid protocol operation name: {e}"),
-            }
-        } else if name == "update_rtt" {
-            (PluginOp::UpdateRtt, anchor)
-        } else {
-            let mut name_array = [0; 32];
-            name_array[..name.len()].copy_from_slice(name.as_bytes());
-            (PluginOp::Other(name_array), anchor)
+            return Ok((PluginOp::Init, anchor));
+        }
+        if name == "update_rtt" {
+            return Ok((PluginOp::UpdateRtt, anchor));
+        }
+        if let Some((_, op)) = PARAMETRIZED_OPS
+            .iter()
+            .find(|(prefix, _)| name.starts_with(prefix))
+        {
+            return match extract_po_param(name) {
+                Ok(param) => Ok((op(param), anchor)),
+                Err(_) => Err(PluginOpNameError::InvalidParameter(name.to_string())),
+            };
         }
+        let mut name_array = [0; 32];
+        if name.len() > name_array.len() {
+            return Err(PluginOpNameError::NameTooLong(name.to_string()));
+        }
+        name_array[..name.len()].copy_from_slice(name.as_bytes());
+        Ok((PluginOp::Other(name_array), anchor))
     }
 
     /// Returns whether the plugin operation can be called, even if it is not fully
@@ -230,7 +436,7 @@ pub struct Bytes {
 /// Values used to communicate with underlying plugins, either as inputs or
 /// outputs.
 #[allow(clippy::large_enum_variant)]
-#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, PartialOrd)]
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, PartialOrd)]
 pub enum PluginVal {
     /// A boolean value.
     Bool(bool),
@@ -258,6 +464,14 @@ pub enum PluginVal {
     SocketAddr(SocketAddr),
     /// QUIC specific inputs.
     QUIC(quic::QVal),
+    /// A string.
+    String(String),
+    /// Some raw bytes, carried by value.
+    ByteVec(Vec<u8>),
+    /// A sequence of values.
+    List(Vec<PluginVal>),
+    /// An optional value.
+    Option(Option<Box<PluginVal>>),
 }
 
 macro_rules! impl_from_try_from {
@@ -336,5 +550,80 @@ impl_from_try_from!(
     InvalidSocketAddr
 );
 impl_from_try_from!(PluginVal, QUIC, quic::QVal, ConversionError, InvalidQVal);
+impl_from_try_from!(PluginVal, String, String, ConversionError, InvalidString);
+impl_from_try_from!(PluginVal, ByteVec, Vec<u8>, ConversionError, InvalidByteVec);
+impl_from_try_from!(
+    PluginVal,
+    List,
+    Vec<PluginVal>,
+    ConversionError,
+    InvalidList
+);
+
+impl From<&str> for PluginVal {
+    fn from(value: &str) -> Self {
+        PluginVal::String(value.to_string())
+    }
+}
+
+/// Implements the conversions between `PluginVal` and `Option`s and `Vec`s of `$t`, carried as
+/// `PluginVal::Option` and `PluginVal::List`.
+macro_rules! impl_option_list {
+    ($($t:ty),+) => {
+        $(
+            impl From<Option<$t>> for PluginVal {
+                fn from(v: Option<$t>) -> Self {
+                    PluginVal::Option(v.map(|v| Box::new(v.into())))
+                }
+            }
+
+            impl TryFrom<PluginVal> for Option<$t> {
+                type Error = ConversionError;
+
+                fn try_from(v: PluginVal) -> Result<Self, Self::Error> {
+                    match v {
+                        PluginVal::Option(v) => v.map(|v| (*v).try_into()).transpose(),
+                        _ => Err(ConversionError::InvalidOption),
+                    }
+                }
+            }
+
+            impl From<Vec<$t>> for PluginVal {
+                fn from(v: Vec<$t>) -> Self {
+                    PluginVal::List(v.into_iter().map(|v| v.into()).collect())
+                }
+            }
+
+            impl TryFrom<PluginVal> for Vec<$t> {
+                type Error = ConversionError;
+
+                fn try_from(v: PluginVal) -> Result<Self, Self::Error> {
+                    match v {
+                        PluginVal::List(v) => v.into_iter().map(|v| v.try_into()).collect(),
+                        _ => Err(ConversionError::InvalidList),
+                    }
+                }
+            }
+        )+
+    };
+}
+
+impl_option_list!(
+    bool,
+    i32,
+    i64,
+    u32,
+    u64,
+    f32,
+    f64,
+    usize,
+    Bytes,
+    Duration,
+    UnixInstant,
+    SocketAddr,
+    String,
+    Vec<u8>
+);
 
+pub mod manifest;
 pub mod quic;
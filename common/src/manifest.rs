@@ -0,0 +1,129 @@
+//! The metadata that plugins embed in their bytecode.
+//!
+//! The manifest is stored in the [`MANIFEST_SECTION`] custom section of the WebAssembly module,
+//! as UTF-8 text made of `key=value` lines. Recognized keys are `name`, `version`, `author`,
+//! `api_version`, `priority` and `permissions`, the latter being a comma-separated list of
+//! [`Permission`] names. Unknown keys are ignored. Only `api_version` is mandatory.
+
+use std::fmt;
+
+use crate::Permission;
+
+/// The name of the custom section holding the plugin manifest.
+pub const MANIFEST_SECTION: &str = "pluginop.manifest";
+
+/// The errors that may arise when parsing a manifest.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ManifestError {
+    /// The manifest is not valid UTF-8.
+    InvalidUtf8,
+    /// The line is not a `key=value` pair.
+    InvalidLine(String),
+    /// The `api_version` key is missing.
+    MissingApiVersion,
+    /// The `api_version` value is not a number.
+    InvalidApiVersion(String),
+    /// The `priority` value is not a number.
+    InvalidPriority(String),
+    /// The permission name is unknown.
+    UnknownPermission(String),
+}
+
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ManifestError::InvalidUtf8 => write!(f, "manifest is not valid UTF-8"),
+            ManifestError::InvalidLine(l) => write!(f, "invalid manifest line \"{l}\""),
+            ManifestError::MissingApiVersion => write!(f, "missing api_version in manifest"),
+            ManifestError::InvalidApiVersion(v) => write!(f, "invalid api_version \"{v}\""),
+            ManifestError::InvalidPriority(v) => write!(f, "invalid priority \"{v}\""),
+            ManifestError::UnknownPermission(p) => write!(f, "unknown permission \"{p}\""),
+        }
+    }
+}
+
+/// The metadata of a plugin.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PluginManifest {
+    /// The name of the plugin.
+    pub name: String,
+    /// The version of the plugin.
+    pub version: String,
+    /// The author(s) of the plugin.
+    pub author: String,
+    /// The version of the host API the plugin was built against.
+    pub api_version: u32,
+    /// The priority of the plugin over the other ones defining the same operations, unless the
+    /// host sets one on insertion. `None` if the plugin does not state it.
+    pub priority: Option<i32>,
+    /// The permissions requested by the plugin. `None` if the plugin does not state them.
+    pub permissions: Option<Vec<Permission>>,
+}
+
+impl PluginManifest {
+    /// Parse the content of a manifest section.
+    pub fn parse(bytes: &[u8]) -> Result<Self, ManifestError> {
+        let text = std::str::from_utf8(bytes).map_err(|_| ManifestError::InvalidUtf8)?;
+        let mut manifest = PluginManifest::default();
+        let mut api_version = None;
+        for line in text.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| ManifestError::InvalidLine(line.to_string()))?;
+            let value = value.trim();
+            match key.trim() {
+                "name" => manifest.name = value.to_string(),
+                "version" => manifest.version = value.to_string(),
+                "author" => manifest.author = value.to_string(),
+                "api_version" => {
+                    api_version = Some(
+                        value
+                            .parse()
+                            .map_err(|_| ManifestError::InvalidApiVersion(value.to_string()))?,
+                    )
+                }
+                "priority" => {
+                    manifest.priority = Some(
+                        value
+                            .parse()
+                            .map_err(|_| ManifestError::InvalidPriority(value.to_string()))?,
+                    )
+                }
+                "permissions" => {
+                    manifest.permissions = Some(
+                        value
+                            .split(',')
+                            .map(str::trim)
+                            .filter(|p| !p.is_empty())
+                            .map(|p| {
+                                Permission::from_name(p)
+                                    .ok_or_else(|| ManifestError::UnknownPermission(p.to_string()))
+                            })
+                            .collect::<Result<_, _>>()?,
+                    )
+                }
+                _ => {}
+            }
+        }
+        manifest.api_version = api_version.ok_or(ManifestError::MissingApiVersion)?;
+        Ok(manifest)
+    }
+}
+
+impl fmt::Display for PluginManifest {
+    /// Writes the manifest in the format expected by [`PluginManifest::parse`].
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "name={}", self.name)?;
+        writeln!(f, "version={}", self.version)?;
+        writeln!(f, "author={}", self.author)?;
+        writeln!(f, "api_version={}", self.api_version)?;
+        if let Some(priority) = self.priority {
+            writeln!(f, "priority={priority}")?;
+        }
+        if let Some(permissions) = &self.permissions {
+            let names: Vec<_> = permissions.iter().map(|p| p.name()).collect();
+            writeln!(f, "permissions={}", names.join(","))?;
+        }
+        Ok(())
+    }
+}
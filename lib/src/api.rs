@@ -1,20 +1,24 @@
 //! Definition of the API offered to the plugins.
 
-use std::path::Path;
+use std::{path::Path, time::Duration};
 
+use log::Level;
 use pluginop_common::{
     quic::{ConnectionField, RecoveryField},
-    APIResult, WASMLen,
+    APIResult, ApiErrorCode, LogLevel, LogRecord, WASMLen,
 };
+use serde::Serialize;
 use wasmer::{Exports, Function, FunctionEnv, FunctionEnvMut, Imports, Store, WasmPtr};
 
 use crate::{
     plugin::{Env, TimerEvent},
-    PluginizableConnection,
+    record::NestedCall,
+    Error, Permission, PluginizableConnection,
 };
 
 /// Errors that can occur during the conversion of structures between the host
 /// implementation and the plugins.
+#[derive(Debug)]
 pub enum CTPError {
     /// Type mismatch with what is expected.
     BadType,
@@ -24,6 +28,23 @@ pub enum CTPError {
     BadBytes,
     /// File system error.
     FileError,
+    /// The plugin reached its maximum number of pending timers.
+    TooManyTimers,
+    /// The connection does not provide the requested field.
+    UnsupportedField,
+}
+
+impl From<CTPError> for ApiErrorCode {
+    fn from(e: CTPError) -> Self {
+        match e {
+            CTPError::BadType => ApiErrorCode::InvalidArgument,
+            CTPError::SerializeError => ApiErrorCode::Serialize,
+            CTPError::BadBytes => ApiErrorCode::BadBytes,
+            CTPError::FileError => ApiErrorCode::HostRejected,
+            CTPError::TooManyTimers => ApiErrorCode::TooManyTimers,
+            CTPError::UnsupportedField => ApiErrorCode::NotFound,
+        }
+    }
 }
 
 /// A trait that needs to be implemented by the host implementation to provide
@@ -33,6 +54,10 @@ pub trait ConnectionToPlugin:
 {
     /// Gets the related `ConnectionField` and writes it as a serialized value in `w`.
     /// It is up to the plugin to correctly handle the value and perform the serialization.
+    /// If `w` is too short, this function must fail with `SerializeBufferFull` without any
+    /// side effect, as the host then retries with a larger buffer. If the connection does not
+    /// provide `field`, it should fail with `WontImplement`, that the plugin gets as
+    /// [`ApiErrorCode::NotFound`].
     fn get_connection<'a>(
         &self,
         field: ConnectionField,
@@ -40,10 +65,12 @@ pub trait ConnectionToPlugin:
     ) -> postcard::Result<&'a mut [u8]>;
     /// Sets the related `ConnectionField` to the provided value, that was serialized with content
     /// `value`. It is this function responsibility to correctly convert the
-    /// input to the right type.
+    /// input to the right type. If the connection does not provide `field`, it should fail with
+    /// [`CTPError::UnsupportedField`].
     fn set_connection(&mut self, field: ConnectionField, value: &[u8]) -> Result<(), CTPError>;
     /// Gets the related `RecoveryField` and writes it as a serialized value in `w`. It is up to the
-    /// plugin to correctly handle the value and perform the serialization.
+    /// plugin to correctly handle the value and perform the serialization. Same as
+    /// [`ConnectionToPlugin::get_connection`] if `w` is too short.
     fn get_recovery<'a>(
         &self,
         field: RecoveryField,
@@ -51,7 +78,8 @@ pub trait ConnectionToPlugin:
     ) -> postcard::Result<&'a mut [u8]>;
     /// Sets the related `RecoveryField` to the provided value, that was serialized with content
     /// `value`. It is this function responsibility to correctly convert the
-    /// input to the right type.
+    /// input to the right type. If the connection does not provide `field`, it should fail with
+    /// [`CTPError::UnsupportedField`].
     fn set_recovery(
         &mut self,
         field: RecoveryField,
@@ -70,6 +98,150 @@ pub trait ToPluginizableConnection<CTP: ConnectionToPlugin> {
 
 // -------------------------------- API FUNCTIONS ----------------------------------
 
+/// The error to return when a value cannot be serialized in the plugin memory, or cannot be read
+/// from the connection.
+fn serialize_error(e: postcard::Error) -> ApiErrorCode {
+    match e {
+        postcard::Error::SerializeBufferFull => ApiErrorCode::BufferTooShort,
+        postcard::Error::WontImplement => ApiErrorCode::NotFound,
+        _ => ApiErrorCode::Serialize,
+    }
+}
+
+/// The maximum length of a serialized value that the host returns to a plugin.
+const MAX_VALUE_LEN: usize = 1 << 20;
+
+/// Tells the plugin that its buffer `mem` is too short for a result of `len` bytes, by writing
+/// this length in the first four bytes of `mem`, as a little-endian `u32`. The plugin can then
+/// retry with a large enough buffer. Fails with other errors if the result is too large to be
+/// returned, or if `mem` cannot even hold the length.
+fn buffer_too_short(mem: &mut [u8], len: usize) -> ApiErrorCode {
+    if len > MAX_VALUE_LEN {
+        return ApiErrorCode::ValueTooLarge;
+    }
+    match mem.get_mut(..4) {
+        Some(dst) => {
+            dst.copy_from_slice(&(len as u32).to_le_bytes());
+            ApiErrorCode::BufferTooShort
+        }
+        None => ApiErrorCode::BufferTooShortForLength,
+    }
+}
+
+/// Copies the serialized `value` in the plugin memory `mem`, or reports its length as in
+/// [`buffer_too_short`].
+fn write_result(value: &[u8], mem: &mut [u8]) -> APIResult {
+    match mem.get_mut(..value.len()) {
+        Some(dst) => {
+            dst.copy_from_slice(value);
+            0
+        }
+        None => buffer_too_short(mem, value.len()).into(),
+    }
+}
+
+/// Serializes `value` in the plugin memory `mem`.
+fn write_value<T: Serialize + ?Sized>(value: &T, mem: &mut [u8]) -> APIResult {
+    match postcard::to_slice(value, mem) {
+        Ok(_) => 0,
+        Err(postcard::Error::SerializeBufferFull) => match postcard::to_stdvec(value) {
+            Ok(v) => buffer_too_short(mem, v.len()).into(),
+            Err(e) => serialize_error(e).into(),
+        },
+        Err(e) => serialize_error(e).into(),
+    }
+}
+
+/// Serializes the `outputs` of a nested call in the plugin memory `mem`. If they do not fit, they
+/// are kept in `env`, so that the plugin fetches them with [`get_call_result_from_plugin`]
+/// instead of running the call again.
+fn write_call_result<CTP: ConnectionToPlugin>(
+    env: &mut Env<CTP>,
+    outputs: &[crate::PluginVal],
+    mem: &mut [u8],
+) -> APIResult {
+    let serialized = match postcard::to_stdvec(outputs) {
+        Ok(v) => v,
+        Err(e) => return serialize_error(e).into(),
+    };
+    let res = write_result(&serialized, mem);
+    if res == ApiErrorCode::BufferTooShort.code() {
+        env.set_call_result(Some(serialized));
+    }
+    res
+}
+
+/// A value read for the plugin by [`read_value`].
+enum ReadValue<'a> {
+    /// The value, serialized in the plugin memory.
+    Written(&'a [u8]),
+    /// The value, serialized by the host as the plugin memory was too short, and the error
+    /// reported to the plugin.
+    TooShort(Vec<u8>, ApiErrorCode),
+}
+
+impl ReadValue<'_> {
+    /// The serialized value.
+    fn bytes(&self) -> &[u8] {
+        match self {
+            ReadValue::Written(b) => b,
+            ReadValue::TooShort(b, _) => b,
+        }
+    }
+
+    /// The value to return to the plugin.
+    fn result(&self) -> APIResult {
+        match self {
+            ReadValue::Written(_) => 0,
+            ReadValue::TooShort(_, e) => e.code(),
+        }
+    }
+}
+
+/// Reads a value for the plugin in its memory `mem` with `read`, that serializes the value in
+/// the provided buffer and returns its length. `read` must fail with `SerializeBufferFull` and
+/// no side effect when the buffer is too short. In such case, the value is read in a larger host
+/// buffer, and its length is reported to the plugin as in [`buffer_too_short`].
+fn read_value(
+    mem: &mut [u8],
+    mut read: impl FnMut(&mut [u8]) -> postcard::Result<usize>,
+) -> Result<ReadValue<'_>, ApiErrorCode> {
+    match read(mem) {
+        Ok(len) => return Ok(ReadValue::Written(&mem[..len])),
+        Err(postcard::Error::SerializeBufferFull) => {}
+        Err(e) => return Err(serialize_error(e)),
+    }
+    let mut buf = vec![0; mem.len().max(64) * 2];
+    loop {
+        match read(&mut buf) {
+            Ok(len) => {
+                buf.truncate(len);
+                let e = buffer_too_short(mem, len);
+                return Ok(ReadValue::TooShort(buf, e));
+            }
+            Err(postcard::Error::SerializeBufferFull) if buf.len() <= MAX_VALUE_LEN => {
+                buf.resize(buf.len() * 2, 0);
+            }
+            Err(postcard::Error::SerializeBufferFull) => return Err(ApiErrorCode::ValueTooLarge),
+            Err(e) => return Err(serialize_error(e)),
+        }
+    }
+}
+
+/// Gets the `len` bytes of the plugin `memory` starting at `ptr`, or `None` if they do not all
+/// lie within it. All the accesses to the plugin memory should go through this function or
+/// [`guest_bytes_mut`], so that a wrong pointer never panics the host.
+fn guest_bytes(memory: &[u8], ptr: WasmPtr<u8>, len: WASMLen) -> Option<&[u8]> {
+    let start = ptr.offset() as usize;
+    memory.get(start..start.checked_add(len as usize)?)
+}
+
+/// Mutable version of [`guest_bytes`].
+fn guest_bytes_mut(memory: &mut [u8], ptr: WasmPtr<u8>, len: WASMLen) -> Option<&mut [u8]> {
+    let start = ptr.offset() as usize;
+    memory.get_mut(start..start.checked_add(len as usize)?)
+}
+
 /// Stores a value generated by a running plugin as one of its outputs.
 ///
 /// Function intended to be part of the Plugin API.
@@ -78,31 +250,33 @@ fn save_output_from_plugin<CTP: ConnectionToPlugin>(
     ptr: WasmPtr<u8>,
     len: WASMLen,
 ) -> APIResult {
+    if !env.data().has_permission(Permission::Output) {
+        return ApiErrorCode::PermissionDenied.into();
+    }
     let instance = if let Some(i) = env.data().get_instance() {
         i
     } else {
-        return -1;
+        return ApiErrorCode::NoInstance.into();
     };
     let instance = instance.as_ref();
     let memory = match instance.exports.get_memory("memory") {
         Ok(m) => m,
-        Err(_) => return -2,
+        Err(_) => return ApiErrorCode::NoMemory.into(),
     };
     let view = memory.view(&env);
-    let output_cells = match ptr.slice(&view, len) {
-        Ok(oc) => oc,
-        Err(_) => return -3,
-    };
-    let output_serialized = match output_cells.read_to_vec() {
-        Ok(os) => os,
-        Err(_) => return -4,
+    // SAFETY: Given that plugins are single-threaded per-connection, this does
+    // not introduce any UB.
+    let memory_slice = unsafe { view.data_unchecked() };
+    let output_serialized = match guest_bytes(memory_slice, ptr, len) {
+        Some(m) => m,
+        None => return ApiErrorCode::BadPointer.into(),
     };
-    match postcard::from_bytes(&output_serialized) {
+    match postcard::from_bytes(output_serialized) {
         Ok(pv) => {
             env.data_mut().outputs.push(pv);
             0
         }
-        Err(_) => -5,
+        Err(_) => ApiErrorCode::Serialize.into(),
     }
 }
 
@@ -114,31 +288,33 @@ fn save_outputs_from_plugin<CTP: ConnectionToPlugin>(
     ptr: WasmPtr<u8>,
     len: WASMLen,
 ) -> APIResult {
+    if !env.data().has_permission(Permission::Output) {
+        return ApiErrorCode::PermissionDenied.into();
+    }
     let instance = if let Some(i) = env.data().get_instance() {
         i
     } else {
-        return -1;
+        return ApiErrorCode::NoInstance.into();
     };
     let instance = instance.as_ref();
     let memory = match instance.exports.get_memory("memory") {
         Ok(m) => m,
-        Err(_) => return -2,
+        Err(_) => return ApiErrorCode::NoMemory.into(),
     };
     let view = memory.view(&env);
-    let output_cells = match ptr.slice(&view, len) {
-        Ok(oc) => oc,
-        Err(_) => return -3,
-    };
-    let output_serialized = match output_cells.read_to_vec() {
-        Ok(os) => os,
-        Err(_) => return -4,
+    // SAFETY: Given that plugins are single-threaded per-connection, this does
+    // not introduce any UB.
+    let memory_slice = unsafe { view.data_unchecked() };
+    let output_serialized = match guest_bytes(memory_slice, ptr, len) {
+        Some(m) => m,
+        None => return ApiErrorCode::BadPointer.into(),
     };
-    match postcard::from_bytes(&output_serialized) {
+    match postcard::from_bytes(output_serialized) {
         Ok(pvs) => {
             *env.data_mut().outputs = pvs;
             0
         }
-        Err(_) => -5,
+        Err(_) => ApiErrorCode::Serialize.into(),
     }
 }
 
@@ -153,37 +329,32 @@ fn get_input_from_plugin<CTP: ConnectionToPlugin>(
     mem_ptr: WasmPtr<u8>,
     mem_len: WASMLen,
 ) -> APIResult {
+    if !env.data().has_permission(Permission::Opaque) {
+        return ApiErrorCode::PermissionDenied.into();
+    }
     let instance = if let Some(i) = env.data().get_instance() {
         i
     } else {
-        return -1;
+        return ApiErrorCode::NoInstance.into();
     };
     let instance = instance.as_ref();
     let memory = match instance.exports.get_memory("memory") {
         Ok(m) => m,
-        Err(_) => return -2,
+        Err(_) => return ApiErrorCode::NoMemory.into(),
     };
     let view = memory.view(&env);
     let input = match env.data().inputs.get(index as usize) {
         Some(i) => i,
-        None => return -3,
-    };
-    // Sanity check to avoid memory overwrite.
-    // match bincode::serialized_size(input) {
-    //     Ok(l) if l > mem_len.into() => return -4,
-    //     Err(_) => return -5,
-    //     _ => {}
-    // };
+        None => return ApiErrorCode::NotFound.into(),
+    };
     // SAFETY: Given that plugins are single-threaded per-connection, this does
     // not introduce any UB.
     let memory_slice = unsafe { view.data_unchecked_mut() };
-    match postcard::to_slice(
-        input,
-        &mut memory_slice[mem_ptr.offset() as usize..(mem_ptr.offset() + mem_len) as usize],
-    ) {
-        Ok(_) => 0,
-        Err(_) => -6,
-    }
+    let mem = match guest_bytes_mut(memory_slice, mem_ptr, mem_len) {
+        Some(m) => m,
+        None => return ApiErrorCode::BadPointer.into(),
+    };
+    write_value(input, mem)
 }
 
 /// Gets the serialized inputs.
@@ -196,33 +367,28 @@ fn get_inputs_from_plugin<CTP: ConnectionToPlugin>(
     mem_ptr: WasmPtr<u8>,
     mem_len: WASMLen,
 ) -> APIResult {
+    if !env.data().has_permission(Permission::Opaque) {
+        return ApiErrorCode::PermissionDenied.into();
+    }
     let instance = if let Some(i) = env.data().get_instance() {
         i
     } else {
-        return -1;
+        return ApiErrorCode::NoInstance.into();
     };
     let instance = instance.as_ref();
     let memory = match instance.exports.get_memory("memory") {
         Ok(m) => m,
-        Err(_) => return -2,
+        Err(_) => return ApiErrorCode::NoMemory.into(),
     };
     let view = memory.view(&env);
-    // Sanity check to avoid memory overwrite.
-    // match bincode::serialized_size(&*env.data().inputs) {
-    //     Ok(l) if l > mem_len.into() => return -3,
-    //     Err(_) => return -4,
-    //     _ => {}
-    // };
     // SAFETY: Given that plugins are single-threaded per-connection, this does
     // not introduce any UB.
     let memory_slice = unsafe { view.data_unchecked_mut() };
-    match postcard::to_slice(
-        &*env.data().inputs,
-        &mut memory_slice[mem_ptr.offset() as usize..(mem_ptr.offset() + mem_len) as usize],
-    ) {
-        Ok(_) => 0,
-        Err(_) => -5,
-    }
+    let mem = match guest_bytes_mut(memory_slice, mem_ptr, mem_len) {
+        Some(m) => m,
+        None => return ApiErrorCode::BadPointer.into(),
+    };
+    write_value(&*env.data().inputs, mem)
 }
 
 /// Prints the content of the plugin memory located at the address `ptr` as a `str` having a length
@@ -236,6 +402,9 @@ fn print_from_plugin<CTP: ConnectionToPlugin>(
     ptr: WasmPtr<u8>,
     len: WASMLen,
 ) {
+    if !env.data().has_permission(Permission::Output) {
+        return;
+    }
     let instance = if let Some(i) = env.data().get_instance() {
         i
     } else {
@@ -254,6 +423,107 @@ fn print_from_plugin<CTP: ConnectionToPlugin>(
     }
 }
 
+/// Logs the [`LogRecord`] serialized in the plugin memory at the address `ptr` through the `log`
+/// facade of the host, with the [`LogLevel`] whose value is `level`. The message is tagged with
+/// the name of the plugin and the operation it is running, and dropped if it is more verbose
+/// than the log level of the plugin. Its fields are passed as key-values, and also as a
+/// `tracing` event when the `tracing` feature is enabled.
+///
+/// Function intended to be part of the Plugin API.
+fn log_from_plugin<CTP: ConnectionToPlugin>(
+    env: FunctionEnvMut<Env<CTP>>,
+    level: u32,
+    ptr: WasmPtr<u8>,
+    len: WASMLen,
+) -> i64 {
+    if !env.data().has_permission(Permission::Output) {
+        return ApiErrorCode::PermissionDenied.into();
+    }
+    let level = match LogLevel::from_u32(level) {
+        Some(LogLevel::Error) => Level::Error,
+        Some(LogLevel::Warn) => Level::Warn,
+        Some(LogLevel::Info) => Level::Info,
+        Some(LogLevel::Debug) => Level::Debug,
+        Some(LogLevel::Trace) => Level::Trace,
+        None => return ApiErrorCode::InvalidArgument.into(),
+    };
+    if level > env.data().log_level() || level > log::max_level() {
+        return 0;
+    }
+    let instance = if let Some(i) = env.data().get_instance() {
+        i
+    } else {
+        return ApiErrorCode::NoInstance.into();
+    };
+    let instance = instance.as_ref();
+    let memory = match instance.exports.get_memory("memory") {
+        Ok(m) => m,
+        Err(_) => return ApiErrorCode::NoMemory.into(),
+    };
+    let view = memory.view(&env);
+    // SAFETY: Given that plugins are single-threaded per-connection, this does
+    // not introduce any UB.
+    let memory_slice = unsafe { view.data_unchecked() };
+    let mem = match guest_bytes(memory_slice, ptr, len) {
+        Some(m) => m,
+        None => return ApiErrorCode::BadPointer.into(),
+    };
+    let record: LogRecord = match postcard::from_bytes(mem) {
+        Ok(r) => r,
+        Err(_) => return ApiErrorCode::Serialize.into(),
+    };
+    let data = env.data();
+    let target = if record.target.is_empty() {
+        format!("plugin::{}", data.name())
+    } else {
+        format!("plugin::{}::{}", data.name(), record.target)
+    };
+    let op = match data.current_op() {
+        Some(po) => format!("{po:?}"),
+        None => "-".to_string(),
+    };
+    let kvs: Vec<(&str, &str)> = record
+        .fields
+        .iter()
+        .map(|(k, v)| (k.as_str(), v.as_str()))
+        .collect();
+    let kvs = kvs.as_slice();
+    log::logger().log(
+        &log::Record::builder()
+            .args(format_args!("[{}] {}", op, record.message))
+            .level(level)
+            .target(&target)
+            .key_values(&kvs)
+            .build(),
+    );
+    #[cfg(feature = "tracing")]
+    {
+        // The fields of an event are known at compile time, so the ones of the plugin are
+        // grouped into a single one.
+        macro_rules! plugin_event {
+            ($level:expr) => {
+                tracing::event!(
+                    $level,
+                    plugin = data.plugin_id(),
+                    target = %target,
+                    op = %op,
+                    fields = ?kvs,
+                    "{}",
+                    record.message
+                )
+            };
+        }
+        match level {
+            Level::Error => plugin_event!(tracing::Level::ERROR),
+            Level::Warn => plugin_event!(tracing::Level::WARN),
+            Level::Info => plugin_event!(tracing::Level::INFO),
+            Level::Debug => plugin_event!(tracing::Level::DEBUG),
+            Level::Trace => plugin_event!(tracing::Level::TRACE),
+        }
+    }
+    0
+}
+
 /// Gets a specific connection field.
 ///
 /// Function intended to be part of the Plugin API.
@@ -264,15 +534,18 @@ fn get_connection_from_plugin<CTP: ConnectionToPlugin>(
     res_ptr: WasmPtr<u8>,
     res_len: WASMLen,
 ) -> i64 {
+    if !env.data().has_permission(Permission::ConnectionAccess) {
+        return ApiErrorCode::PermissionDenied.into();
+    }
     let instance = if let Some(i) = env.data().get_instance() {
         i
     } else {
-        return -1;
+        return ApiErrorCode::NoInstance.into();
     };
     let instance = instance.as_ref();
     let memory = match instance.exports.get_memory("memory") {
         Ok(m) => m,
-        Err(_) => return -2,
+        Err(_) => return ApiErrorCode::NoMemory.into(),
     };
     let view = memory.view(&env);
     // SAFETY: Given that plugins are single-threaded per-connection, this does
@@ -282,28 +555,37 @@ fn get_connection_from_plugin<CTP: ConnectionToPlugin>(
     // as the guest will preallocate the memory.
     let memory_slice =
         unsafe { std::slice::from_raw_parts_mut(memory_slice.as_mut_ptr(), memory_slice.len()) };
-    let field = match postcard::from_bytes(
-        &memory_slice[field_ptr.offset() as usize..(field_ptr.offset() + field_len) as usize],
-    ) {
+    let field_mem = match guest_bytes(memory_slice, field_ptr, field_len) {
+        Some(m) => m,
+        None => return ApiErrorCode::BadPointer.into(),
+    };
+    let field = match postcard::from_bytes(field_mem) {
         Ok(f) => f,
-        Err(_) => return -3,
+        Err(_) => return ApiErrorCode::Serialize.into(),
     };
     let ph = if let Some(ph) = env.data_mut().get_ph() {
         ph
     } else {
-        return -4;
+        return ApiErrorCode::NoHandler.into();
     };
     let conn = match ph.get_conn() {
         Some(c) => c,
-        None => return -5,
+        None => return ApiErrorCode::NoConnection.into(),
     };
-    match conn.get_conn().get_connection(
-        field,
-        &mut memory_slice[res_ptr.offset() as usize..(res_ptr.offset() + res_len) as usize],
-    ) {
-        Ok(_) => 0,
-        Err(_) => -6,
+    let res_mem = match guest_bytes_mut(memory_slice, res_ptr, res_len) {
+        Some(m) => m,
+        None => return ApiErrorCode::BadPointer.into(),
+    };
+    let value = match read_value(res_mem, |w| {
+        conn.get_conn().get_connection(field, w).map(|w| w.len())
+    }) {
+        Ok(v) => v,
+        Err(e) => return e.into(),
+    };
+    if let Some(r) = ph.recorder_mut() {
+        r.connection_read(field, value.bytes());
     }
+    value.result()
 }
 
 /// Sets a specific connection field.
@@ -316,15 +598,18 @@ fn set_connection_from_plugin<CTP: ConnectionToPlugin>(
     val_ptr: WasmPtr<u8>,
     val_len: WASMLen,
 ) -> i64 {
+    if !env.data().has_permission(Permission::ConnectionAccess) {
+        return ApiErrorCode::PermissionDenied.into();
+    }
     let instance = if let Some(i) = env.data().get_instance() {
         i
     } else {
-        return -1;
+        return ApiErrorCode::NoInstance.into();
     };
     let instance = instance.as_ref();
     let memory = match instance.exports.get_memory("memory") {
         Ok(m) => m,
-        Err(_) => return -2,
+        Err(_) => return ApiErrorCode::NoMemory.into(),
     };
     let view = memory.view(&env);
     // SAFETY: Given that plugins are single-threaded per-connection, this does
@@ -334,27 +619,30 @@ fn set_connection_from_plugin<CTP: ConnectionToPlugin>(
     // as the guest will preallocate the memory.
     let memory_slice =
         unsafe { std::slice::from_raw_parts(memory_slice.as_ptr(), memory_slice.len()) };
-    let field = match postcard::from_bytes(
-        &memory_slice[field_ptr.offset() as usize..(field_ptr.offset() + field_len) as usize],
-    ) {
+    let field_mem = match guest_bytes(memory_slice, field_ptr, field_len) {
+        Some(m) => m,
+        None => return ApiErrorCode::BadPointer.into(),
+    };
+    let field = match postcard::from_bytes(field_mem) {
         Ok(f) => f,
-        Err(_) => return -3,
+        Err(_) => return ApiErrorCode::Serialize.into(),
     };
     let ph = if let Some(ph) = env.data_mut().get_ph() {
         ph
     } else {
-        return -4;
+        return ApiErrorCode::NoHandler.into();
     };
     let conn = match ph.get_conn_mut() {
         Some(c) => c,
-        None => return -5,
+        None => return ApiErrorCode::NoConnection.into(),
+    };
+    let val_mem = match guest_bytes(memory_slice, val_ptr, val_len) {
+        Some(m) => m,
+        None => return ApiErrorCode::BadPointer.into(),
     };
-    match conn.get_conn_mut().set_connection(
-        field,
-        &memory_slice[val_ptr.offset() as usize..(val_ptr.offset() + val_len) as usize],
-    ) {
+    match conn.get_conn_mut().set_connection(field, val_mem) {
         Ok(()) => 0,
-        Err(_) => -6,
+        Err(e) => ApiErrorCode::from(e).into(),
     }
 }
 
@@ -365,15 +653,18 @@ fn get_bytes_from_plugin<CTP: ConnectionToPlugin>(
     res_ptr: WasmPtr<u8>,
     res_len: WASMLen,
 ) -> i64 {
+    if !env.data().has_permission(Permission::ReadBuffer) {
+        return ApiErrorCode::PermissionDenied.into();
+    }
     let instance = if let Some(i) = env.data().get_instance() {
         i
     } else {
-        return -1;
+        return ApiErrorCode::NoInstance.into();
     };
     let instance = instance.as_ref();
     let memory = match instance.exports.get_memory("memory") {
         Ok(m) => m,
-        Err(_) => return -2,
+        Err(_) => return ApiErrorCode::NoMemory.into(),
     };
     let view = memory.view(&env);
     // SAFETY: Given that plugins are single-threaded per-connection, this does
@@ -383,10 +674,22 @@ fn get_bytes_from_plugin<CTP: ConnectionToPlugin>(
     // as the guest will preallocate the memory.
     let memory_slice =
         unsafe { std::slice::from_raw_parts_mut(memory_slice.as_mut_ptr(), memory_slice.len()) };
-    let mem = &mut memory_slice[res_ptr.offset() as usize..(res_ptr.offset() + res_len) as usize];
-    match env.data_mut().get_bytes(tag as usize, len as usize, mem) {
+    let mem = match guest_bytes_mut(memory_slice, res_ptr, res_len) {
+        Some(m) => m,
+        None => return ApiErrorCode::BadPointer.into(),
+    };
+    let res = env.data_mut().get_bytes(tag as usize, len as usize, mem);
+    #[cfg(feature = "tracing")]
+    tracing::trace!(
+        plugin = env.data().plugin_id(),
+        tag,
+        len,
+        result = ?res,
+        "plugin read bytes"
+    );
+    match res {
         Ok(w) => w as i64,
-        Err(_) => -3,
+        Err(e) => ApiErrorCode::from(e).into(),
     }
 }
 
@@ -396,15 +699,18 @@ fn put_bytes_from_plugin<CTP: ConnectionToPlugin>(
     ptr: WasmPtr<u8>,
     len: WASMLen,
 ) -> i64 {
+    if !env.data().has_permission(Permission::WriteBuffer) {
+        return ApiErrorCode::PermissionDenied.into();
+    }
     let instance = if let Some(i) = env.data().get_instance() {
         i
     } else {
-        return -1;
+        return ApiErrorCode::NoInstance.into();
     };
     let instance = instance.as_ref();
     let memory = match instance.exports.get_memory("memory") {
         Ok(m) => m,
-        Err(_) => return -2,
+        Err(_) => return ApiErrorCode::NoMemory.into(),
     };
     let view = memory.view(&env);
     // SAFETY: Given that plugins are single-threaded per-connection, this does
@@ -414,10 +720,22 @@ fn put_bytes_from_plugin<CTP: ConnectionToPlugin>(
     // as the guest will preallocate the memory.
     let memory_slice =
         unsafe { std::slice::from_raw_parts(memory_slice.as_ptr(), memory_slice.len()) };
-    let mem = &memory_slice[ptr.offset() as usize..(ptr.offset() + len) as usize];
-    match env.data_mut().put_bytes(tag as usize, mem) {
+    let mem = match guest_bytes(memory_slice, ptr, len) {
+        Some(m) => m,
+        None => return ApiErrorCode::BadPointer.into(),
+    };
+    let res = env.data_mut().put_bytes(tag as usize, mem);
+    #[cfg(feature = "tracing")]
+    tracing::trace!(
+        plugin = env.data().plugin_id(),
+        tag,
+        len,
+        result = ?res,
+        "plugin wrote bytes"
+    );
+    match res {
         Ok(w) => w as i64,
-        Err(_) => -3,
+        Err(e) => ApiErrorCode::from(e).into(),
     }
 }
 
@@ -426,32 +744,38 @@ fn register_from_plugin<CTP: ConnectionToPlugin>(
     ptr: WasmPtr<u8>,
     len: WASMLen,
 ) -> i64 {
+    if !env.data().has_permission(Permission::ConnectionAccess) {
+        return ApiErrorCode::PermissionDenied.into();
+    }
     let instance = if let Some(i) = env.data().get_instance() {
         i
     } else {
-        return -1;
+        return ApiErrorCode::NoInstance.into();
     };
     let instance = instance.as_ref();
     let memory = match instance.exports.get_memory("memory") {
         Ok(m) => m,
-        Err(_) => return -2,
+        Err(_) => return ApiErrorCode::NoMemory.into(),
     };
     let view = memory.view(&env);
     // SAFETY: Given that plugins are single-threaded per-connection, this does
     // not introduce any UB.
     let memory_slice = unsafe { view.data_unchecked() };
-    let r = match postcard::from_bytes(
-        &memory_slice[ptr.offset() as usize..(ptr.offset() + len) as usize],
-    ) {
+    let mem = match guest_bytes(memory_slice, ptr, len) {
+        Some(m) => m,
+        None => return ApiErrorCode::BadPointer.into(),
+    };
+    let r = match postcard::from_bytes(mem) {
         Ok(f) => f,
-        Err(_) => return -3,
+        Err(_) => return ApiErrorCode::Serialize.into(),
     };
+    let plugin_id = env.data().plugin_id();
     let ph = if let Some(ph) = env.data_mut().get_ph() {
         ph
     } else {
-        return -4;
+        return ApiErrorCode::NoHandler.into();
     };
-    ph.add_registration(r);
+    ph.add_plugin_registration(plugin_id, r);
     0
 }
 
@@ -462,82 +786,121 @@ fn set_timer_from_plugin<CTP: ConnectionToPlugin>(
     id: u64,
     timer_id: u64,
 ) -> i64 {
+    if !env.data().has_permission(Permission::Timers) {
+        return ApiErrorCode::PermissionDenied.into();
+    }
     let instance = if let Some(i) = env.data().get_instance() {
         i
     } else {
-        return -1;
+        return ApiErrorCode::NoInstance.into();
     };
     let instance = instance.as_ref();
     let memory = match instance.exports.get_memory("memory") {
         Ok(m) => m,
-        Err(_) => return -2,
+        Err(_) => return ApiErrorCode::NoMemory.into(),
     };
     let view = memory.view(&env);
     // SAFETY: Given that plugins are single-threaded per-connection, this does
     // not introduce any UB.
     let memory_slice = unsafe { view.data_unchecked() };
-    let unix_instant = match postcard::from_bytes(
-        &memory_slice[ts_ptr.offset() as usize..(ts_ptr.offset() + ts_len) as usize],
-    ) {
+    let ts_mem = match guest_bytes(memory_slice, ts_ptr, ts_len) {
+        Some(m) => m,
+        None => return ApiErrorCode::BadPointer.into(),
+    };
+    let unix_instant = match postcard::from_bytes(ts_mem) {
         Ok(i) => i,
-        Err(_) => return -3,
+        Err(_) => return ApiErrorCode::Serialize.into(),
     };
     let ph = if let Some(ph) = env.data_mut().get_ph() {
         ph
     } else {
-        return -4;
+        return ApiErrorCode::NoHandler.into();
     };
     let instant = ph.get_instant_from_unix_instant(unix_instant);
-    env.data_mut()
-        .insert_timer_event(TimerEvent::new(instant, id, timer_id));
-    0
+    match env
+        .data_mut()
+        .insert_timer_event(TimerEvent::new(instant, id, timer_id))
+    {
+        Ok(()) => 0,
+        Err(e) => ApiErrorCode::from(e).into(),
+    }
+}
+
+fn set_relative_timer_from_plugin<CTP: ConnectionToPlugin>(
+    mut env: FunctionEnvMut<Env<CTP>>,
+    delay_ns: u64,
+    period_ns: u64,
+    id: u64,
+    timer_id: u64,
+) -> APIResult {
+    if !env.data().has_permission(Permission::Timers) {
+        return ApiErrorCode::PermissionDenied.into();
+    }
+    let now = match env.data_mut().get_ph() {
+        Some(ph) => ph.now(),
+        None => return ApiErrorCode::NoHandler.into(),
+    };
+    let at = match now.checked_add(Duration::from_nanos(delay_ns)) {
+        Some(at) => at,
+        None => return ApiErrorCode::InvalidArgument.into(),
+    };
+    // A period of 0 requests a timer firing once.
+    let te = match period_ns {
+        0 => TimerEvent::new(at, id, timer_id),
+        p => TimerEvent::periodic(at, Duration::from_nanos(p), id, timer_id),
+    };
+    match env.data_mut().insert_timer_event(te) {
+        Ok(()) => 0,
+        Err(e) => ApiErrorCode::from(e).into(),
+    }
 }
 
 fn cancel_timer_from_plugin<CTP: ConnectionToPlugin>(
     mut env: FunctionEnvMut<Env<CTP>>,
     id: u64,
 ) -> i64 {
+    if !env.data().has_permission(Permission::Timers) {
+        return ApiErrorCode::PermissionDenied.into();
+    }
     // Just returns whether some event was actually removed.
     if env.data_mut().cancel_timer_event(id).is_some() {
         0
     } else {
-        -1
+        ApiErrorCode::NotFound.into()
     }
 }
 
 fn get_unix_instant_from_plugin<CTP: ConnectionToPlugin>(
-    env: FunctionEnvMut<Env<CTP>>,
+    mut env: FunctionEnvMut<Env<CTP>>,
     res_ptr: WasmPtr<u8>,
     res_len: WASMLen,
 ) -> APIResult {
+    let now = match env.data_mut().get_ph() {
+        Some(ph) => match ph.plugin_unix_now() {
+            Some(now) => now,
+            None => return ApiErrorCode::NotFound.into(),
+        },
+        None => return ApiErrorCode::NoHandler.into(),
+    };
     let instance = if let Some(i) = env.data().get_instance() {
         i
     } else {
-        return -1;
+        return ApiErrorCode::NoInstance.into();
     };
     let instance = instance.as_ref();
     let memory = match instance.exports.get_memory("memory") {
         Ok(m) => m,
-        Err(_) => return -2,
+        Err(_) => return ApiErrorCode::NoMemory.into(),
     };
     let view = memory.view(&env);
-    let now = unix_time::Instant::now();
-    // Sanity check to avoid memory overwrite.
-    // match bincode::serialized_size(&now) {
-    //     Ok(l) if l > res_len.into() => return -4,
-    //     Err(_) => return -5,
-    //     _ => {}
-    // };
     // SAFETY: Given that plugins are single-threaded per-connection, this does
     // not introduce any UB.
     let memory_slice = unsafe { view.data_unchecked_mut() };
-    match postcard::to_slice(
-        &now,
-        &mut memory_slice[res_ptr.offset() as usize..(res_ptr.offset() + res_len) as usize],
-    ) {
-        Ok(_) => 0,
-        Err(_) => -6,
-    }
+    let res_mem = match guest_bytes_mut(memory_slice, res_ptr, res_len) {
+        Some(m) => m,
+        None => return ApiErrorCode::BadPointer.into(),
+    };
+    write_value(&now, res_mem)
 }
 
 fn create_file_from_plugin<CTP: ConnectionToPlugin>(
@@ -545,30 +908,35 @@ fn create_file_from_plugin<CTP: ConnectionToPlugin>(
     path_ptr: WasmPtr<u8>,
     path_len: WASMLen,
 ) -> APIResult {
+    if !env.data().has_permission(Permission::FileSystem) {
+        return ApiErrorCode::PermissionDenied.into();
+    }
     let instance = if let Some(i) = env.data().get_instance() {
         i
     } else {
-        return -1;
+        return ApiErrorCode::NoInstance.into();
     };
     let instance = instance.as_ref();
     let memory = match instance.exports.get_memory("memory") {
         Ok(m) => m,
-        Err(_) => return -2,
+        Err(_) => return ApiErrorCode::NoMemory.into(),
     };
     let view = memory.view(&env);
     // SAFETY: Given that plugins are single-threaded per-connection, this does
     // not introduce any UB.
     let memory_slice = unsafe { view.data_unchecked() };
-    let path: String = match std::str::from_utf8(
-        &memory_slice[path_ptr.offset() as usize..(path_ptr.offset() + path_len) as usize],
-    ) {
+    let path_mem = match guest_bytes(memory_slice, path_ptr, path_len) {
+        Some(m) => m,
+        None => return ApiErrorCode::BadPointer.into(),
+    };
+    let path: String = match std::str::from_utf8(path_mem) {
         Ok(p) => p.to_string(),
-        Err(_) => return -3,
+        Err(_) => return ApiErrorCode::InvalidArgument.into(),
     };
     println!("Path is {}", path);
     env.data_mut()
         .create_file_with_path(Path::new(&path))
-        .unwrap_or(-4)
+        .unwrap_or_else(|e| ApiErrorCode::from(e).into())
 }
 
 fn write_file_from_plugin<CTP: ConnectionToPlugin>(
@@ -577,26 +945,30 @@ fn write_file_from_plugin<CTP: ConnectionToPlugin>(
     ptr: WasmPtr<u8>,
     ptr_len: WASMLen,
 ) -> i64 {
+    if !env.data().has_permission(Permission::FileSystem) {
+        return ApiErrorCode::PermissionDenied.into();
+    }
     let instance = if let Some(i) = env.data().get_instance() {
         i
     } else {
-        return -1;
+        return ApiErrorCode::NoInstance.into();
     };
     let instance = instance.as_ref();
     let memory = match instance.exports.get_memory("memory") {
         Ok(m) => m,
-        Err(_) => return -2,
+        Err(_) => return ApiErrorCode::NoMemory.into(),
     };
     let view = memory.view(&env);
     // SAFETY: Given that plugins are single-threaded per-connection, this does
     // not introduce any UB.
     let memory_slice = unsafe { view.data_unchecked() };
-    match env.data().write_to_file(
-        fd,
-        &memory_slice[ptr.offset() as usize..(ptr.offset() + ptr_len) as usize],
-    ) {
+    let mem = match guest_bytes(memory_slice, ptr, ptr_len) {
+        Some(m) => m,
+        None => return ApiErrorCode::BadPointer.into(),
+    };
+    match env.data().write_to_file(fd, mem) {
         Ok(w) => w as i64,
-        Err(_) => -3,
+        Err(e) => ApiErrorCode::from(e).into(),
     }
 }
 
@@ -614,15 +986,18 @@ fn get_recovery_from_plugin<CTP: ConnectionToPlugin>(
     res_ptr: WasmPtr<u8>,
     res_len: WASMLen,
 ) -> i64 {
+    if !env.data().has_permission(Permission::ConnectionAccess) {
+        return ApiErrorCode::PermissionDenied.into();
+    }
     let instance = if let Some(i) = env.data().get_instance() {
         i
     } else {
-        return -1;
+        return ApiErrorCode::NoInstance.into();
     };
     let instance = instance.as_ref();
     let memory = match instance.exports.get_memory("memory") {
         Ok(m) => m,
-        Err(_) => return -2,
+        Err(_) => return ApiErrorCode::NoMemory.into(),
     };
     let view = memory.view(&env);
     // SAFETY: Given that plugins are single-threaded per-connection, this does
@@ -632,28 +1007,37 @@ fn get_recovery_from_plugin<CTP: ConnectionToPlugin>(
     // as the guest will preallocate the memory.
     let memory_slice =
         unsafe { std::slice::from_raw_parts_mut(memory_slice.as_mut_ptr(), memory_slice.len()) };
-    let field = match postcard::from_bytes(
-        &memory_slice[field_ptr.offset() as usize..(field_ptr.offset() + field_len) as usize],
-    ) {
+    let field_mem = match guest_bytes(memory_slice, field_ptr, field_len) {
+        Some(m) => m,
+        None => return ApiErrorCode::BadPointer.into(),
+    };
+    let field = match postcard::from_bytes(field_mem) {
         Ok(f) => f,
-        Err(_) => return -3,
+        Err(_) => return ApiErrorCode::Serialize.into(),
     };
     let ph = if let Some(ph) = env.data_mut().get_ph() {
         ph
     } else {
-        return -4;
+        return ApiErrorCode::NoHandler.into();
     };
     let conn = match ph.get_conn() {
         Some(c) => c,
-        None => return -5,
+        None => return ApiErrorCode::NoConnection.into(),
     };
-    match conn.get_conn().get_recovery(
-        field,
-        &mut memory_slice[res_ptr.offset() as usize..(res_ptr.offset() + res_len) as usize],
-    ) {
-        Ok(_) => 0,
-        Err(_) => -6,
+    let res_mem = match guest_bytes_mut(memory_slice, res_ptr, res_len) {
+        Some(m) => m,
+        None => return ApiErrorCode::BadPointer.into(),
+    };
+    let value = match read_value(res_mem, |w| {
+        conn.get_conn().get_recovery(field, w).map(|w| w.len())
+    }) {
+        Ok(v) => v,
+        Err(e) => return e.into(),
+    };
+    if let Some(r) = ph.recorder_mut() {
+        r.recovery_read(field, value.bytes());
     }
+    value.result()
 }
 
 /// Sets a specific connection field.
@@ -666,15 +1050,18 @@ fn set_recovery_from_plugin<CTP: ConnectionToPlugin>(
     val_ptr: WasmPtr<u8>,
     val_len: WASMLen,
 ) -> i64 {
+    if !env.data().has_permission(Permission::ConnectionAccess) {
+        return ApiErrorCode::PermissionDenied.into();
+    }
     let instance = if let Some(i) = env.data().get_instance() {
         i
     } else {
-        return -1;
+        return ApiErrorCode::NoInstance.into();
     };
     let instance = instance.as_ref();
     let memory = match instance.exports.get_memory("memory") {
         Ok(m) => m,
-        Err(_) => return -2,
+        Err(_) => return ApiErrorCode::NoMemory.into(),
     };
     let view = memory.view(&env);
     // SAFETY: Given that plugins are single-threaded per-connection, this does
@@ -684,27 +1071,30 @@ fn set_recovery_from_plugin<CTP: ConnectionToPlugin>(
     // as the guest will preallocate the memory.
     let memory_slice =
         unsafe { std::slice::from_raw_parts(memory_slice.as_ptr(), memory_slice.len()) };
-    let field = match postcard::from_bytes(
-        &memory_slice[field_ptr.offset() as usize..(field_ptr.offset() + field_len) as usize],
-    ) {
+    let field_mem = match guest_bytes(memory_slice, field_ptr, field_len) {
+        Some(m) => m,
+        None => return ApiErrorCode::BadPointer.into(),
+    };
+    let field = match postcard::from_bytes(field_mem) {
         Ok(f) => f,
-        Err(_) => return -3,
+        Err(_) => return ApiErrorCode::Serialize.into(),
     };
     let ph = if let Some(ph) = env.data_mut().get_ph() {
         ph
     } else {
-        return -4;
+        return ApiErrorCode::NoHandler.into();
     };
     let conn = match ph.get_conn_mut() {
         Some(c) => c,
-        None => return -5,
+        None => return ApiErrorCode::NoConnection.into(),
+    };
+    let val_mem = match guest_bytes(memory_slice, val_ptr, val_len) {
+        Some(m) => m,
+        None => return ApiErrorCode::BadPointer.into(),
     };
-    match conn.get_conn_mut().set_recovery(
-        field,
-        &memory_slice[val_ptr.offset() as usize..(val_ptr.offset() + val_len) as usize],
-    ) {
+    match conn.get_conn_mut().set_recovery(field, val_mem) {
         Ok(()) => 0,
-        Err(_) => -6,
+        Err(e) => ApiErrorCode::from(e).into(),
     }
 }
 
@@ -719,15 +1109,18 @@ fn poctl_from_plugin<CTP: ConnectionToPlugin>(
     res_ptr: WasmPtr<u8>,
     res_len: WASMLen,
 ) -> i64 {
+    if !env.data().has_permission(Permission::NestedCalls) {
+        return ApiErrorCode::PermissionDenied.into();
+    }
     let instance = if let Some(i) = env.data().get_instance() {
         i
     } else {
-        return -1;
+        return ApiErrorCode::NoInstance.into();
     };
     let instance = instance.as_ref();
     let memory = match instance.exports.get_memory("memory") {
         Ok(m) => m,
-        Err(_) => return -2,
+        Err(_) => return ApiErrorCode::NoMemory.into(),
     };
     let view = memory.view(&env);
     // SAFETY: Given that plugins are single-threaded per-connection, this does
@@ -737,28 +1130,138 @@ fn poctl_from_plugin<CTP: ConnectionToPlugin>(
     // as the guest will preallocate the memory.
     let memory_slice =
         unsafe { std::slice::from_raw_parts_mut(memory_slice.as_mut_ptr(), memory_slice.len()) };
-    let inputs: Vec<crate::PluginVal> = match postcard::from_bytes(
-        &memory_slice[inputs_ptr.offset() as usize..(inputs_ptr.offset() + inputs_len) as usize],
-    ) {
+    let inputs_mem = match guest_bytes(memory_slice, inputs_ptr, inputs_len) {
+        Some(m) => m,
+        None => return ApiErrorCode::BadPointer.into(),
+    };
+    let inputs: Vec<crate::PluginVal> = match postcard::from_bytes(inputs_mem) {
         Ok(i) => i,
-        Err(_) => return -3,
+        Err(_) => return ApiErrorCode::Serialize.into(),
+    };
+    let res_mem = match guest_bytes_mut(memory_slice, res_ptr, res_len) {
+        Some(m) => m,
+        None => return ApiErrorCode::BadPointer.into(),
     };
     let ph = if let Some(ph) = env.data_mut().get_ph() {
         ph
     } else {
-        return -4;
+        return ApiErrorCode::NoHandler.into();
     };
-    let outputs = match ph.poctl(id, &inputs) {
+    let res = ph.nested_call(NestedCall::Poctl(id), |ph| {
+        ph.poctl(id, &inputs)
+            .map_err(|_| ApiErrorCode::HostRejected)
+    });
+    let outputs = match res {
         Ok(pvs) => pvs,
-        Err(_) => return -5,
+        Err(e) => return e.into(),
     };
-    match postcard::to_slice(
-        &outputs,
-        &mut memory_slice[res_ptr.offset() as usize..(res_ptr.offset() + res_len) as usize],
-    ) {
-        Ok(_) => 0,
-        Err(_) => -6,
+    write_call_result(env.data_mut(), &outputs, res_mem)
+}
+
+/// Calls the host implementation of the plugin operation that the plugin is defining.
+///
+/// Function intended to be part of the Plugin API.
+fn call_default_from_plugin<CTP: ConnectionToPlugin>(
+    mut env: FunctionEnvMut<Env<CTP>>,
+    inputs_ptr: WasmPtr<u8>,
+    inputs_len: WASMLen,
+    res_ptr: WasmPtr<u8>,
+    res_len: WASMLen,
+) -> i64 {
+    if !env.data().has_permission(Permission::NestedCalls) {
+        return ApiErrorCode::PermissionDenied.into();
+    }
+    let instance = if let Some(i) = env.data().get_instance() {
+        i
+    } else {
+        return ApiErrorCode::NoInstance.into();
+    };
+    let instance = instance.as_ref();
+    let memory = match instance.exports.get_memory("memory") {
+        Ok(m) => m,
+        Err(_) => return ApiErrorCode::NoMemory.into(),
+    };
+    let view = memory.view(&env);
+    // SAFETY: Given that plugins are single-threaded per-connection, this does
+    // not introduce any UB.
+    let memory_slice = unsafe { view.data_unchecked_mut() };
+    // SAFETY:  Also, this won't increase the memory of the plugin,
+    // as the guest will preallocate the memory.
+    let memory_slice =
+        unsafe { std::slice::from_raw_parts_mut(memory_slice.as_mut_ptr(), memory_slice.len()) };
+    let inputs_mem = match guest_bytes(memory_slice, inputs_ptr, inputs_len) {
+        Some(m) => m,
+        None => return ApiErrorCode::BadPointer.into(),
+    };
+    let inputs: Vec<crate::PluginVal> = match postcard::from_bytes(inputs_mem) {
+        Ok(i) => i,
+        Err(_) => return ApiErrorCode::Serialize.into(),
+    };
+    let res_mem = match guest_bytes_mut(memory_slice, res_ptr, res_len) {
+        Some(m) => m,
+        None => return ApiErrorCode::BadPointer.into(),
+    };
+    let ph = if let Some(ph) = env.data_mut().get_ph() {
+        ph
+    } else {
+        return ApiErrorCode::NoHandler.into();
+    };
+    let res = ph.nested_call(NestedCall::Default, |ph| {
+        ph.call_default(&inputs).map_err(|e| match e {
+            Error::NoDefaultImplementation => ApiErrorCode::NotFound,
+            Error::InvalidDefaultInput(_) => ApiErrorCode::InvalidArgument,
+            _ => ApiErrorCode::HostRejected,
+        })
+    });
+    let outputs = match res {
+        Ok(pvs) => pvs,
+        Err(e) => return e.into(),
+    };
+    write_call_result(env.data_mut(), &outputs, res_mem)
+}
+
+/// Gets the outputs of the last nested call of the plugin, when they did not fit in the buffer
+/// provided to [`poctl_from_plugin`] or [`call_default_from_plugin`].
+///
+/// Function intended to be part of the Plugin API.
+fn get_call_result_from_plugin<CTP: ConnectionToPlugin>(
+    mut env: FunctionEnvMut<Env<CTP>>,
+    res_ptr: WasmPtr<u8>,
+    res_len: WASMLen,
+) -> APIResult {
+    if !env.data().has_permission(Permission::NestedCalls) {
+        return ApiErrorCode::PermissionDenied.into();
+    }
+    let instance = if let Some(i) = env.data().get_instance() {
+        i
+    } else {
+        return ApiErrorCode::NoInstance.into();
+    };
+    let instance = instance.as_ref();
+    let memory = match instance.exports.get_memory("memory") {
+        Ok(m) => m,
+        Err(_) => return ApiErrorCode::NoMemory.into(),
+    };
+    let view = memory.view(&env);
+    // SAFETY: Given that plugins are single-threaded per-connection, this does
+    // not introduce any UB.
+    let memory_slice = unsafe { view.data_unchecked_mut() };
+    // SAFETY:  Also, this won't increase the memory of the plugin,
+    // as the guest will preallocate the memory.
+    let memory_slice =
+        unsafe { std::slice::from_raw_parts_mut(memory_slice.as_mut_ptr(), memory_slice.len()) };
+    let res_mem = match guest_bytes_mut(memory_slice, res_ptr, res_len) {
+        Some(m) => m,
+        None => return ApiErrorCode::BadPointer.into(),
+    };
+    let res = match env.data().call_result() {
+        Some(r) => write_result(r, res_mem),
+        None => return ApiErrorCode::NotFound.into(),
+    };
+    if res == 0 {
+        env.data_mut().set_call_result(None);
     }
+    res
 }
 
 macro_rules! exports_insert {
@@ -779,12 +1282,14 @@ pub fn get_imports_with<CTP: ConnectionToPlugin>(
     exports_insert!(exports, store, env, get_input_from_plugin);
     exports_insert!(exports, store, env, get_inputs_from_plugin);
     exports_insert!(exports, store, env, print_from_plugin);
+    exports_insert!(exports, store, env, log_from_plugin);
     exports_insert!(exports, store, env, get_connection_from_plugin);
     exports_insert!(exports, store, env, set_connection_from_plugin);
     exports_insert!(exports, store, env, get_bytes_from_plugin);
     exports_insert!(exports, store, env, put_bytes_from_plugin);
     exports_insert!(exports, store, env, register_from_plugin);
     exports_insert!(exports, store, env, set_timer_from_plugin);
+    exports_insert!(exports, store, env, set_relative_timer_from_plugin);
     exports_insert!(exports, store, env, cancel_timer_from_plugin);
     exports_insert!(exports, store, env, get_unix_instant_from_plugin);
     exports_insert!(exports, store, env, create_file_from_plugin);
@@ -793,6 +1298,8 @@ pub fn get_imports_with<CTP: ConnectionToPlugin>(
     exports_insert!(exports, store, env, get_recovery_from_plugin);
     exports_insert!(exports, store, env, set_recovery_from_plugin);
     exports_insert!(exports, store, env, poctl_from_plugin);
+    exports_insert!(exports, store, env, get_call_result_from_plugin);
+    exports_insert!(exports, store, env, call_default_from_plugin);
 
     let mut imports = Imports::new();
     imports.register_namespace("env", exports);
@@ -0,0 +1,103 @@
+//! Sources of time for the plugins.
+//!
+//! A [`PluginHandler`](crate::handler::PluginHandler) reads the time through a [`Clock`]. It uses
+//! the [`SystemClock`] by default, but tests and simulators can provide a [`VirtualClock`] whose
+//! time only moves when they advance it.
+
+use std::{
+    fmt::Debug,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use unix_time::Instant as UnixInstant;
+
+/// A source of time.
+pub trait Clock: Debug + Send + Sync {
+    /// The current monotonic time.
+    fn now(&self) -> Instant;
+
+    /// The current UNIX-based time.
+    fn unix_now(&self) -> UnixInstant;
+}
+
+/// The clock of the system.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn unix_now(&self) -> UnixInstant {
+        UnixInstant::now()
+    }
+}
+
+/// A clock whose time only moves when advanced. Clones share the same time, so a clone can be
+/// given to a handler while the original drives it.
+#[derive(Clone, Debug)]
+pub struct VirtualClock {
+    /// The monotonic time at which the clock started.
+    start: Instant,
+    /// The UNIX-based time at which the clock started.
+    unix_start: UnixInstant,
+    /// The nanoseconds elapsed since the clock started.
+    elapsed: Arc<AtomicU64>,
+}
+
+impl VirtualClock {
+    /// Create a virtual clock starting at the current time of the system.
+    pub fn new() -> Self {
+        Self::starting_at(Instant::now(), UnixInstant::now())
+    }
+
+    /// Create a virtual clock starting at the provided times.
+    pub fn starting_at(start: Instant, unix_start: UnixInstant) -> Self {
+        Self {
+            start,
+            unix_start,
+            elapsed: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Move the time of the clock forward by `d`.
+    pub fn advance(&self, d: Duration) {
+        let nanos = u64::try_from(d.as_nanos()).unwrap_or(u64::MAX);
+        self.elapsed
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |e| {
+                Some(e.saturating_add(nanos))
+            })
+            .ok();
+    }
+
+    /// Move the time of the clock forward up to `t`. Does nothing if `t` is in the past.
+    pub fn advance_to(&self, t: Instant) {
+        self.advance(t.saturating_duration_since(self.now()));
+    }
+
+    /// The time elapsed since the clock started.
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_nanos(self.elapsed.load(Ordering::Relaxed))
+    }
+}
+
+impl Default for VirtualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+
+    fn unix_now(&self) -> UnixInstant {
+        self.unix_start + self.elapsed()
+    }
+}
@@ -0,0 +1,60 @@
+//! Per-plugin settings chosen by the host when inserting a plugin.
+
+use std::collections::BTreeSet;
+
+use log::LevelFilter;
+
+use crate::Permission;
+
+/// Bounds on the resources that a plugin instance may use.
+///
+/// `None` means that the related resource is only bounded by the WebAssembly specification and
+/// the plugin bytecode.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PluginLimits {
+    /// The maximum number of 64 KiB pages of the plugin linear memory.
+    pub memory_pages: Option<u32>,
+    /// The maximum number of elements of the plugin tables.
+    pub table_elements: Option<u32>,
+    /// The size, in bytes, of the stack on which the plugin runs. It bounds the call depth, and
+    /// exhausting it fails the call instead of crashing the host.
+    ///
+    /// Note that the runtime keeps a process-wide pool of stacks, and only uses this size when
+    /// the pool is empty. A plugin may thus run on a stack allocated for another plugin, so
+    /// this setting is a hint rather than a guarantee.
+    pub stack_size: Option<usize>,
+    /// The maximum number of pending timers of the plugin. Arming a timer beyond it fails with
+    /// [`ApiErrorCode::TooManyTimers`](pluginop_common::ApiErrorCode::TooManyTimers).
+    pub timers: Option<usize>,
+}
+
+/// The settings of an inserted plugin.
+#[derive(Clone, Debug)]
+pub struct PluginConfig {
+    /// The resource limits of the plugin.
+    pub limits: PluginLimits,
+    /// The permissions granted to the plugin. A host API call requiring a missing permission
+    /// fails with [`ApiErrorCode::PermissionDenied`](pluginop_common::ApiErrorCode::PermissionDenied).
+    pub permissions: BTreeSet<Permission>,
+    /// The priority of the plugin when several plugins define the same operation: the one with
+    /// the highest priority serves it, and hooks run by decreasing priority. Plugins with the same
+    /// priority keep their insertion order. `None` falls back to the priority stated in the plugin
+    /// manifest, or 0.
+    pub priority: Option<i32>,
+    /// The most verbose level of the messages logged by the plugin that get forwarded to the
+    /// `log` facade of the host.
+    pub log_level: LevelFilter,
+}
+
+impl Default for PluginConfig {
+    /// No resource limits, all permissions granted, the priority of the manifest and all the
+    /// logged messages forwarded.
+    fn default() -> Self {
+        Self {
+            limits: PluginLimits::default(),
+            permissions: Permission::ALL.into_iter().collect(),
+            priority: None,
+            log_level: LevelFilter::Trace,
+        }
+    }
+}
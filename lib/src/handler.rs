@@ -3,30 +3,32 @@
 use std::{
     marker::PhantomPinned,
     ops::{Deref, DerefMut},
-    path::PathBuf,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::Arc,
     time::Instant,
 };
 
-use log::error;
-use pluginop_common::{quic::Registration, Anchor, Bytes, PluginOp, PluginVal};
+use log::{debug, error, warn, LevelFilter};
+use pluginop_common::{
+    manifest::PluginManifest, quic::Registration, Anchor, ApiErrorCode, Bytes, PluginOp, PluginVal,
+};
 use unix_time::Instant as UnixInstant;
-use wasmer::{Engine, Exports, FunctionEnv, Store};
-use wasmer_compiler_singlepass::Singlepass;
+use wasmer::{Exports, FunctionEnv, Store};
 
 use crate::{
     api::{CTPError, ConnectionToPlugin},
+    clock::{Clock, SystemClock},
+    config::PluginConfig,
     plugin::{Env, Plugin},
+    record::{NestedCall, Recorder, ReplayedAnswers},
+    registry::{CompiledModule, PluginRegistry},
+    stats::{CallKey, StatsSnapshot},
     BytesContent, Error, PluginizableConnection,
 };
 
 use pluginop_rawptr::RawMutPtr;
 
-/// Get a store for plugins. Note that this function should be called once for a host.
-fn create_engine() -> Engine {
-    let compiler = Singlepass::new();
-    compiler.into()
-}
-
 /// A pinned `Vec` of plugins.
 struct PluginArray<CTP: ConnectionToPlugin> {
     /// The inner array.
@@ -58,12 +60,133 @@ impl<CTP: ConnectionToPlugin> PluginArray<CTP> {
     fn get_first_plugin(&mut self, po: &PluginOp) -> Option<&mut Plugin<CTP>> {
         self.iter_mut().find(|p| p.provides(po, Anchor::Define))
     }
+
+    /// Returns the index at which a plugin with the provided `priority` should be inserted, i.e.,
+    /// after all the plugins with a higher or equal priority.
+    fn insertion_index(&self, priority: i32) -> usize {
+        self.partition_point(|p| p.priority() >= priority)
+    }
+
+    /// Returns the operations defined by more than one plugin, with the identifiers of these
+    /// plugins in the order they are consulted.
+    fn conflicts(&self) -> Vec<(PluginOp, Vec<PluginId>)> {
+        let mut defined: Vec<(PluginOp, Vec<PluginId>)> = Vec::new();
+        for p in self.iter() {
+            for po in p.defined_ops() {
+                match defined.iter_mut().find(|(dpo, _)| dpo == po) {
+                    Some((_, ids)) => ids.push(p.id()),
+                    None => defined.push((*po, vec![p.id()])),
+                }
+            }
+        }
+        defined.retain(|(_, ids)| ids.len() > 1);
+        defined
+    }
+
+    /// Returns whether any of the plugins has bytecode for each anchor.
+    fn has_anchor(&self) -> [bool; 4] {
+        let mut has_anchor = [false; 4];
+        for p in self.iter() {
+            has_anchor
+                .iter_mut()
+                .zip(p.has_anchor())
+                .for_each(|(i, e)| *i |= e);
+        }
+        has_anchor
+    }
+}
+
+/// The host implementation of a plugin operation, that the plugin defining the operation can
+/// invoke through [`PluginHandler::call_default`]. It takes the inputs of the operation, in the
+/// same order as the parameters given to the plugin, and returns the outputs of the host
+/// implementation.
+pub type DefaultFn<'a, CTP> =
+    dyn FnMut(&mut PluginHandler<CTP>, &[PluginVal]) -> Result<Vec<PluginVal>, Error> + 'a;
+
+/// A plugin call running in a [`PluginHandler`].
+enum RunningCall<CTP: ConnectionToPlugin> {
+    /// The host invoked the definition of `po` by a plugin, and `default` is the host
+    /// implementation of the operation.
+    Define {
+        po: PluginOp,
+        default: RawMutPtr<DefaultFn<'static, CTP>>,
+    },
+    /// Any other call, during which no host implementation can be invoked.
+    Barrier,
+}
+
+/// What a [`PluginHandler`] does when a plugin fails to serve an operation invoked by the host,
+/// e.g., because it trapped, exceeded its budget or returned unexpected outputs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FailurePolicy {
+    /// Panic, bringing down the host.
+    #[default]
+    Panic,
+    /// Run the host implementation of the operation instead.
+    Fallback,
+    /// Run the host implementation of the operation instead, and stop using the failing plugin
+    /// for the rest of the connection.
+    Quarantine,
+}
+
+/// A plugin failure, as reported to the callback set with
+/// [`PluginHandler::set_failure_callback`].
+#[derive(Debug)]
+pub struct PluginFailure<'a> {
+    /// The operation invoked by the host.
+    pub po: PluginOp,
+    /// The plugin that failed, if known.
+    pub plugin: Option<PluginId>,
+    /// The error raised by the plugin.
+    pub error: &'a Error,
+    /// The policy applied.
+    pub policy: FailurePolicy,
+}
+
+type FailureCallback = Box<dyn FnMut(&PluginFailure)>;
+
+/// The error code converted into the error of an operation returning a [`Result`] when one of
+/// its `Before` anchors fails in strict mode without returning an error code itself, e.g.,
+/// because it trapped or exceeded its budget. Plugins should not return this code.
+pub const BEFORE_ANCHOR_FAILED: i64 = i64::MIN;
+
+/// A failure of a plugin running a `Before`, `Wrap` or `After` anchor of an operation whose
+/// definition is left to the host, as reported to the callback set with
+/// [`PluginHandler::set_anchor_failure_callback`].
+#[derive(Debug)]
+pub struct AnchorFailure<'a> {
+    /// The operation invoked by the host.
+    pub po: PluginOp,
+    /// The anchor that failed.
+    pub anchor: Anchor,
+    /// The plugin that failed, if known.
+    pub plugin: Option<PluginId>,
+    /// The error raised by the plugin.
+    pub error: &'a Error,
+}
+
+type AnchorFailureCallback = Box<dyn FnMut(&AnchorFailure)>;
+
+/// Logs `failure` and reports it to `callback`, if any.
+fn report_anchor_failure(callback: Option<&mut AnchorFailureCallback>, failure: &AnchorFailure) {
+    warn!(
+        "plugin {:?} failed on {:?} of {:?}: {:?}",
+        failure.plugin, failure.anchor, failure.po, failure.error
+    );
+    if let Some(callback) = callback {
+        callback(failure);
+    }
 }
 
+/// The identifier of a plugin inserted in a [`PluginHandler`].
+///
+/// Identifiers are unique within a given handler and are never reused.
+pub type PluginId = u64;
+
 /// The core structure handling the pluginization of connections.
 pub struct PluginHandler<CTP: ConnectionToPlugin> {
-    /// The engine used to instantiate plugins.
-    engine: Engine,
+    /// The registry compiling the plugins and holding the engine used to instantiate them.
+    registry: Arc<PluginRegistry>,
     /// A pointer to the serving session. It can stay null if no plugin is inserted.
     conn: RawMutPtr<PluginizableConnection<CTP>>,
     /// Function creating an `Imports`.
@@ -74,6 +197,12 @@ pub struct PluginHandler<CTP: ConnectionToPlugin> {
     bytes_contents: Vec<BytesContent>,
     /// Registrations made by the plugins.
     registrations: Vec<Registration>,
+    /// The plugin that made each of the `registrations`, if any.
+    registration_owners: Vec<Option<PluginId>>,
+    /// The identifier to give to the next inserted plugin.
+    next_plugin_id: PluginId,
+    /// The source of time of the plugins.
+    clock: Arc<dyn Clock>,
     /// A reference time used to make conversions between `Duration` at plugin side
     /// and `Instant` at host side.
     reference_instant: Instant,
@@ -81,7 +210,29 @@ pub struct PluginHandler<CTP: ConnectionToPlugin> {
     /// plugin side and `Instant` at host side.
     reference_unix_instant: UnixInstant,
     /// Whether the anchor is provided by any of the plugins.
-    has_anchor: [bool; 3],
+    has_anchor: [bool; 4],
+    /// The maximum number of instructions a plugin can execute for a plugin operation call.
+    call_budget: Option<u64>,
+    /// The maximum number of instructions a plugin can execute for a timer callback.
+    timer_budget: Option<u64>,
+    /// The plugin calls currently running, the innermost one being the last.
+    running: Vec<RunningCall<CTP>>,
+    /// What to do when a plugin fails.
+    failure_policy: FailurePolicy,
+    /// Called on each plugin failure.
+    failure_callback: Option<FailureCallback>,
+    /// The last plugin that failed during the current call.
+    failed_plugin: Option<PluginId>,
+    /// Whether failures of `Before` anchors abort the operation.
+    strict_anchors: bool,
+    /// Called on each anchor failure.
+    anchor_failure_callback: Option<AnchorFailureCallback>,
+    /// Whether statistics about the plugin calls are collected.
+    stats_enabled: bool,
+    /// Records the plugin calls, if enabled.
+    recorder: Option<Recorder>,
+    /// The answers served to the plugins instead of the live ones, if replaying a call.
+    replayed: Option<ReplayedAnswers>,
     /// Force this structure to be pinned.
     _pin: PhantomPinned,
 }
@@ -97,17 +248,43 @@ impl<CTP: ConnectionToPlugin> std::fmt::Debug for PluginHandler<CTP> {
 impl<CTP: ConnectionToPlugin> PluginHandler<CTP> {
     /// Create a new [`PluginHandler`], enabling the execution of plugins inserted on the fly to
     /// customize the behavior of a connection.
+    ///
+    /// The handler gets its own [`PluginRegistry`]. Prefer
+    /// [`PluginHandler::new_with_registry`] when many connections load the same plugins.
     pub fn new(exports_func: fn(&mut Store, &FunctionEnv<Env<CTP>>) -> Exports) -> Self {
+        Self::new_with_registry(exports_func, Arc::new(PluginRegistry::new()))
+    }
+
+    /// Create a new [`PluginHandler`] compiling its plugins through the provided, possibly
+    /// shared, [`PluginRegistry`].
+    pub fn new_with_registry(
+        exports_func: fn(&mut Store, &FunctionEnv<Env<CTP>>) -> Exports,
+        registry: Arc<PluginRegistry>,
+    ) -> Self {
         Self {
-            engine: create_engine(),
+            registry,
             conn: RawMutPtr::null(),
             exports_func,
             plugins: PluginArray { array: Vec::new() },
             bytes_contents: Vec::new(),
             registrations: Vec::new(),
+            registration_owners: Vec::new(),
+            next_plugin_id: 0,
+            clock: Arc::new(SystemClock),
             reference_instant: Instant::now(),
             reference_unix_instant: UnixInstant::now(),
-            has_anchor: [false; 3],
+            has_anchor: [false; 4],
+            call_budget: None,
+            timer_budget: None,
+            running: Vec::new(),
+            failure_policy: FailurePolicy::default(),
+            failure_callback: None,
+            failed_plugin: None,
+            strict_anchors: false,
+            anchor_failure_callback: None,
+            stats_enabled: false,
+            recorder: None,
+            replayed: None,
             _pin: PhantomPinned,
         }
     }
@@ -126,12 +303,23 @@ impl<CTP: ConnectionToPlugin> PluginHandler<CTP> {
         true
     }
 
+    /// Read the bytecode of the plugin located at the provided path.
+    fn read_plugin(plugin_fname: &PathBuf) -> Result<Vec<u8>, Error> {
+        std::fs::read(plugin_fname).map_err(|e| {
+            error!("Cannot read plugin: {}", e);
+            Error::PluginLoadingError(e.to_string())
+        })
+    }
+
     pub(crate) fn insert_plugin_internal(
         &mut self,
-        plugin_fname: &PathBuf,
+        module: &CompiledModule,
+        config: &PluginConfig,
         force_enable: bool,
-    ) -> Result<(), Error> {
-        let mut plugin = Plugin::new(plugin_fname, self)?;
+    ) -> Result<PluginId, Error> {
+        let id = self.next_plugin_id;
+        let mut plugin = Plugin::new(id, module, config, self)?;
+        self.next_plugin_id += 1;
         // Cache whether anchors are provided.
         self.has_anchor
             .iter_mut()
@@ -140,29 +328,175 @@ impl<CTP: ConnectionToPlugin> PluginHandler<CTP> {
         if force_enable {
             plugin.force_enable();
         }
-        self.plugins.push(plugin);
+        plugin.set_stats_enabled(self.stats_enabled);
+        let idx = self.plugins.insertion_index(plugin.priority());
+        self.plugins.insert(idx, plugin);
         // Now the plugin is at its definitive area in memory, so we can initialize it.
-        self.plugins
-            .last_mut()
+        let res = self
+            .plugins
+            .get_mut(idx)
             .ok_or(Error::PluginLoadingError("PluginNotInserted".to_string()))?
-            .initialize()
-            .map_err(|e| Error::PluginLoadingError(format!("{:?}", e)))
+            .initialize(self.call_budget);
+        if let Err(e) = res {
+            // Do not keep a half-initialized plugin around, nor what it registered so far.
+            self.remove_plugin(id)?;
+            return Err(Error::PluginLoadingError(format!("{:?}", e)));
+        }
+        Ok(id)
     }
 
-    /// Attach a new plugin whose bytecode is accessible through the provided path. Return whether
-    /// the insertion succeeded, or the related [`Error`] otherwise.
+    /// Attach a new plugin whose bytecode is accessible through the provided path. Return the
+    /// identifier of the inserted plugin if the insertion succeeded, or the related [`Error`]
+    /// otherwise.
     ///
     /// If the insertion succeeds and the plugin provides an `init` function as a protocol
     /// operation, this function calls it. This can be useful to, e.g., initialize a plugin-specific
     /// structure or register new frames.
-    pub fn insert_plugin(&mut self, plugin_fname: &PathBuf) -> Result<(), Error> {
-        self.insert_plugin_internal(plugin_fname, false)
+    ///
+    /// When several plugins define the same operation, the one with the highest priority (see
+    /// [`PluginConfig::priority`]) serves it. See also [`PluginHandler::conflicts`].
+    pub fn insert_plugin(&mut self, plugin_fname: &PathBuf) -> Result<PluginId, Error> {
+        self.insert_plugin_with_config(plugin_fname, &PluginConfig::default())
+    }
+
+    /// Same as [`PluginHandler::insert_plugin`], but with the provided [`PluginConfig`].
+    pub fn insert_plugin_with_config(
+        &mut self,
+        plugin_fname: &PathBuf,
+        config: &PluginConfig,
+    ) -> Result<PluginId, Error> {
+        let wasm = Self::read_plugin(plugin_fname)?;
+        self.insert_plugin_from_bytes_with_config(&wasm, config)
+    }
+
+    /// Attach a new plugin from its WebAssembly bytecode, e.g., received from the network or
+    /// embedded with `include_bytes!`. Behaves like [`PluginHandler::insert_plugin`].
+    pub fn insert_plugin_from_bytes(&mut self, wasm: &[u8]) -> Result<PluginId, Error> {
+        self.insert_plugin_from_bytes_with_config(wasm, &PluginConfig::default())
+    }
+
+    /// Same as [`PluginHandler::insert_plugin_from_bytes`], but with the provided
+    /// [`PluginConfig`].
+    pub fn insert_plugin_from_bytes_with_config(
+        &mut self,
+        wasm: &[u8],
+        config: &PluginConfig,
+    ) -> Result<PluginId, Error> {
+        let module = self.registry.get_or_compile(wasm)?;
+        self.insert_plugin_internal(&module, config, false)
+    }
+
+    /// Attach a new plugin from a pre-compiled artifact, as produced by
+    /// [`PluginHandler::precompile_plugin`]. This skips the compilation step. Behaves like
+    /// [`PluginHandler::insert_plugin`].
+    ///
+    /// # Safety
+    ///
+    /// The artifact is loaded as native code without any validation. It must come from a
+    /// trusted source and must have been produced by the same version of this crate, on the
+    /// same target.
+    pub unsafe fn insert_plugin_from_serialized(
+        &mut self,
+        artifact: &[u8],
+    ) -> Result<PluginId, Error> {
+        self.insert_plugin_from_serialized_with_config(artifact, &PluginConfig::default())
+    }
+
+    /// Same as [`PluginHandler::insert_plugin_from_serialized`], but with the provided
+    /// [`PluginConfig`].
+    ///
+    /// # Safety
+    ///
+    /// See [`PluginHandler::insert_plugin_from_serialized`].
+    pub unsafe fn insert_plugin_from_serialized_with_config(
+        &mut self,
+        artifact: &[u8],
+        config: &PluginConfig,
+    ) -> Result<PluginId, Error> {
+        let module = self.registry.get_or_deserialize(artifact)?;
+        self.insert_plugin_internal(&module, config, false)
+    }
+
+    /// Compile the provided WebAssembly bytecode and return the serialized native artifact, that
+    /// can later be loaded with [`PluginHandler::insert_plugin_from_serialized`].
+    pub fn precompile_plugin(&self, wasm: &[u8]) -> Result<Vec<u8>, Error> {
+        let module = self.registry.get_or_compile(wasm)?;
+        module
+            .module()
+            .serialize()
+            .map(|b| b.to_vec())
+            .map_err(|e| Error::PluginLoadingError(e.to_string()))
     }
 
     /// To be used in testing code only.
     #[doc(hidden)]
-    pub fn insert_plugin_testing(&mut self, plugin_fname: &PathBuf) -> Result<(), Error> {
-        self.insert_plugin_internal(plugin_fname, true)
+    pub fn insert_plugin_testing(&mut self, plugin_fname: &PathBuf) -> Result<PluginId, Error> {
+        let wasm = Self::read_plugin(plugin_fname)?;
+        let module = self.registry.get_or_compile(&wasm)?;
+        self.insert_plugin_internal(&module, &PluginConfig::default(), true)
+    }
+
+    /// Detach the plugin with the provided identifier from the connection.
+    ///
+    /// Its pending timers are cancelled, its files are closed and the [`Registration`]s it made
+    /// are withdrawn. The plugin operations it provided fall back to the remaining plugins, or
+    /// to the host implementation.
+    pub fn remove_plugin(&mut self, id: PluginId) -> Result<(), Error> {
+        let idx = self
+            .plugins
+            .iter()
+            .position(|p| p.id() == id)
+            .ok_or(Error::UnknownPlugin(id))?;
+        let mut plugin = self.plugins.remove(idx);
+        plugin.teardown();
+        drop(plugin);
+
+        // Withdraw the registrations of the removed plugin.
+        let owners = std::mem::take(&mut self.registration_owners);
+        (self.registrations, self.registration_owners) = self
+            .registrations
+            .drain(..)
+            .zip(owners)
+            .filter(|(_, o)| *o != Some(id))
+            .unzip();
+
+        // And update the anchor cache with the remaining plugins.
+        self.has_anchor = self.plugins.has_anchor();
+        Ok(())
+    }
+
+    /// Return the identifiers of the plugins currently inserted, by decreasing priority. Plugins
+    /// with the same priority are listed in insertion order.
+    pub fn plugin_ids(&self) -> Vec<PluginId> {
+        self.plugins.iter().map(|p| p.id()).collect()
+    }
+
+    /// Return the plugin operations defined by more than one inserted plugin, along with the
+    /// identifiers of these plugins by decreasing priority. Only the first of them serves the
+    /// operation, the other definitions are shadowed.
+    pub fn conflicts(&self) -> Vec<(PluginOp, Vec<PluginId>)> {
+        self.plugins.conflicts()
+    }
+
+    /// Return the manifest embedded in the plugin with the provided identifier, if the plugin
+    /// exists and has one.
+    pub fn get_manifest(&self, id: PluginId) -> Option<&PluginManifest> {
+        self.plugins
+            .iter()
+            .find(|p| p.id() == id)
+            .and_then(|p| p.manifest())
+    }
+
+    /// Set the most verbose level of the messages logged by the plugin with the provided
+    /// identifier that get forwarded to the `log` facade, overriding
+    /// [`PluginConfig::log_level`].
+    pub fn set_log_level(&mut self, id: PluginId, level: LevelFilter) -> Result<(), Error> {
+        self.plugins
+            .iter_mut()
+            .find(|p| p.id() == id)
+            .ok_or(Error::UnknownPlugin(id))?
+            .set_log_level(level);
+        Ok(())
     }
 
     /// Return whether there is a bytecode providing the plugin operation
@@ -171,19 +505,236 @@ impl<CTP: ConnectionToPlugin> PluginHandler<CTP> {
         self.has_anchor[anchor.index()] && self.plugins.provides(po, anchor)
     }
 
+    /// Limit the number of WebAssembly instructions that a plugin can execute when serving a
+    /// plugin operation, including the calls it triggers. Exhausting the budget aborts the
+    /// call with [`Error::BudgetExceeded`]. `None`, the default, means no limit.
+    pub fn set_call_budget(&mut self, budget: Option<u64>) {
+        self.call_budget = budget;
+    }
+
+    /// Set what to do when a plugin fails to serve an operation invoked by the host. Defaults
+    /// to [`FailurePolicy::Panic`].
+    pub fn set_failure_policy(&mut self, policy: FailurePolicy) {
+        self.failure_policy = policy;
+    }
+
+    /// The policy applied on plugin failures.
+    pub fn failure_policy(&self) -> FailurePolicy {
+        self.failure_policy
+    }
+
+    /// Set a callback reporting each plugin failure, before the [`FailurePolicy`] is applied.
+    pub fn set_failure_callback<F: FnMut(&PluginFailure) + 'static>(&mut self, callback: F) {
+        self.failure_callback = Some(Box::new(callback));
+    }
+
+    /// Return whether the plugin with the provided identifier was quarantined after a failure.
+    pub fn is_quarantined(&self, id: PluginId) -> bool {
+        self.plugins
+            .iter()
+            .any(|p| p.id() == id && p.is_quarantined())
+    }
+
+    /// Handle the failure of a plugin serving the host-invoked operation `po`, according to the
+    /// [`FailurePolicy`]. The failing plugin is the last one whose call failed or, if none did,
+    /// the one defining `po`.
+    pub fn handle_failure(&mut self, po: &PluginOp, error: &Error) {
+        let plugin = self
+            .failed_plugin
+            .take()
+            .or_else(|| self.plugins.get_first_plugin(po).map(|p| p.id()));
+        error!(
+            "plugin {:?} failed on {:?}: {:?}; applying {:?}",
+            plugin, po, error, self.failure_policy
+        );
+        if let Some(callback) = self.failure_callback.as_mut() {
+            callback(&PluginFailure {
+                po: *po,
+                plugin,
+                error,
+                policy: self.failure_policy,
+            });
+        }
+        match self.failure_policy {
+            FailurePolicy::Panic => panic!("plugin execution error: {:?}", error),
+            FailurePolicy::Fallback => {}
+            FailurePolicy::Quarantine => {
+                if let Some(p) = self.plugins.iter_mut().find(|p| Some(p.id()) == plugin) {
+                    p.quarantine();
+                }
+            }
+        }
+    }
+
+    /// Set whether a failing `Before` anchor of an operation defined by the host aborts the
+    /// operation. When strict, an operation returning a [`Result`] returns the error code of
+    /// the plugin converted into its error, or [`BEFORE_ANCHOR_FAILED`] if the plugin did not
+    /// return any, e.g., because it trapped. As other operations cannot return an error, the
+    /// failure is handled according to the [`FailurePolicy`], and the operation proceeds
+    /// unless the policy panics. Otherwise, the default, anchor failures are only reported and
+    /// the operation proceeds.
+    pub fn set_strict_anchors(&mut self, strict: bool) {
+        self.strict_anchors = strict;
+    }
+
+    /// Whether failing `Before` anchors abort the operation.
+    pub fn strict_anchors(&self) -> bool {
+        self.strict_anchors
+    }
+
+    /// Set a callback reporting each failure of a plugin running an anchor of an operation
+    /// defined by the host.
+    pub fn set_anchor_failure_callback<F: FnMut(&AnchorFailure) + 'static>(&mut self, callback: F) {
+        self.anchor_failure_callback = Some(Box::new(callback));
+    }
+
+    /// Report the failure of the last failing plugin running the `anchor` of `po`, an operation
+    /// defined by the host.
+    pub fn anchor_failed(&mut self, po: &PluginOp, anchor: Anchor, error: &Error) {
+        let plugin = self.failed_plugin.take();
+        report_anchor_failure(
+            self.anchor_failure_callback.as_mut(),
+            &AnchorFailure {
+                po: *po,
+                anchor,
+                plugin,
+                error,
+            },
+        );
+    }
+
+    /// Start or stop collecting statistics about the calls made to the plugins, including
+    /// timer callbacks. The time spent in a call includes the one of the nested calls it
+    /// triggers. Stopping drops the statistics collected so far.
+    pub fn set_stats_enabled(&mut self, enabled: bool) {
+        self.stats_enabled = enabled;
+        for p in self.plugins.iter_mut() {
+            p.set_stats_enabled(enabled);
+        }
+    }
+
+    /// Whether statistics about the plugin calls are collected.
+    pub fn stats_enabled(&self) -> bool {
+        self.stats_enabled
+    }
+
+    /// Return the statistics collected about the calls of each plugin, operation and anchor.
+    /// The snapshot is empty if statistics are not collected.
+    pub fn stats(&self) -> StatsSnapshot {
+        let mut snapshot = StatsSnapshot::default();
+        for p in self.plugins.iter() {
+            for ((po, anchor), stats) in p.stats().into_iter().flatten() {
+                let key = CallKey {
+                    plugin: p.id(),
+                    po: *po,
+                    anchor: *anchor,
+                };
+                snapshot.insert(key, stats.clone());
+            }
+        }
+        snapshot
+    }
+
+    /// Reset the statistics collected so far.
+    pub fn reset_stats(&mut self) {
+        for p in self.plugins.iter_mut() {
+            p.reset_stats();
+        }
+    }
+
+    /// Start recording the calls made to the plugins, including their nested ones, in a new
+    /// trace at `path`. See [`record`](crate::record) for the trace format. An ongoing
+    /// recording is stopped first.
+    pub fn start_recording(&mut self, path: &Path) -> Result<(), Error> {
+        self.stop_recording()?;
+        self.recorder = Some(Recorder::create(path)?);
+        Ok(())
+    }
+
+    /// Stop recording the calls made to the plugins, and flush the trace.
+    pub fn stop_recording(&mut self) -> Result<(), Error> {
+        match self.recorder.take() {
+            Some(mut r) => r.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// Whether the calls made to the plugins are being recorded.
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    /// The recorder of the plugin calls, if recording.
+    pub(crate) fn recorder_mut(&mut self) -> Option<&mut Recorder> {
+        self.recorder.as_mut()
+    }
+
+    /// Serve the times and the answers to nested calls recorded in `answers` to the plugins,
+    /// instead of the live ones, until reset with `None`. This enables replaying a recorded
+    /// call, see [`ReplayedAnswers::from_record`].
+    pub fn set_replayed_answers(&mut self, answers: Option<ReplayedAnswers>) {
+        self.replayed = answers;
+    }
+
+    /// The current UNIX-based time given to the plugins, or `None` if a replayed call has no
+    /// recorded time left.
+    pub(crate) fn plugin_unix_now(&mut self) -> Option<UnixInstant> {
+        let now = match self.replayed.as_mut() {
+            Some(r) => r.next_clock_read()?,
+            None => self.unix_now(),
+        };
+        if let Some(r) = self.recorder.as_mut() {
+            r.clock_read(now);
+        }
+        Some(now)
+    }
+
+    /// Answer the nested `call` made by a plugin by running `run`, or with the recorded answer
+    /// if replaying a call.
+    pub(crate) fn nested_call<F>(
+        &mut self,
+        call: NestedCall,
+        run: F,
+    ) -> Result<Vec<PluginVal>, ApiErrorCode>
+    where
+        F: FnOnce(&mut Self) -> Result<Vec<PluginVal>, ApiErrorCode>,
+    {
+        let res = match self.replayed.as_mut() {
+            Some(r) => r.next_nested_call(call),
+            None => run(self),
+        };
+        if let Some(r) = self.recorder.as_mut() {
+            r.nested_call(call, &res);
+        }
+        res
+    }
+
+    /// Same as [`PluginHandler::set_call_budget`], but for each timer callback of the plugins.
+    pub fn set_timer_budget(&mut self, budget: Option<u64>) {
+        self.timer_budget = budget;
+    }
+
     /// Return the first timeout event required by a plugin.
     pub fn timeout(&self) -> Option<Instant> {
-        self.plugins.iter().filter_map(|p| p.timeout()).min()
+        self.plugins
+            .iter()
+            .filter(|p| !p.is_quarantined())
+            .filter_map(|p| p.timeout())
+            .min()
     }
 
     /// Call potential timeouts that fired since the provided time.
     ///
-    /// If there were not firing timers, this method does nothing.
+    /// If there were not firing timers, this method does nothing. A failing timer callback does
+    /// not prevent the other timers from being serviced, and the first failure is returned.
     pub fn on_timeout(&mut self, t: Instant) -> Result<(), Error> {
-        for p in self.plugins.iter_mut() {
-            p.on_timeout(t)?;
-        }
-        Ok(())
+        self.enter(RunningCall::Barrier, |ph| {
+            let mut res = Ok(());
+            for p in ph.plugins.iter_mut().filter(|p| !p.is_quarantined()) {
+                res = res.and(p.on_timeout(t, ph.timer_budget));
+            }
+            res
+        })
     }
 
     /// Get an immutable reference to the serving connection.
@@ -227,6 +778,15 @@ impl<CTP: ConnectionToPlugin> PluginHandler<CTP> {
         self.bytes_contents.clear();
     }
 
+    /// Get a copy of the content behind `bytes`, if it holds a byte vector given to the
+    /// plugins, as they currently see it.
+    pub fn get_copied_bytes(&self, bytes: &Bytes) -> Option<Vec<u8>> {
+        match self.bytes_contents.get(bytes.tag as usize)? {
+            BytesContent::Copied(v) => Some(v.clone()),
+            _ => None,
+        }
+    }
+
     /// Get a mutable reference on the [`BytesContent`] with the associtated `tag`.
     pub(crate) fn get_mut_bytes_content(
         &mut self,
@@ -237,7 +797,18 @@ impl<CTP: ConnectionToPlugin> PluginHandler<CTP> {
 
     /// Register some plugin [`Registration`].
     pub fn add_registration(&mut self, r: Registration) {
+        #[cfg(feature = "tracing")]
+        tracing::debug!(registration = ?r, "host registration");
         self.registrations.push(r);
+        self.registration_owners.push(None);
+    }
+
+    /// Register a [`Registration`] made by the plugin with the identifier `id`.
+    pub(crate) fn add_plugin_registration(&mut self, id: PluginId, r: Registration) {
+        #[cfg(feature = "tracing")]
+        tracing::debug!(plugin = id, registration = ?r, "plugin registration");
+        self.registrations.push(r);
+        self.registration_owners.push(Some(id));
     }
 
     /// Return all the [`Registration`]s that are known by the pluginization handler.
@@ -245,9 +816,9 @@ impl<CTP: ConnectionToPlugin> PluginHandler<CTP> {
         &self.registrations
     }
 
-    /// Clone the plugin environment engine.
-    pub(crate) fn get_cloned_engine(&self) -> Engine {
-        self.engine.clone()
+    /// Return the registry compiling the plugins of this handler.
+    pub fn get_registry(&self) -> &Arc<PluginRegistry> {
+        &self.registry
     }
 
     /// Return the set of exported functions.
@@ -255,6 +826,30 @@ impl<CTP: ConnectionToPlugin> PluginHandler<CTP> {
         self.exports_func
     }
 
+    /// Set the source of time of the plugins, e.g., a [`VirtualClock`](crate::clock::VirtualClock)
+    /// to run timers without waiting for them. The conversions between host-side and plugin-side
+    /// times are reset against the new clock, so it should be set before arming any timer.
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.reference_instant = clock.now();
+        self.reference_unix_instant = clock.unix_now();
+        self.clock = clock;
+    }
+
+    /// Return the source of time of the plugins.
+    pub fn get_clock(&self) -> &Arc<dyn Clock> {
+        &self.clock
+    }
+
+    /// The current time according to the clock of the plugins.
+    pub fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    /// The current UNIX-based time according to the clock of the plugins.
+    pub fn unix_now(&self) -> UnixInstant {
+        self.clock.unix_now()
+    }
+
     /// Gets a UNIX-based `Instant` usable by the plugin side from a host-side `Instant`.
     pub(crate) fn get_unix_instant_from_instant(&self, i: Instant) -> UnixInstant {
         let d = i.duration_since(self.reference_instant);
@@ -262,69 +857,214 @@ impl<CTP: ConnectionToPlugin> PluginHandler<CTP> {
     }
 
     /// Gets a `Instant` usable by the host side from a plugin-side UNIX-based `Instant`.
-    pub(crate) fn get_instant_from_unix_instant(&self, i: UnixInstant) -> Instant {
+    pub fn get_instant_from_unix_instant(&self, i: UnixInstant) -> Instant {
         let d = i.duration_since(self.reference_unix_instant);
         self.reference_instant + d
     }
 
-    /// Invokes the protocol operation `po` and runs its anchors.
+    /// Runs `f` while `call` is the innermost running plugin call. `call` is removed once `f`
+    /// returns, even if it panics.
+    fn enter<T>(&mut self, call: RunningCall<CTP>, f: impl FnOnce(&mut Self) -> T) -> T {
+        self.running.push(call);
+        let res = panic::catch_unwind(AssertUnwindSafe(|| f(self)));
+        self.running.pop();
+        res.unwrap_or_else(|payload| panic::resume_unwind(payload))
+    }
+
+    /// Invokes the protocol operation `po` and runs its anchors. The `Define` anchor runs with
+    /// `define` as its running call.
     fn call_internal(
         &mut self,
         po: &PluginOp,
         params: &[PluginVal],
+        define: RunningCall<CTP>,
     ) -> Result<Vec<PluginVal>, Error> {
         // BEFORE part
-        for p in self
-            .plugins
-            .iter_mut()
-            .filter(|p| p.provides(po, Anchor::Before))
-        {
-            p.call(po, Anchor::Before, params)?;
-        }
+        self.enter(RunningCall::Barrier, |ph| {
+            for p in ph
+                .plugins
+                .iter_mut()
+                .filter(|p| p.provides(po, Anchor::Before))
+            {
+                p.call(po, Anchor::Before, params, ph.call_budget)
+                    .inspect_err(|_| ph.failed_plugin = Some(p.id()))?;
+            }
+            Ok(())
+        })?;
 
         // DEFINE part
-        let res = match self.plugins.get_first_plugin(po) {
-            Some(p) => p.call(po, Anchor::Define, params)?,
-            None => return Err(Error::NoDefault(*po)),
-        };
+        let res = self.enter(define, |ph| match ph.plugins.get_first_plugin(po) {
+            Some(p) => p
+                .call(po, Anchor::Define, params, ph.call_budget)
+                .inspect_err(|_| ph.failed_plugin = Some(p.id())),
+            None => Err(Error::NoDefault(*po)),
+        })?;
+
+        // WRAP part
+        let res = self.call_wrap(po, params, res)?;
 
         // AFTER part
-        for p in self
-            .plugins
-            .iter_mut()
-            .filter(|p| p.provides(po, Anchor::After))
-        {
-            p.call(po, Anchor::After, params)?;
+        if self.provides(po, Anchor::After) {
+            let inputs = [params, &res].concat();
+            self.enter(RunningCall::Barrier, |ph| {
+                for p in ph
+                    .plugins
+                    .iter_mut()
+                    .filter(|p| p.provides(po, Anchor::After))
+                {
+                    p.call(po, Anchor::After, &inputs, ph.call_budget)
+                        .inspect_err(|_| ph.failed_plugin = Some(p.id()))?;
+                }
+                Ok(())
+            })?;
         }
 
         Ok(res)
     }
 
-    /// Only for BEFORE or AFTER calls.
+    /// Runs the `Wrap` anchors of `po`, by decreasing priority, over the outputs `res` of the
+    /// operation. Each anchor receives the outputs after the `params`, and replaces them by its
+    /// own outputs if it provides any. Returns the resulting outputs.
+    pub fn call_wrap(
+        &mut self,
+        po: &PluginOp,
+        params: &[PluginVal],
+        mut res: Vec<PluginVal>,
+    ) -> Result<Vec<PluginVal>, Error> {
+        if !self.provides(po, Anchor::Wrap) {
+            return Ok(res);
+        }
+        self.enter(RunningCall::Barrier, |ph| {
+            for p in ph
+                .plugins
+                .iter_mut()
+                .filter(|p| p.provides(po, Anchor::Wrap))
+            {
+                let outputs = p
+                    .call(po, Anchor::Wrap, &[params, &res].concat(), ph.call_budget)
+                    .inspect_err(|_| ph.failed_plugin = Some(p.id()))?;
+                if !outputs.is_empty() {
+                    res = outputs;
+                }
+            }
+            Ok(res)
+        })
+    }
+
+    /// Only for BEFORE or AFTER calls. AFTER calls expect the outputs of the operation, if any,
+    /// after its inputs in `params`.
+    ///
+    /// Failing plugins are reported to the anchor failure callback, and do not prevent the
+    /// other ones from running. The error of the first failing plugin is then returned, the
+    /// caller deciding whether it aborts the operation (see
+    /// [`PluginHandler::set_strict_anchors`]).
     pub fn call_direct(
         &mut self,
         po: &PluginOp,
         anchor: Anchor,
         params: &[PluginVal],
     ) -> Result<(), Error> {
-        assert_ne!(anchor, Anchor::Define);
-        if anchor == Anchor::Define {
+        if !matches!(anchor, Anchor::Before | Anchor::After) {
             return Err(Error::InternalError(
                 "call_direct only available for Before or After anchors.".to_string(),
             ));
         }
-        for p in self.plugins.iter_mut().filter(|p| p.provides(po, anchor)) {
-            p.call(po, anchor, params)?;
-        }
+        self.failed_plugin = None;
+        self.enter(RunningCall::Barrier, |ph| {
+            let mut res = Ok(());
+            for p in ph.plugins.iter_mut().filter(|p| p.provides(po, anchor)) {
+                if let Err(e) = p.call(po, anchor, params, ph.call_budget) {
+                    report_anchor_failure(
+                        ph.anchor_failure_callback.as_mut(),
+                        &AnchorFailure {
+                            po: *po,
+                            anchor,
+                            plugin: Some(p.id()),
+                            error: &e,
+                        },
+                    );
+                    if res.is_ok() {
+                        ph.failed_plugin = Some(p.id());
+                        res = Err(e);
+                    }
+                }
+            }
+            res
+        })
+    }
 
-        Ok(())
+    /// Invokes the `anchor` of the plugin operation `po` on the plugin with the provided
+    /// identifier only, without running the other anchors nor the other plugins.
+    pub fn call_plugin(
+        &mut self,
+        id: PluginId,
+        po: &PluginOp,
+        anchor: Anchor,
+        params: &[PluginVal],
+    ) -> Result<Vec<PluginVal>, Error> {
+        self.enter(RunningCall::Barrier, |ph| {
+            let budget = ph.call_budget;
+            ph.plugins
+                .iter_mut()
+                .find(|p| p.id() == id)
+                .ok_or(Error::UnknownPlugin(id))?
+                .call(po, anchor, params, budget)
+        })
     }
 
     /// Invokes the plugin operation `po` and runs its anchors.
     pub fn call(&mut self, po: &PluginOp, params: &[PluginVal]) -> Result<Vec<PluginVal>, Error> {
-        // trace!("Calling protocol operation {:?}", po);
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("plugin_op", po = ?po).entered();
+        self.failed_plugin = None;
+        self.call_internal(po, params, RunningCall::Barrier)
+    }
 
-        self.call_internal(po, params)
+    /// Same as [`PluginHandler::call`], but the plugin defining `po` can invoke `default`, the
+    /// host implementation of the operation, through [`PluginHandler::call_default`] while it
+    /// runs.
+    pub fn call_with_default(
+        &mut self,
+        po: &PluginOp,
+        params: &[PluginVal],
+        default: &mut DefaultFn<'_, CTP>,
+    ) -> Result<Vec<PluginVal>, Error> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("plugin_op", po = ?po).entered();
+        // SAFETY: `default` outlives its presence in `running`, as `enter` removes it before
+        // returning or unwinding.
+        let default = unsafe {
+            std::mem::transmute::<&mut DefaultFn<'_, CTP>, &mut DefaultFn<'static, CTP>>(default)
+        };
+        self.failed_plugin = None;
+        let define = RunningCall::Define {
+            po: *po,
+            default: RawMutPtr::new(default),
+        };
+        self.call_internal(po, params, define)
+    }
+
+    /// Runs the host implementation of the plugin operation being defined by a plugin with the
+    /// provided `inputs`, and returns its outputs. This enables a plugin to only handle some
+    /// cases of an operation and leave the other ones to the host.
+    ///
+    /// This is only possible when the innermost running plugin call is the definition of an
+    /// operation invoked by the host through [`PluginHandler::call_with_default`]. Otherwise,
+    /// e.g., from an anchor, a timer or a plugin control operation, this returns
+    /// [`Error::NoDefaultImplementation`].
+    pub fn call_default(&mut self, inputs: &[PluginVal]) -> Result<Vec<PluginVal>, Error> {
+        let (po, default) = match self.running.last() {
+            Some(RunningCall::Define { po, default }) => (*po, **default),
+            _ => return Err(Error::NoDefaultImplementation),
+        };
+        debug!("running the host implementation of {:?}", po);
+        // The host implementation cannot call itself back.
+        self.enter(RunningCall::Barrier, |ph| {
+            // SAFETY: the closure is alive as long as it is in `running`, see
+            // `call_with_default`, and it is not borrowed elsewhere while the barrier is in
+            // place.
+            unsafe { (*default)(ph, inputs) }
+        })
     }
 
     /// Invokes a plugin operation control operation.
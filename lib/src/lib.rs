@@ -5,6 +5,7 @@ use std::{
     io::Write,
     marker::PhantomPinned,
     ops::{Deref, DerefMut},
+    sync::Arc,
     time::Instant,
 };
 
@@ -13,27 +14,14 @@ use bytes::Buf;
 use common::PluginVal;
 use handler::PluginHandler;
 use plugin::Env;
+pub use pluginop_common::Permission;
 use pluginop_common::{quic, PluginOp};
 pub use pluginop_octets::{OctetsMutPtr, OctetsPtr};
 pub use pluginop_rawptr::{BytesMutPtr, CursorBytesPtr, RawMutPtr};
+use registry::PluginRegistry;
 use unix_time::Instant as UnixInstant;
 use wasmer::RuntimeError;
 
-/// Permission that can be granted to plugins.
-#[derive(Debug, Ord, PartialOrd, Eq, PartialEq)]
-pub enum Permission {
-    /// Permission to save output (should be always granted)
-    Output,
-    /// Permission to store opaque values (should be always granted)
-    Opaque,
-    /// Permission to access the Connection state
-    ConnectionAccess,
-    /// Permission to access the write byte buffer
-    WriteBuffer,
-    /// Permission to access the read byte buffer
-    ReadBuffer,
-}
-
 /// An enum storing the actual content of `Bytes` that are not directly exposed
 /// to plugins. Some side utilities are provided to let plugins access these
 /// values under some conditions.
@@ -166,6 +154,19 @@ impl<CTP: ConnectionToPlugin> TLSBeforeQUIC<CTP> {
             _pin: PhantomPinned,
         })
     }
+
+    /// Create a `TLSBeforeQUIC` structure whose plugins are compiled through the provided
+    /// [`PluginRegistry`].
+    pub fn new_with_registry(
+        exports_func: fn(&mut Store, &FunctionEnv<Env<CTP>>) -> Exports,
+        registry: Arc<PluginRegistry>,
+    ) -> Box<Self> {
+        // We return a `Box` to pin the structure.
+        Box::new(Self {
+            ph: PluginHandler::new_with_registry(exports_func, registry),
+            _pin: PhantomPinned,
+        })
+    }
 }
 
 /// Pluginization wrapper structure for a QUIC connection.
@@ -178,9 +179,13 @@ pub struct PluginizableConnection<CTP: ConnectionToPlugin> {
 }
 
 impl<CTP: ConnectionToPlugin> PluginizableConnection<CTP> {
-    fn new(exports_func: fn(&mut Store, &FunctionEnv<Env<CTP>>) -> Exports, conn: CTP) -> Self {
+    fn new(
+        exports_func: fn(&mut Store, &FunctionEnv<Env<CTP>>) -> Exports,
+        conn: CTP,
+        registry: Arc<PluginRegistry>,
+    ) -> Self {
         Self {
-            ph: PluginHandler::new(exports_func),
+            ph: PluginHandler::new_with_registry(exports_func, registry),
             conn,
             _pin: PhantomPinned,
         }
@@ -192,7 +197,22 @@ impl<CTP: ConnectionToPlugin> PluginizableConnection<CTP> {
         conn: CTP,
     ) -> Box<PluginizableConnection<CTP>> {
         // We return a `Box` to pin the structure.
-        Box::new(Self::new(exports_func, conn))
+        Box::new(Self::new(
+            exports_func,
+            conn,
+            Arc::new(PluginRegistry::new()),
+        ))
+    }
+
+    /// Create a new `PluginizableConnection` whose plugins are compiled through the provided,
+    /// endpoint-wide, [`PluginRegistry`].
+    pub fn new_pluginizable_connection_with_registry(
+        exports_func: fn(&mut Store, &FunctionEnv<Env<CTP>>) -> Exports,
+        conn: CTP,
+        registry: Arc<PluginRegistry>,
+    ) -> Box<PluginizableConnection<CTP>> {
+        // We return a `Box` to pin the structure.
+        Box::new(Self::new(exports_func, conn, registry))
     }
 
     /// Immutable reference to the inner connection structure.
@@ -271,6 +291,35 @@ pub enum Error {
 
     /// There is no plugin function for the requested `PluginOp`.
     NoPluginFunction,
+
+    /// There is no inserted plugin with the provided identifier.
+    UnknownPlugin(handler::PluginId),
+
+    /// The plugin exhausted its instruction budget before returning.
+    BudgetExceeded,
+
+    /// The plugin aborted after being refused to grow its memory beyond its limit.
+    MemoryLimitExceeded,
+
+    /// The plugin targets a version of the host API that is not the one of this library.
+    IncompatibleApiVersion(u32),
+
+    /// There is no host implementation to call, as the plugin is not defining an operation
+    /// invoked by the host.
+    NoDefaultImplementation,
+
+    /// The input at the provided index, given to the host implementation of an operation, is
+    /// missing or has an unexpected type.
+    InvalidDefaultInput(usize),
+
+    /// The host implementation of the operation returned an error.
+    DefaultFailed,
+
+    /// The outputs of the plugin do not match the ones expected by the host.
+    InvalidOutputs,
+
+    /// The trace of the plugin calls cannot be written or read.
+    TraceError(String),
 }
 
 /// A trait allowing converting an host-implementation type to a `T` one, possibly
@@ -321,6 +370,8 @@ impl_from_with_ph!(PluginVal, std::time::Duration);
 impl_from_with_ph!(PluginVal, UnixInstant);
 impl_from_with_ph!(PluginVal, std::net::SocketAddr);
 impl_from_with_ph!(PluginVal, quic::QVal);
+impl_from_with_ph!(PluginVal, String);
+impl_from_with_ph!(PluginVal, Vec<PluginVal>);
 
 impl_from_with_ph!(PluginVal, quic::Header);
 impl_from_with_ph!(PluginVal, quic::Frame);
@@ -328,12 +379,24 @@ impl_from_with_ph!(PluginVal, quic::RcvInfo);
 impl_from_with_ph!(PluginVal, quic::KPacketNumberSpace);
 impl_from_with_ph!(PluginVal, quic::PacketType);
 
+/// The bytes are kept by the `PluginHandler` and given to the plugins as a `PluginVal::Bytes`
+/// token. Host implementations willing to pass the bytes by value should provide a
+/// `PluginVal::ByteVec` instead.
 impl<CTP: ConnectionToPlugin> FromWithPH<Vec<u8>, CTP> for PluginVal {
     fn from_with_ph(value: Vec<u8>, ph: &mut PluginHandler<CTP>) -> Self {
         PluginVal::Bytes(ph.add_bytes_content(value.into()))
     }
 }
 
+impl<T, CTP: ConnectionToPlugin> FromWithPH<Option<T>, CTP> for PluginVal
+where
+    PluginVal: FromWithPH<T, CTP>,
+{
+    fn from_with_ph(value: Option<T>, ph: &mut PluginHandler<CTP>) -> Self {
+        PluginVal::Option(value.map(|v| Box::new(PluginVal::from_with_ph(v, ph))))
+    }
+}
+
 /// The reflexive trait of `FromWithPH`.
 pub trait IntoWithPH<T, CTP: ConnectionToPlugin>: Sized {
     fn into_with_ph(self, ph: &mut PluginHandler<CTP>) -> T;
@@ -385,10 +448,9 @@ impl<T: TryFrom<PluginVal>, CTP: ConnectionToPlugin> TryFromWithPH<PluginVal, CT
     type Error = <T as TryFrom<PluginVal>>::Error;
 
     fn try_from_with_ph(value: PluginVal, _: &PluginHandler<CTP>) -> Result<Self, Self::Error> {
-        match value {
-            PluginVal::Bytes(_) => todo!("try_from_with_ph bytes"),
-            _ => value.try_into(),
-        }
+        // `Bytes` tokens are not resolved to their content, so only the types accepting the
+        // token itself can be converted from them, the other ones return their conversion error.
+        value.try_into()
     }
 }
 
@@ -410,8 +472,14 @@ impl<CTP: ConnectionToPlugin, T: TryFromWithPH<PluginVal, CTP>> TryIntoWithPH<T,
 }
 
 pub mod api;
+pub mod clock;
+pub mod config;
 pub mod handler;
 pub mod plugin;
+pub mod record;
+pub mod registry;
+pub mod stats;
+mod tunables;
 
 // Reexport common, macro and octets.
 pub use pluginop_common as common;
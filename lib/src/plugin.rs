@@ -2,27 +2,39 @@
 
 use std::{
     cell::UnsafeCell,
-    collections::BTreeSet,
+    collections::{BTreeMap, BTreeSet},
     fmt::Debug,
     fs::File,
     io::Write,
     marker::PhantomPinned,
     ops::{Deref, DerefMut},
-    path::{Path, PathBuf},
+    panic::{self, AssertUnwindSafe},
+    path::Path,
     pin::Pin,
-    sync::{Arc, Weak},
-    time::Instant,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Weak,
+    },
+    time::{Duration, Instant},
 };
 
 use fnv::FnvHashMap;
-use log::{error, warn};
-use pluginop_common::{Anchor, PluginInputType, PluginOp, PluginOutputType, PluginVal};
+use log::{error, warn, LevelFilter};
+use pluginop_common::{
+    manifest::{PluginManifest, MANIFEST_SECTION},
+    Anchor, PluginInputType, PluginOp, PluginOutputType, PluginVal, API_VERSION,
+};
 use pluginop_rawptr::RawMutPtr;
-use wasmer::{FunctionEnv, Instance, Module, Store, TypedFunction};
+use wasmer::{sys::BaseTunables, FunctionEnv, Instance, NativeEngineExt, Store, TypedFunction};
+use wasmer_middlewares::metering::{get_remaining_points, set_remaining_points, MeteringPoints};
 
 use crate::{
     api::{get_imports_with, CTPError, ConnectionToPlugin},
-    handler::PluginHandler,
+    config::PluginConfig,
+    handler::{PluginHandler, PluginId},
+    registry::CompiledModule,
+    stats::CallStats,
+    tunables::LimitingTunables,
     Error, Permission,
 };
 
@@ -33,6 +45,7 @@ struct POCode {
     before: Option<PluginFunction>,
     define: Option<PluginFunction>,
     after: Option<PluginFunction>,
+    wrap: Option<PluginFunction>,
 }
 
 impl Debug for POCode {
@@ -41,6 +54,7 @@ impl Debug for POCode {
             .field("before", &self.before.is_some())
             .field("define", &self.define.is_some())
             .field("after", &self.after.is_some())
+            .field("wrap", &self.wrap.is_some())
             .finish()
     }
 }
@@ -52,6 +66,7 @@ impl POCode {
             Anchor::Before => self.before.as_ref(),
             Anchor::Define => self.define.as_ref(),
             Anchor::After => self.after.as_ref(),
+            Anchor::Wrap => self.wrap.as_ref(),
         }
     }
 }
@@ -86,17 +101,99 @@ pub(crate) struct TimerEvent {
     id: u64,
     /// The timer identifier.
     timer_id: u64,
+    /// The interval between two firings of a periodic timer.
+    period: Option<Duration>,
 }
 
 impl TimerEvent {
     pub fn new(at: Instant, id: u64, timer_id: u64) -> Self {
-        Self { at, id, timer_id }
+        Self {
+            at,
+            id,
+            timer_id,
+            period: None,
+        }
+    }
+
+    /// Create a timer firing every `period`, the first time at `at`.
+    pub fn periodic(at: Instant, period: Duration, id: u64, timer_id: u64) -> Self {
+        Self {
+            period: Some(period),
+            ..Self::new(at, id, timer_id)
+        }
+    }
+
+    /// The next firing of a periodic timer that fired while processing the timers up to `t`.
+    /// Missed periods are skipped.
+    fn next(&self, t: Instant) -> Option<TimerEvent> {
+        let period = self.period.filter(|p| !p.is_zero())?;
+        let missed = t.saturating_duration_since(self.at).as_nanos() / period.as_nanos();
+        let at = u32::try_from(missed + 1)
+            .ok()
+            .and_then(|n| self.at.checked_add(period.checked_mul(n)?))?;
+        Some(TimerEvent { at, ..*self })
+    }
+}
+
+/// The pending timers of a plugin, ordered by deadline.
+#[derive(Debug, Default)]
+pub(crate) struct TimerQueue {
+    /// The timers, indexed by their deadline and then their identifier.
+    by_deadline: BTreeMap<(Instant, u64), TimerEvent>,
+    /// The deadline of the timer with each identifier.
+    deadlines: FnvHashMap<u64, Instant>,
+}
+
+impl TimerQueue {
+    /// The number of pending timers.
+    fn len(&self) -> usize {
+        self.deadlines.len()
+    }
+
+    /// The deadline of the next timer to fire.
+    fn first(&self) -> Option<Instant> {
+        self.by_deadline.keys().next().map(|(at, _)| *at)
+    }
+
+    /// Insert a timer, replacing the one with the same identifier if any.
+    fn insert(&mut self, te: TimerEvent) {
+        if let Some(at) = self.deadlines.insert(te.id, te.at) {
+            self.by_deadline.remove(&(at, te.id));
+        }
+        self.by_deadline.insert((te.at, te.id), te);
+    }
+
+    /// Remove the first timer if it should fire at `t`.
+    fn pop_if_earlier_than(&mut self, t: Instant) -> Option<TimerEvent> {
+        let entry = self.by_deadline.first_entry().filter(|e| e.key().0 <= t)?;
+        let te = entry.remove();
+        self.deadlines.remove(&te.id);
+        Some(te)
+    }
+
+    /// Remove the timer with the provided identifier.
+    fn cancel(&mut self, id: u64) -> Option<TimerEvent> {
+        let at = self.deadlines.remove(&id)?;
+        self.by_deadline.remove(&(at, id))
+    }
+
+    fn clear(&mut self) {
+        self.by_deadline.clear();
+        self.deadlines.clear();
     }
 }
 
 /// A companion structure to the plugin execution environment, containing plugin-specific
 /// data allowing the bytecode to interact with the host implementation.
 pub struct Env<CTP: ConnectionToPlugin> {
+    /// The identifier of the plugin running this environment.
+    plugin_id: PluginId,
+    /// The name of the plugin, as stated by its manifest.
+    name: String,
+    /// The most verbose level of the plugin messages forwarded to the host logger.
+    log_level: LevelFilter,
+    /// The operation that the plugin is currently running, if any.
+    current_op: Option<PluginOp>,
     /// The underlying plugin handler holding the plugin running this environment.
     ph: RawMutPtr<PluginHandler<CTP>>,
     /// The (weak) reference to the instance of the plugin. The value is set when
@@ -111,7 +208,9 @@ pub struct Env<CTP: ConnectionToPlugin> {
     /// a dedicated API call.
     enabled: bool,
     /// The next timeout events to fire.
-    timer_events: Vec<TimerEvent>,
+    timer_events: TimerQueue,
+    /// The maximum number of pending timers.
+    max_timers: Option<usize>,
     /// Contains the inputs specific to the called operation.
     pub inputs: Pin<PluginValArray>,
     /// Enables a plugin to output more than one (serializable) value, as returning more than 1
@@ -119,19 +218,31 @@ pub struct Env<CTP: ConnectionToPlugin> {
     pub outputs: Pin<PluginValArray>,
     /// The files currently in use by the underlying plugin.
     files: Vec<UnsafeCell<File>>,
+    /// The serialized outputs of the last nested call, kept when they did not fit in the buffer
+    /// of the plugin.
+    call_result: Option<Vec<u8>>,
 }
 
-pub(crate) fn create_env<CTP: ConnectionToPlugin>(ph: RawMutPtr<PluginHandler<CTP>>) -> Env<CTP> {
+pub(crate) fn create_env<CTP: ConnectionToPlugin>(
+    plugin_id: PluginId,
+    ph: RawMutPtr<PluginHandler<CTP>>,
+) -> Env<CTP> {
     Env {
+        plugin_id,
+        name: format!("plugin-{plugin_id}"),
+        log_level: LevelFilter::Trace,
+        current_op: None,
         ph,
         instance: Weak::new(),
         permissions: BTreeSet::new(),
         initialized: false,
         enabled: false,
-        timer_events: Vec::new(),
+        timer_events: TimerQueue::default(),
+        max_timers: None,
         inputs: Pin::new(PluginValArray::default()),
         outputs: Pin::new(PluginValArray::default()),
         files: Vec::new(),
+        call_result: None,
     }
 }
 
@@ -141,6 +252,43 @@ impl<CTP: ConnectionToPlugin> Env<CTP> {
         self.inputs.clear();
         // And the outputs.
         self.outputs.clear();
+        // As well as the outputs of the nested calls of a previous call.
+        self.call_result = None;
+    }
+
+    /// Returns the serialized outputs of the last nested call that the plugin did not fetch.
+    pub(crate) fn call_result(&self) -> Option<&[u8]> {
+        self.call_result.as_deref()
+    }
+
+    /// Sets the serialized outputs of the last nested call, for the plugin to fetch them.
+    pub(crate) fn set_call_result(&mut self, result: Option<Vec<u8>>) {
+        self.call_result = result;
+    }
+
+    /// Returns the identifier of the plugin running this environment.
+    pub(crate) fn plugin_id(&self) -> PluginId {
+        self.plugin_id
+    }
+
+    /// Returns the name of the plugin running this environment.
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the most verbose level of the plugin messages forwarded to the host logger.
+    pub(crate) fn log_level(&self) -> LevelFilter {
+        self.log_level
+    }
+
+    /// Returns the operation that the plugin is currently running, if any.
+    pub(crate) fn current_op(&self) -> Option<PluginOp> {
+        self.current_op
+    }
+
+    /// Returns whether the plugin was granted the provided permission.
+    pub(crate) fn has_permission(&self, p: Permission) -> bool {
+        self.permissions.contains(&p)
     }
 
     pub(crate) fn get_instance(&self) -> Option<Arc<Pin<Box<Instance>>>> {
@@ -173,57 +321,59 @@ impl<CTP: ConnectionToPlugin> Env<CTP> {
             );
             return Err(CTPError::BadBytes);
         }
-        bc.write_into(len, mem)
+        let w = bc.write_into(len, mem)?;
+        if let Some(r) = ph.recorder_mut() {
+            r.bytes_read(tag as u64, &mem[..w]);
+        }
+        Ok(w)
     }
 
     pub(crate) fn put_bytes(&mut self, tag: usize, mem: &[u8]) -> Result<usize, CTPError> {
         let ph = self.get_ph().ok_or(CTPError::BadBytes)?;
         let bc = ph.get_mut_bytes_content(tag)?;
         // TODO: limit the length that plugins should be able to write.
-        bc.extend_from(mem)
+        let w = bc.extend_from(mem)?;
+        if let Some(r) = ph.recorder_mut() {
+            r.bytes_written(tag as u64, &mem[..w]);
+        }
+        Ok(w)
     }
 
     fn timeout(&self) -> Option<Instant> {
-        self.timer_events.first().map(|r| r.at)
+        self.timer_events.first()
     }
 
-    /// Insert a timer event.
-    pub(crate) fn insert_timer_event(&mut self, v: TimerEvent) {
-        // If there is an element where the id is already there, update it.
-        if let Some(te) = self.timer_events.iter_mut().find(|te| te.id == v.id) {
-            *te = v;
-        } else {
-            self.timer_events.push(v);
+    /// Insert a timer event. If there is already a timer with the same id, it is replaced.
+    pub(crate) fn insert_timer_event(&mut self, v: TimerEvent) -> Result<(), CTPError> {
+        let replaces = self.timer_events.deadlines.contains_key(&v.id);
+        if !replaces
+            && self
+                .max_timers
+                .is_some_and(|m| self.timer_events.len() >= m)
+        {
+            return Err(CTPError::TooManyTimers);
         }
-        // Always ensure the structure is sorted.
-        self.timer_events.sort();
+        self.timer_events.insert(v);
+        Ok(())
     }
 
-    /// Pop a fired timer event, if any.
+    /// Pop a fired timer event, if any. A periodic timer is re-armed for its next firing.
     pub(crate) fn pop_timer_event_if_earlier_than(&mut self, t: Instant) -> Option<TimerEvent> {
-        if let Some(te) = self.timer_events.first() {
-            if te.at <= t {
-                // This is safe since we just checked that such an element exists.
-                // Note that the Vec is still sorted.
-                return Some(self.timer_events.remove(0));
-            }
+        let te = self.timer_events.pop_if_earlier_than(t)?;
+        if let Some(next) = te.next(t) {
+            self.timer_events.insert(next);
         }
-        None
+        Some(te)
     }
 
     /// Cancel a timer.
     pub(crate) fn cancel_timer_event(&mut self, id: u64) -> Option<TimerEvent> {
-        let mut cancelled = None;
-        // This works, because we can only have a single id inside the timer events.
-        self.timer_events.retain(|te| {
-            if te.id == id {
-                cancelled = Some(*te);
-                false
-            } else {
-                true
-            }
-        });
-        cancelled
+        self.timer_events.cancel(id)
+    }
+
+    /// Cancel all the pending timers.
+    pub(crate) fn clear_timer_events(&mut self) {
+        self.timer_events.clear();
     }
 
     pub(crate) fn create_file_with_path(&mut self, path: &Path) -> Result<i64, CTPError> {
@@ -264,6 +414,15 @@ impl<CTP: ConnectionToPlugin> Env<CTP> {
         }
     }
 
+    /// Close all the files opened by the plugin.
+    pub(crate) fn close_files(&mut self) {
+        for ucf in self.files.drain(..) {
+            if let Err(e) = ucf.into_inner().sync_all() {
+                warn!("plugin: cannot sync file before closing: {:?}", e);
+            }
+        }
+    }
+
     /// Fully enable the plugin operations.
     pub(crate) fn enable(&mut self) {
         self.enabled = true;
@@ -313,6 +472,13 @@ where
         }
     }
 
+    fn iter(&self) -> Box<dyn Iterator<Item = (&K, &V)> + '_> {
+        match self {
+            KeyValueCollectionInner::Vec(v) => Box::new(v.iter().map(|(k, v)| (k, v))),
+            KeyValueCollectionInner::HashMap(hm) => Box::new(hm.iter()),
+        }
+    }
+
     fn insert(&mut self, k: K, v: V) {
         // FIXME: in Vec mode, we should ideally check that the element is not already there.
         match self {
@@ -358,11 +524,17 @@ where
     fn get_mut(&mut self, k: &K) -> Option<&mut V> {
         self.inner.get_mut(k)
     }
+
+    fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.inner.iter()
+    }
 }
 
 /// Structure holding the state of an inserted plugin. Because all the useful state is hold in the
 /// `Env` structure, this structure does not need to be public anymore.
 pub(crate) struct Plugin<CTP: ConnectionToPlugin> {
+    /// The identifier of the plugin within its handler.
+    id: PluginId,
     /// The actual WASM instance.
     instance: Arc<Pin<Box<Instance>>>,
     /// The store in which the plugin operates.
@@ -371,66 +543,115 @@ pub(crate) struct Plugin<CTP: ConnectionToPlugin> {
     env: FunctionEnv<Env<CTP>>,
     /// A collection holding the plugin functions contained in the instance.
     pocodes: Pin<Box<KeyValueCollection<PluginOp, POCode>>>,
-    /// Cache indicating whether the plugin has the anchor or not (Pre, Replace, Post).
-    has_anchor: [bool; 3],
+    /// Cache indicating whether the plugin has the anchor or not (Pre, Replace, Post, Wrap).
+    has_anchor: [bool; 4],
     /// Opaque value provided as argument to the plugin.
     plugin_state: u32,
+    /// The number of calls to the plugin currently running, as a plugin can be re-entered
+    /// through host functions.
+    running_calls: u32,
+    /// Set when the plugin got refused to grow its memory.
+    grow_denied: Arc<AtomicBool>,
+    /// The manifest embedded in the plugin bytecode, if any.
+    manifest: Option<PluginManifest>,
+    /// The priority of the plugin over the other ones defining the same operations.
+    priority: i32,
+    /// Set once the plugin failed under the quarantine policy. It then no longer provides any
+    /// operation.
+    quarantined: bool,
+    /// Statistics about the calls to the plugin, if collected.
+    stats: Option<FnvHashMap<(PluginOp, Anchor), CallStats>>,
 }
 
-impl<CTP: ConnectionToPlugin> Plugin<CTP> {
-    /// Creates a new `Plugin` instance.
-    pub fn new(plugin_fname: &PathBuf, ph: &PluginHandler<CTP>) -> Result<Self, Error> {
-        match std::fs::read(plugin_fname) {
-            Ok(wasm) => {
-                let ph_ptr = ph as *const _ as *mut _;
-                let mut store = Store::new(ph.get_cloned_engine());
-                let env = FunctionEnv::new(&mut store, create_env(RawMutPtr::new(ph_ptr)));
-                let exports = (ph.get_export_func())(&mut store, &env);
-                let imports = get_imports_with(exports, &mut store, &env);
-                let module = match Module::from_binary(&store, &wasm) {
-                    Ok(m) => m,
-                    Err(e) => {
-                        error!("failed WASM compilation: {}", e);
-                        return Err(Error::PluginLoadingError(e.to_string()));
-                    }
-                };
-
-                match Instance::new(&mut store, &module, &imports) {
-                    Ok(instance) => {
-                        let mut plugin_state = [0u8; 4];
+/// Parse the manifest embedded in the module, if any, and check that the plugin targets the
+/// API of this host.
+fn read_manifest(compiled: &CompiledModule) -> Result<Option<PluginManifest>, Error> {
+    let section = match compiled.module().custom_sections(MANIFEST_SECTION).next() {
+        Some(s) => s,
+        None => return Ok(None),
+    };
+    let manifest = PluginManifest::parse(&section).map_err(|e| {
+        error!("Invalid plugin manifest: {}", e);
+        Error::PluginLoadingError(e.to_string())
+    })?;
+    if manifest.api_version != API_VERSION {
+        error!(
+            "Plugin {} targets API version {}, but host offers {}",
+            manifest.name, manifest.api_version, API_VERSION
+        );
+        return Err(Error::IncompatibleApiVersion(manifest.api_version));
+    }
+    Ok(Some(manifest))
+}
 
-                        if let Err(e) = getrandom::getrandom(&mut plugin_state) {
-                            warn!("cannot generate random plugin state: {}", e);
-                        }
+impl<CTP: ConnectionToPlugin> Plugin<CTP> {
+    /// Creates a new `Plugin` instance from an already compiled `module`.
+    pub fn new(
+        id: PluginId,
+        compiled: &CompiledModule,
+        config: &PluginConfig,
+        ph: &PluginHandler<CTP>,
+    ) -> Result<Self, Error> {
+        let manifest = read_manifest(compiled)?;
+        let ph_ptr = ph as *const _ as *mut _;
+        let grow_denied = Arc::new(AtomicBool::new(false));
+        let mut engine = compiled.engine().clone();
+        let base = BaseTunables::for_target(engine.target());
+        engine.set_tunables(LimitingTunables::new(
+            base,
+            config.limits,
+            grow_denied.clone(),
+        ));
+        let mut store = Store::new(engine);
+        let env = FunctionEnv::new(&mut store, create_env(id, RawMutPtr::new(ph_ptr)));
+        let exports = (ph.get_export_func())(&mut store, &env);
+        let imports = get_imports_with(exports, &mut store, &env);
+
+        match Instance::new(&mut store, compiled.module(), &imports) {
+            Ok(instance) => {
+                let mut plugin_state = [0u8; 4];
+
+                if let Err(e) = getrandom::getrandom(&mut plugin_state) {
+                    warn!("cannot generate random plugin state: {}", e);
+                }
 
-                        // XXX We could update the permissions later.
-                        let permissions = &mut env.as_mut(&mut store).permissions;
-                        permissions.insert(Permission::Output);
-                        permissions.insert(Permission::Opaque);
-                        permissions.insert(Permission::ConnectionAccess);
-                        permissions.insert(Permission::WriteBuffer);
-                        permissions.insert(Permission::ReadBuffer);
-
-                        let (pocodes, has_anchor) =
-                            Plugin::<CTP>::get_pocodes(&instance, &mut store);
-
-                        Ok(Plugin {
-                            instance: Arc::new(Box::pin(instance)),
-                            store,
-                            env,
-                            pocodes: Box::pin(pocodes),
-                            has_anchor,
-                            plugin_state: u32::from_be_bytes(plugin_state),
-                        })
-                    }
-                    Err(e) => {
-                        error!("Cannot instantiate plugin: {}", e);
-                        Err(Error::PluginLoadingError(e.to_string()))
-                    }
+                // A plugin never gets more than what it requested.
+                let mut permissions = config.permissions.clone();
+                if let Some(requested) = manifest.as_ref().and_then(|m| m.permissions.as_ref()) {
+                    permissions.retain(|p| requested.contains(p));
+                }
+                let env_mut = env.as_mut(&mut store);
+                env_mut.permissions = permissions;
+                env_mut.log_level = config.log_level;
+                env_mut.max_timers = config.limits.timers;
+                if let Some(m) = manifest.as_ref() {
+                    env_mut.name = m.name.clone();
                 }
+
+                let (pocodes, has_anchor) = Plugin::<CTP>::get_pocodes(&instance, &mut store)?;
+                let priority = config
+                    .priority
+                    .or_else(|| manifest.as_ref().and_then(|m| m.priority))
+                    .unwrap_or(0);
+
+                Ok(Plugin {
+                    id,
+                    instance: Arc::new(Box::pin(instance)),
+                    store,
+                    env,
+                    pocodes: Box::pin(pocodes),
+                    has_anchor,
+                    plugin_state: u32::from_be_bytes(plugin_state),
+                    running_calls: 0,
+                    grow_denied,
+                    manifest,
+                    priority,
+                    quarantined: false,
+                    stats: None,
+                })
             }
             Err(e) => {
-                error!("Cannot read plugin: {}", e);
+                error!("Cannot instantiate plugin: {}", e);
                 Err(Error::PluginLoadingError(e.to_string()))
             }
         }
@@ -439,22 +660,26 @@ impl<CTP: ConnectionToPlugin> Plugin<CTP> {
     fn get_pocodes(
         instance: &Instance,
         store: &mut Store,
-    ) -> (KeyValueCollection<PluginOp, POCode>, [bool; 3]) {
+    ) -> Result<(KeyValueCollection<PluginOp, POCode>, [bool; 4]), Error> {
         let mut pocodes: KeyValueCollection<PluginOp, POCode> =
             KeyValueCollection::new(KV_VEC_MAX_ELEMS);
-        let mut has_anchor = [false; 3];
+        let mut has_anchor = [false; 4];
 
         for (name, _) in instance.exports.iter() {
             if let Ok(func) = instance.exports.get_typed_function(store, name) {
                 let func = func.clone();
 
-                let (po, a) = PluginOp::from_name(name);
+                let (po, a) = PluginOp::try_from_name(name).map_err(|e| {
+                    error!("Cannot load plugin: {}", e);
+                    Error::PluginLoadingError(e.to_string())
+                })?;
                 has_anchor[a.index()] = true;
                 match pocodes.get_mut(&po) {
                     Some(poc) => match a {
                         Anchor::Before => poc.before = Some(func),
                         Anchor::Define => poc.define = Some(func),
                         Anchor::After => poc.after = Some(func),
+                        Anchor::Wrap => poc.wrap = Some(func),
                     },
                     None => {
                         let mut poc = POCode::default();
@@ -462,6 +687,7 @@ impl<CTP: ConnectionToPlugin> Plugin<CTP> {
                             Anchor::Before => poc.before = Some(func),
                             Anchor::Define => poc.define = Some(func),
                             Anchor::After => poc.after = Some(func),
+                            Anchor::Wrap => poc.wrap = Some(func),
                         }
                         pocodes.insert(po, poc);
                     }
@@ -469,7 +695,76 @@ impl<CTP: ConnectionToPlugin> Plugin<CTP> {
             }
         }
 
-        (pocodes, has_anchor)
+        Ok((pocodes, has_anchor))
+    }
+
+    /// Returns the identifier of this plugin.
+    pub(crate) fn id(&self) -> PluginId {
+        self.id
+    }
+
+    /// Returns the manifest of the plugin, if it embeds one.
+    pub(crate) fn manifest(&self) -> Option<&PluginManifest> {
+        self.manifest.as_ref()
+    }
+
+    /// Stops the plugin from serving any operation or timer.
+    pub(crate) fn quarantine(&mut self) {
+        self.quarantined = true;
+    }
+
+    /// Returns whether the plugin was quarantined.
+    pub(crate) fn is_quarantined(&self) -> bool {
+        self.quarantined
+    }
+
+    /// Starts or stops collecting statistics about the calls to the plugin. Stopping drops the
+    /// statistics collected so far.
+    pub(crate) fn set_stats_enabled(&mut self, enabled: bool) {
+        match (enabled, self.stats.is_some()) {
+            (true, false) => self.stats = Some(FnvHashMap::default()),
+            (false, true) => self.stats = None,
+            _ => {}
+        }
+    }
+
+    /// Returns the statistics collected about the calls to the plugin, if enabled.
+    pub(crate) fn stats(&self) -> Option<&FnvHashMap<(PluginOp, Anchor), CallStats>> {
+        self.stats.as_ref()
+    }
+
+    /// Resets the statistics collected about the calls to the plugin, if enabled.
+    pub(crate) fn reset_stats(&mut self) {
+        if let Some(stats) = self.stats.as_mut() {
+            stats.clear();
+        }
+    }
+
+    /// Sets the most verbose level of the plugin messages forwarded to the host logger.
+    pub(crate) fn set_log_level(&mut self, level: LevelFilter) {
+        self.env.as_mut(&mut self.store).log_level = level;
+    }
+
+    /// Returns the priority of the plugin.
+    pub(crate) fn priority(&self) -> i32 {
+        self.priority
+    }
+
+    /// Returns the operations for which the plugin has a `Define` bytecode, whether they are
+    /// enabled or not.
+    pub(crate) fn defined_ops(&self) -> impl Iterator<Item = &PluginOp> {
+        self.pocodes
+            .iter()
+            .filter(|(_, poc)| poc.define.is_some())
+            .map(|(po, _)| po)
+    }
+
+    /// Releases the resources held by the plugin on the host side, i.e., its pending timers
+    /// and its open files. To be called before removing the plugin.
+    pub(crate) fn teardown(&mut self) {
+        let env_mut = self.env.as_mut(&mut self.store);
+        env_mut.clear_timer_events();
+        env_mut.close_files();
     }
 
     /// Returns the first timer event related to this plugin.
@@ -477,29 +772,43 @@ impl<CTP: ConnectionToPlugin> Plugin<CTP> {
         self.env.as_ref(&self.store).timeout()
     }
 
-    /// Process the timeout events related to this plugin.
-    pub(crate) fn on_timeout(&mut self, t: Instant) -> Result<(), Error> {
+    /// Process the timeout events related to this plugin. A failing callback does not prevent
+    /// the other fired timers from being processed, and the first failure is returned.
+    pub(crate) fn on_timeout(&mut self, t: Instant, budget: Option<u64>) -> Result<(), Error> {
+        let mut res = Ok(());
         while let Some(te) = self
             .env
             .as_mut(&mut self.store)
             .pop_timer_event_if_earlier_than(t)
         {
-            self.call(&PluginOp::OnPluginTimeout(te.timer_id), Anchor::Define, &[])?;
+            #[cfg(feature = "tracing")]
+            tracing::debug!(
+                plugin = self.id,
+                timer_id = te.timer_id,
+                id = te.id,
+                "plugin timer fired"
+            );
+            let po = PluginOp::OnPluginTimeout(te.timer_id);
+            if let Err(e) = self.call(&po, Anchor::Define, &[], budget) {
+                error!("plugin {} failed on {:?}: {:?}", self.id, po, e);
+                res = res.and(Err(e));
+            }
         }
 
-        Ok(())
+        res
     }
 
     /// Returns an array indicating whether there is any provided bytecode
     /// serving each anchor.
-    pub(crate) fn has_anchor(&self) -> [bool; 3] {
+    pub(crate) fn has_anchor(&self) -> [bool; 4] {
         self.has_anchor
     }
 
     /// Returns whether this plugin provides behavior for the requested
     /// `PluginOp` and `Anchor`.
     pub(crate) fn provides(&self, po: &PluginOp, anchor: Anchor) -> bool {
-        self.has_anchor[anchor.index()]
+        !self.quarantined
+            && self.has_anchor[anchor.index()]
             && (self.env.as_ref(&self.store).enabled || po.always_enabled())
             && self
                 .pocodes
@@ -509,7 +818,7 @@ impl<CTP: ConnectionToPlugin> Plugin<CTP> {
     }
 
     /// Initializes the plugin.
-    pub(crate) fn initialize(&mut self) -> Result<(), Error> {
+    pub(crate) fn initialize(&mut self, budget: Option<u64>) -> Result<(), Error> {
         let env_mut = self.env.as_mut(&mut self.store);
         env_mut.initialized = true;
 
@@ -517,7 +826,7 @@ impl<CTP: ConnectionToPlugin> Plugin<CTP> {
         env_mut.instance = Arc::<Pin<Box<Instance>>>::downgrade(&self.instance);
 
         // And call a potential `init` method provided by the plugin.
-        match self.call(&PluginOp::Init, Anchor::Define, &[]) {
+        match self.call(&PluginOp::Init, Anchor::Define, &[], budget) {
             Ok(_) | Err(Error::NoPluginFunction) => Ok(()),
             Err(e) => Err(e),
         }
@@ -534,6 +843,30 @@ impl<CTP: ConnectionToPlugin> Plugin<CTP> {
         po: &PluginOp,
         anchor: Anchor,
         params: &[PluginVal],
+        budget: Option<u64>,
+    ) -> Result<Vec<PluginVal>, Error> {
+        // A nested call must leave the inputs and outputs of the running call untouched.
+        let outer = (self.running_calls > 0).then(|| {
+            let env = self.env.as_ref(&self.store);
+            (env.inputs.to_vec(), env.outputs.to_vec())
+        });
+        let res = self.call_internal(po, anchor, params, budget);
+        if let Some((inputs, outputs)) = outer {
+            let env_mut = self.env.as_mut(&mut self.store);
+            env_mut.inputs.clear();
+            env_mut.inputs.extend(inputs);
+            env_mut.outputs.clear();
+            env_mut.outputs.extend(outputs);
+        }
+        res
+    }
+
+    fn call_internal(
+        &mut self,
+        po: &PluginOp,
+        anchor: Anchor,
+        params: &[PluginVal],
+        budget: Option<u64>,
     ) -> Result<Vec<PluginVal>, Error> {
         let env_mut = self.env.as_mut(&mut self.store);
         // Before launching any call, we should sanitize the running `env`.
@@ -543,25 +876,98 @@ impl<CTP: ConnectionToPlugin> Plugin<CTP> {
             return Err(Error::Disabled);
         }
 
-        for p in params {
-            env_mut.inputs.push(*p);
-        }
+        env_mut.inputs.extend_from_slice(params);
 
-        let func = match self.pocodes.get(po) {
-            Some(poc) => match anchor {
-                Anchor::Before => poc.before.as_ref(),
-                Anchor::Define => poc.define.as_ref(),
-                Anchor::After => poc.after.as_ref(),
-            },
-            None => None,
-        };
+        let func = self.pocodes.get(po).and_then(|poc| poc.get(anchor));
 
         let func = func.ok_or(Error::NoPluginFunction)?;
+        // Nested calls share the budget of the outermost one.
+        if self.running_calls == 0 {
+            self.grow_denied.store(false, Ordering::Relaxed);
+            set_remaining_points(&mut self.store, &self.instance, budget.unwrap_or(u64::MAX));
+        }
         // debug!("Calling PO with param {:?}", params);
-        match func.call(&mut self.store, self.plugin_state) {
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!(
+            "plugin_call",
+            plugin = self.id,
+            po = ?po,
+            anchor = ?anchor,
+            duration_ns = tracing::field::Empty,
+            result = tracing::field::Empty,
+        )
+        .entered();
+        if let Some(r) = self
+            .env
+            .as_mut(&mut self.store)
+            .get_ph()
+            .and_then(|ph| ph.recorder_mut())
+        {
+            r.begin(self.id, *po, anchor, params);
+        }
+        let start = (self.stats.is_some() || cfg!(feature = "tracing")).then(Instant::now);
+        self.running_calls += 1;
+        let outer_op = self.env.as_mut(&mut self.store).current_op.replace(*po);
+        // The host may panic while serving the plugin, e.g., in a host implementation. The state
+        // of the call is restored before unwinding further, so that the plugin stays usable.
+        let res = panic::catch_unwind(AssertUnwindSafe(|| {
+            func.call(&mut self.store, self.plugin_state)
+        }));
+        self.env.as_mut(&mut self.store).current_op = outer_op;
+        self.running_calls -= 1;
+        let res = match res {
+            Ok(res) => res,
+            Err(payload) => {
+                if let Some(r) = self
+                    .env
+                    .as_mut(&mut self.store)
+                    .get_ph()
+                    .and_then(|ph| ph.recorder_mut())
+                {
+                    let err = Error::InternalError("the host panicked".to_string());
+                    if let Err(e) = r.end(&Err(err)) {
+                        error!("cannot record plugin call: {:?}", e);
+                    }
+                }
+                panic::resume_unwind(payload)
+            }
+        };
+        let res = match res {
             Ok(0) => Ok((*self.env.as_ref(&self.store).outputs).clone()),
             Ok(err) => Err(Error::OperationError(err)),
-            Err(re) => Err(Error::RuntimeError(re)),
+            Err(re) => match get_remaining_points(&mut self.store, &self.instance) {
+                MeteringPoints::Exhausted => Err(Error::BudgetExceeded),
+                // A refused growth usually makes the plugin abort.
+                MeteringPoints::Remaining(_) if self.grow_denied.load(Ordering::Relaxed) => {
+                    Err(Error::MemoryLimitExceeded)
+                }
+                MeteringPoints::Remaining(_) => Err(Error::RuntimeError(re)),
+            },
+        };
+        let elapsed = start.map(|s| s.elapsed());
+        if let Some(r) = self
+            .env
+            .as_mut(&mut self.store)
+            .get_ph()
+            .and_then(|ph| ph.recorder_mut())
+        {
+            if let Err(e) = r.end(&res) {
+                error!("cannot record plugin call: {:?}", e);
+            }
+        }
+        #[cfg(feature = "tracing")]
+        {
+            if let Some(elapsed) = elapsed {
+                span.record("duration_ns", elapsed.as_nanos() as u64);
+            }
+            span.record("result", tracing::field::debug(&res.as_ref().map(|_| ())));
+        }
+        if let (Some(stats), Some(elapsed)) = (self.stats.as_mut(), elapsed) {
+            stats
+                .entry((*po, anchor))
+                .or_default()
+                .record(elapsed, &res);
         }
+        res
     }
 }
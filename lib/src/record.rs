@@ -0,0 +1,252 @@
+//! Recording of the plugin calls, to replay them later on.
+//!
+//! A trace is a sequence of [`CallRecord`]s, each serialized with `postcard` and prefixed by its
+//! length as a little-endian `u32`. Records are written when their call completes, so a nested
+//! call appears before the call that triggered it.
+
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufWriter, Read, Write},
+    path::Path,
+};
+
+use pluginop_common::{
+    quic::{ConnectionField, RecoveryField},
+    Anchor, ApiErrorCode, PluginOp, PluginVal,
+};
+use serde::{Deserialize, Serialize};
+use unix_time::Instant as UnixInstant;
+
+use crate::{handler::PluginId, Error};
+
+/// How a recorded call failed.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RecordedError {
+    /// The plugin returned [`Error::OperationError`] with this code.
+    OperationError(i64),
+    /// The call failed for another reason, described by the debug output of the error.
+    Failure(String),
+}
+
+impl From<&Error> for RecordedError {
+    fn from(e: &Error) -> Self {
+        match e {
+            Error::OperationError(code) => RecordedError::OperationError(*code),
+            e => RecordedError::Failure(format!("{e:?}")),
+        }
+    }
+}
+
+/// A call made by a plugin through the host.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NestedCall {
+    /// The plugin control operation with the provided identifier.
+    Poctl(u64),
+    /// The host implementation of the operation defined by the plugin.
+    Default,
+}
+
+/// Everything a plugin saw and produced during a call.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CallRecord {
+    /// The plugin called.
+    pub plugin: PluginId,
+    /// The operation invoked.
+    pub po: PluginOp,
+    /// The anchor of the operation run by the plugin.
+    pub anchor: Anchor,
+    /// The number of recorded calls that were running when this one started. Calls with a
+    /// depth of 0 are the ones made by the host.
+    pub depth: u32,
+    /// The inputs given to the plugin.
+    pub inputs: Vec<PluginVal>,
+    /// The bytes read by the plugin from each `Bytes` tag, in read order.
+    pub bytes: Vec<(u64, Vec<u8>)>,
+    /// The bytes written by the plugin to each `Bytes` tag, in write order.
+    pub bytes_written: Vec<(u64, Vec<u8>)>,
+    /// The connection fields read by the plugin, with their serialized values, in read order.
+    pub connection_reads: Vec<(ConnectionField, Vec<u8>)>,
+    /// The recovery fields read by the plugin, with their serialized values, in read order.
+    pub recovery_reads: Vec<(RecoveryField, Vec<u8>)>,
+    /// The times given to the plugin, in read order.
+    pub clock_reads: Vec<UnixInstant>,
+    /// The calls made by the plugin through the host, with the outputs or the error code it
+    /// received, in call order.
+    pub nested_calls: Vec<(NestedCall, Result<Vec<PluginVal>, ApiErrorCode>)>,
+    /// The outputs of the call, or how it failed.
+    pub outputs: Result<Vec<PluginVal>, RecordedError>,
+}
+
+/// Writes the records of the plugin calls to a trace file.
+pub(crate) struct Recorder {
+    writer: BufWriter<File>,
+    /// The calls currently running, the innermost one being the last.
+    running: Vec<CallRecord>,
+}
+
+impl Recorder {
+    /// Create a recorder writing a new trace at `path`.
+    pub(crate) fn create(path: &Path) -> Result<Self, Error> {
+        let file = File::create(path).map_err(|e| Error::TraceError(e.to_string()))?;
+        Ok(Self {
+            writer: BufWriter::new(file),
+            running: Vec::new(),
+        })
+    }
+
+    /// Start recording a call.
+    pub(crate) fn begin(
+        &mut self,
+        plugin: PluginId,
+        po: PluginOp,
+        anchor: Anchor,
+        inputs: &[PluginVal],
+    ) {
+        self.running.push(CallRecord {
+            plugin,
+            po,
+            anchor,
+            depth: self.running.len() as u32,
+            inputs: inputs.to_vec(),
+            bytes: Vec::new(),
+            bytes_written: Vec::new(),
+            connection_reads: Vec::new(),
+            recovery_reads: Vec::new(),
+            clock_reads: Vec::new(),
+            nested_calls: Vec::new(),
+            outputs: Ok(Vec::new()),
+        });
+    }
+
+    /// Record that the running call read `data` from the `Bytes` with the provided `tag`.
+    pub(crate) fn bytes_read(&mut self, tag: u64, data: &[u8]) {
+        if let Some(r) = self.running.last_mut() {
+            match r.bytes.iter_mut().find(|(t, _)| *t == tag) {
+                Some((_, d)) => d.extend_from_slice(data),
+                None => r.bytes.push((tag, data.to_vec())),
+            }
+        }
+    }
+
+    /// Record that the running call wrote `data` to the `Bytes` with the provided `tag`.
+    pub(crate) fn bytes_written(&mut self, tag: u64, data: &[u8]) {
+        if let Some(r) = self.running.last_mut() {
+            match r.bytes_written.iter_mut().find(|(t, _)| *t == tag) {
+                Some((_, d)) => d.extend_from_slice(data),
+                None => r.bytes_written.push((tag, data.to_vec())),
+            }
+        }
+    }
+
+    /// Record that the running call read the connection `field`, serialized as `value`.
+    pub(crate) fn connection_read(&mut self, field: ConnectionField, value: &[u8]) {
+        if let Some(r) = self.running.last_mut() {
+            r.connection_reads.push((field, value.to_vec()));
+        }
+    }
+
+    /// Record that the running call read the recovery `field`, serialized as `value`.
+    pub(crate) fn recovery_read(&mut self, field: RecoveryField, value: &[u8]) {
+        if let Some(r) = self.running.last_mut() {
+            r.recovery_reads.push((field, value.to_vec()));
+        }
+    }
+
+    /// Record that the running call was given the time `t`.
+    pub(crate) fn clock_read(&mut self, t: UnixInstant) {
+        if let Some(r) = self.running.last_mut() {
+            r.clock_reads.push(t);
+        }
+    }
+
+    /// Record that the running call made the nested `call`, and received `res`.
+    pub(crate) fn nested_call(
+        &mut self,
+        call: NestedCall,
+        res: &Result<Vec<PluginVal>, ApiErrorCode>,
+    ) {
+        if let Some(r) = self.running.last_mut() {
+            r.nested_calls.push((call, res.clone()));
+        }
+    }
+
+    /// Complete the record of the running call with its result, and write it to the trace.
+    pub(crate) fn end(&mut self, res: &Result<Vec<PluginVal>, Error>) -> Result<(), Error> {
+        let mut record = match self.running.pop() {
+            Some(r) => r,
+            None => return Ok(()),
+        };
+        record.outputs = res.as_ref().cloned().map_err(RecordedError::from);
+        let bytes = postcard::to_stdvec(&record).map_err(|e| Error::TraceError(e.to_string()))?;
+        self.writer
+            .write_all(&(bytes.len() as u32).to_le_bytes())
+            .and_then(|_| self.writer.write_all(&bytes))
+            .map_err(|e| Error::TraceError(e.to_string()))
+    }
+
+    /// Write the buffered records to the trace.
+    pub(crate) fn flush(&mut self) -> Result<(), Error> {
+        self.writer
+            .flush()
+            .map_err(|e| Error::TraceError(e.to_string()))
+    }
+}
+
+/// The answers of the host served to a plugin replaying a recorded call, instead of the live
+/// ones. See [`PluginHandler::set_replayed_answers`](crate::handler::PluginHandler::set_replayed_answers).
+#[derive(Clone, Debug, Default)]
+pub struct ReplayedAnswers {
+    clock_reads: VecDeque<UnixInstant>,
+    nested_calls: VecDeque<(NestedCall, Result<Vec<PluginVal>, ApiErrorCode>)>,
+}
+
+impl ReplayedAnswers {
+    /// The answers received by the plugin during the recorded call.
+    pub fn from_record(record: &CallRecord) -> Self {
+        Self {
+            clock_reads: record.clock_reads.iter().copied().collect(),
+            nested_calls: record.nested_calls.iter().cloned().collect(),
+        }
+    }
+
+    /// The next time to give to the plugin, if any is left.
+    pub(crate) fn next_clock_read(&mut self) -> Option<UnixInstant> {
+        self.clock_reads.pop_front()
+    }
+
+    /// The answer to the next nested call of the plugin. The plugin gets
+    /// [`ApiErrorCode::NotFound`] if it does not make the recorded call.
+    pub(crate) fn next_nested_call(
+        &mut self,
+        call: NestedCall,
+    ) -> Result<Vec<PluginVal>, ApiErrorCode> {
+        match self.nested_calls.pop_front() {
+            Some((c, res)) if c == call => res,
+            _ => Err(ApiErrorCode::NotFound),
+        }
+    }
+}
+
+/// Read all the records of the trace at `path`.
+pub fn read_trace(path: &Path) -> Result<Vec<CallRecord>, Error> {
+    let mut content = Vec::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_end(&mut content))
+        .map_err(|e| Error::TraceError(e.to_string()))?;
+    let mut records = Vec::new();
+    let mut rest = content.as_slice();
+    while !rest.is_empty() {
+        let truncated = || Error::TraceError("truncated record".to_string());
+        let (len, tail) = rest.split_first_chunk::<4>().ok_or_else(truncated)?;
+        let len = u32::from_le_bytes(*len) as usize;
+        if tail.len() < len {
+            return Err(truncated());
+        }
+        let record =
+            postcard::from_bytes(&tail[..len]).map_err(|e| Error::TraceError(e.to_string()))?;
+        records.push(record);
+        rest = &tail[len..];
+    }
+    Ok(records)
+}
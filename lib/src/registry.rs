@@ -0,0 +1,323 @@
+//! Compiled plugin modules shared between the connections of an endpoint.
+
+use std::{
+    sync::{Arc, Mutex},
+    thread::JoinHandle,
+};
+
+use fnv::FnvHashMap;
+use log::{debug, error};
+use sha2::{Digest, Sha256};
+use wasmer::{wasmparser::Operator, CompilerConfig, Engine, Module};
+#[cfg(feature = "cranelift")]
+use wasmer_compiler_cranelift::Cranelift;
+#[cfg(feature = "llvm")]
+use wasmer_compiler_llvm::LLVM;
+use wasmer_compiler_singlepass::Singlepass;
+use wasmer_middlewares::Metering;
+
+use crate::Error;
+
+/// The compilers that can turn plugin bytecodes into native code.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Compiler {
+    /// Very fast compilation, but the generated code is not optimized.
+    #[default]
+    Singlepass,
+    /// Slower compilation, but produces much faster code.
+    #[cfg(feature = "cranelift")]
+    Cranelift,
+    /// Slowest compilation, but produces the fastest code.
+    #[cfg(feature = "llvm")]
+    Llvm,
+}
+
+/// The cost of each WebAssembly instruction, as accounted by the metering middleware.
+fn instruction_cost(_: &Operator) -> u64 {
+    1
+}
+
+fn engine_with_metering<C: CompilerConfig + Into<Engine>>(mut compiler: C) -> Engine {
+    // The metering middleware cannot be shared between modules, so we need an engine per
+    // module. The budget is then set before each call.
+    compiler.push_middleware(Arc::new(Metering::new(u64::MAX, instruction_cost)));
+    compiler.into()
+}
+
+/// Get an engine to compile a plugin with the provided compiler.
+fn create_engine(compiler: Compiler) -> Engine {
+    match compiler {
+        Compiler::Singlepass => engine_with_metering(Singlepass::new()),
+        #[cfg(feature = "cranelift")]
+        Compiler::Cranelift => engine_with_metering(Cranelift::new()),
+        #[cfg(feature = "llvm")]
+        Compiler::Llvm => engine_with_metering(LLVM::new()),
+    }
+}
+
+/// The kinds of input the registry compiles modules from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum InputKind {
+    /// WebAssembly bytecode.
+    Wasm,
+    /// A pre-compiled artifact.
+    Artifact,
+}
+
+/// The key of a module in the registry: the kind of its input and the SHA-256 digest of it.
+/// The digest being collision resistant, a crafted input cannot get the module of another one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct ModuleKey {
+    kind: InputKind,
+    digest: [u8; 32],
+}
+
+impl ModuleKey {
+    fn new(kind: InputKind, input: &[u8]) -> Self {
+        Self {
+            kind,
+            digest: Sha256::digest(input).into(),
+        }
+    }
+}
+
+impl std::fmt::Display for ModuleKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for b in &self.digest[..8] {
+            write!(f, "{b:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+fn compile(compiler: Compiler, wasm: &[u8]) -> Result<CompiledModule, Error> {
+    let engine = create_engine(compiler);
+    let module = Module::from_binary(&engine, wasm).map_err(|e| {
+        error!("failed WASM compilation: {}", e);
+        Error::PluginLoadingError(e.to_string())
+    })?;
+    Ok(CompiledModule {
+        engine,
+        module,
+        compiler,
+    })
+}
+
+/// Tiered compilation settings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Tiering {
+    /// The compiler used to produce the optimized module.
+    pub compiler: Compiler,
+    /// The number of instantiations of a plugin, across all the connections sharing the
+    /// registry, after which it is considered as hot, and hence recompiled with the optimizing
+    /// compiler. The calls made to the plugin instances are not accounted for.
+    pub instantiation_threshold: u64,
+}
+
+/// Configuration of a [`PluginRegistry`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RegistryConfig {
+    /// The compiler used when a plugin is inserted for the first time.
+    pub compiler: Compiler,
+    /// If set, hot plugins get recompiled in the background with an optimizing compiler. Only
+    /// the plugins inserted from their WebAssembly bytecode are concerned.
+    pub tiering: Option<Tiering>,
+}
+
+/// A compiled plugin module, along with the engine that compiled it. Plugins must be
+/// instantiated with the engine of their module.
+#[derive(Clone, Debug)]
+pub struct CompiledModule {
+    engine: Engine,
+    module: Module,
+    compiler: Compiler,
+}
+
+impl CompiledModule {
+    /// The compiled module.
+    pub fn module(&self) -> &Module {
+        &self.module
+    }
+
+    /// The compiler that produced this module.
+    pub fn compiler(&self) -> Compiler {
+        self.compiler
+    }
+
+    /// The engine that compiled the module.
+    pub(crate) fn engine(&self) -> &Engine {
+        &self.engine
+    }
+}
+
+/// An entry of the registry.
+struct Entry {
+    /// The module to use for new instances.
+    compiled: CompiledModule,
+    /// How many times this module was handed out.
+    uses: u64,
+}
+
+type Modules = Arc<Mutex<FnvHashMap<ModuleKey, Entry>>>;
+
+/// A cache of compiled plugin modules, keyed by the SHA-256 digest of their bytecode.
+///
+/// A registry is meant to be created once per endpoint and shared, through an `Arc`, with the
+/// [`PluginHandler`](crate::handler::PluginHandler) of each connection. A plugin is then only
+/// compiled the first time it is inserted, and the following insertions only pay for its
+/// instantiation.
+///
+/// With [`Tiering`] enabled, a plugin is first compiled with the fast compiler of the
+/// [`RegistryConfig`]. Once it has been instantiated [`Tiering::instantiation_threshold`]
+/// times, whatever the connections, it gets recompiled in the background with the optimizing
+/// compiler, and connections inserting it afterwards use the optimized module. Hotness is thus
+/// a matter of how many connections use a plugin, not of how much they call it. Already
+/// running instances keep their current code.
+pub struct PluginRegistry {
+    /// The configuration of the registry.
+    config: RegistryConfig,
+    /// The compiled modules.
+    modules: Modules,
+    /// The pending background compilations.
+    tier_ups: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl std::fmt::Debug for PluginRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PluginRegistry")
+            .field("config", &self.config)
+            .field("modules", &self.len())
+            .finish()
+    }
+}
+
+impl Default for PluginRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PluginRegistry {
+    /// Create a new, empty, registry using the default configuration.
+    pub fn new() -> Self {
+        Self::with_config(RegistryConfig::default())
+    }
+
+    /// Create a new, empty, registry using the provided configuration.
+    pub fn with_config(config: RegistryConfig) -> Self {
+        Self {
+            config,
+            modules: Arc::new(Mutex::new(FnvHashMap::default())),
+            tier_ups: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// The configuration of this registry.
+    pub fn config(&self) -> &RegistryConfig {
+        &self.config
+    }
+
+    /// Return the cached module for `key` and account for its use, or compute it with `f`.
+    /// Returns whether the module just became hot.
+    fn get_or_insert_with<F>(&self, key: ModuleKey, f: F) -> Result<(CompiledModule, bool), Error>
+    where
+        F: FnOnce() -> Result<CompiledModule, Error>,
+    {
+        if let Some(e) = self.modules.lock().unwrap().get_mut(&key) {
+            e.uses += 1;
+            return Ok((e.compiled.clone(), self.is_hot(e.uses)));
+        }
+        // Do not hold the lock while compiling, this may take a while.
+        let compiled = f()?;
+        let mut modules = self.modules.lock().unwrap();
+        let e = modules.entry(key).or_insert(Entry { compiled, uses: 0 });
+        e.uses += 1;
+        Ok((e.compiled.clone(), self.is_hot(e.uses)))
+    }
+
+    fn is_hot(&self, uses: u64) -> bool {
+        matches!(self.config.tiering, Some(t) if uses == t.instantiation_threshold)
+    }
+
+    /// Recompile in the background the provided bytecode with the optimizing compiler.
+    fn tier_up(&self, key: ModuleKey, wasm: &[u8]) {
+        let Some(tiering) = self.config.tiering else {
+            return;
+        };
+        let wasm = wasm.to_vec();
+        let modules = self.modules.clone();
+        let handle = std::thread::spawn(move || {
+            let compiled = match compile(tiering.compiler, &wasm) {
+                Ok(c) => c,
+                Err(_) => return,
+            };
+            // The entry may have been cleared in the meantime.
+            if let Some(e) = modules.lock().unwrap().get_mut(&key) {
+                debug!("plugin {} tiered up to {:?}", key, tiering.compiler);
+                e.compiled = compiled;
+            }
+        });
+        let mut tier_ups = self.tier_ups.lock().unwrap();
+        tier_ups.retain(|h| !h.is_finished());
+        tier_ups.push(handle);
+    }
+
+    /// Return the module corresponding to the provided WebAssembly bytecode, compiling it if
+    /// this is the first time the registry sees it.
+    pub fn get_or_compile(&self, wasm: &[u8]) -> Result<CompiledModule, Error> {
+        let key = ModuleKey::new(InputKind::Wasm, wasm);
+        let (compiled, hot) =
+            self.get_or_insert_with(key, || compile(self.config.compiler, wasm))?;
+        if hot {
+            self.tier_up(key, wasm);
+        }
+        Ok(compiled)
+    }
+
+    /// Return the module corresponding to the provided pre-compiled artifact, loading it if
+    /// this is the first time the registry sees it.
+    ///
+    /// # Safety
+    ///
+    /// See [`PluginHandler::insert_plugin_from_serialized`](crate::handler::PluginHandler::insert_plugin_from_serialized).
+    pub unsafe fn get_or_deserialize(&self, artifact: &[u8]) -> Result<CompiledModule, Error> {
+        self.get_or_insert_with(ModuleKey::new(InputKind::Artifact, artifact), || {
+            let engine = create_engine(self.config.compiler);
+            let module = Module::deserialize(&engine, artifact).map_err(|e| {
+                error!("Cannot deserialize plugin: {}", e);
+                Error::PluginLoadingError(e.to_string())
+            })?;
+            Ok(CompiledModule {
+                engine,
+                module,
+                compiler: self.config.compiler,
+            })
+        })
+        .map(|(compiled, _)| compiled)
+    }
+
+    /// Block until all the pending background compilations are over.
+    pub fn wait_tier_ups(&self) {
+        let tier_ups = std::mem::take(&mut *self.tier_ups.lock().unwrap());
+        for h in tier_ups {
+            if h.join().is_err() {
+                error!("background plugin compilation panicked");
+            }
+        }
+    }
+
+    /// The number of modules held by the registry.
+    pub fn len(&self) -> usize {
+        self.modules.lock().unwrap().len()
+    }
+
+    /// Whether the registry does not hold any module.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drop all the cached modules. Plugins already instantiated from them are not affected.
+    pub fn clear(&self) {
+        self.modules.lock().unwrap().clear();
+    }
+}
@@ -0,0 +1,119 @@
+//! Instrumentation of the calls made to plugins.
+
+use std::time::Duration;
+
+use fnv::FnvHashMap;
+use pluginop_common::{Anchor, PluginOp, PluginVal};
+
+use crate::{handler::PluginId, Error};
+
+/// The number of buckets of a [`LatencyHistogram`].
+pub const LATENCY_BUCKETS: usize = 32;
+
+/// A histogram of call latencies, whose buckets have exponentially growing widths. The bucket
+/// `i > 0` counts the latencies in `[2^(i-1), 2^i)` nanoseconds, and the last bucket also
+/// counts all the longer ones.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    buckets: [u64; LATENCY_BUCKETS],
+}
+
+impl LatencyHistogram {
+    /// Account for a call that took `latency`.
+    pub fn record(&mut self, latency: Duration) {
+        let nanos = u64::try_from(latency.as_nanos()).unwrap_or(u64::MAX);
+        let idx = (u64::BITS - nanos.leading_zeros()) as usize;
+        self.buckets[idx.min(LATENCY_BUCKETS - 1)] += 1;
+    }
+
+    /// The number of latencies in each bucket.
+    pub fn buckets(&self) -> &[u64; LATENCY_BUCKETS] {
+        &self.buckets
+    }
+
+    /// The exclusive upper bound of the latencies counted by the bucket `idx`. The last bucket
+    /// has no upper bound, and `Duration::MAX` is returned.
+    pub fn bucket_bound(idx: usize) -> Duration {
+        if idx + 1 >= LATENCY_BUCKETS {
+            Duration::MAX
+        } else {
+            Duration::from_nanos(1 << idx)
+        }
+    }
+
+    /// The number of recorded latencies.
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    /// An upper bound of the `q`-quantile of the recorded latencies, with `q` in `[0, 1]`, or
+    /// `None` if no latency was recorded.
+    pub fn quantile(&self, q: f64) -> Option<Duration> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        let rank = ((q.clamp(0.0, 1.0) * count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (idx, n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                return Some(Self::bucket_bound(idx));
+            }
+        }
+        None
+    }
+}
+
+/// Statistics about the calls of an anchor of a plugin operation.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CallStats {
+    /// The number of calls.
+    pub calls: u64,
+    /// The number of calls in which the plugin failed, e.g., because it trapped or exceeded
+    /// its budget.
+    pub failures: u64,
+    /// The number of calls in which the plugin returned an [`Error::OperationError`].
+    pub operation_errors: u64,
+    /// The total time spent in the calls.
+    pub total_time: Duration,
+    /// The distribution of the time spent in each call.
+    pub latencies: LatencyHistogram,
+}
+
+impl CallStats {
+    /// Account for a call that took `latency` and ended with `res`.
+    pub(crate) fn record(&mut self, latency: Duration, res: &Result<Vec<PluginVal>, Error>) {
+        self.calls += 1;
+        match res {
+            Ok(_) => {}
+            Err(Error::OperationError(_)) => self.operation_errors += 1,
+            Err(_) => self.failures += 1,
+        }
+        self.total_time += latency;
+        self.latencies.record(latency);
+    }
+
+    /// The mean time spent in a call, or `None` if there was no call.
+    pub fn mean_time(&self) -> Option<Duration> {
+        u32::try_from(self.calls)
+            .ok()
+            .filter(|c| *c > 0)
+            .map(|c| self.total_time / c)
+    }
+}
+
+/// Identifies the calls accounted by a [`CallStats`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct CallKey {
+    /// The plugin called.
+    pub plugin: PluginId,
+    /// The operation invoked.
+    pub po: PluginOp,
+    /// The anchor of the operation run by the plugin.
+    pub anchor: Anchor,
+}
+
+/// A snapshot of the statistics collected by a
+/// [`PluginHandler`](crate::handler::PluginHandler).
+pub type StatsSnapshot = FnvHashMap<CallKey, CallStats>;
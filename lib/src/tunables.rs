@@ -0,0 +1,213 @@
+//! Custom tunables enforcing the [`PluginLimits`] of a plugin instance.
+
+use std::{
+    ptr::NonNull,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use wasmer::{
+    sys::BaseTunables,
+    vm::{
+        LinearMemory, MemoryError, MemoryStyle, TableStyle, VMConfig, VMMemory, VMMemoryDefinition,
+        VMTable, VMTableDefinition,
+    },
+    MemoryType, Pages, TableType, Tunables,
+};
+
+use crate::config::PluginLimits;
+
+/// A linear memory recording whether the plugin tried to grow it beyond its limit.
+#[derive(Debug)]
+struct LimitedMemory {
+    inner: VMMemory,
+    grow_denied: Arc<AtomicBool>,
+}
+
+impl LinearMemory for LimitedMemory {
+    fn ty(&self) -> MemoryType {
+        self.inner.ty()
+    }
+
+    fn size(&self) -> Pages {
+        self.inner.size()
+    }
+
+    fn style(&self) -> MemoryStyle {
+        self.inner.style()
+    }
+
+    fn grow(&mut self, delta: Pages) -> Result<Pages, MemoryError> {
+        self.inner
+            .grow(delta)
+            .inspect_err(|_| self.grow_denied.store(true, Ordering::Relaxed))
+    }
+
+    fn grow_at_least(&mut self, min_size: u64) -> Result<(), MemoryError> {
+        self.inner
+            .grow_at_least(min_size)
+            .inspect_err(|_| self.grow_denied.store(true, Ordering::Relaxed))
+    }
+
+    fn reset(&mut self) -> Result<(), MemoryError> {
+        self.inner.reset()
+    }
+
+    fn vmmemory(&self) -> NonNull<VMMemoryDefinition> {
+        self.inner.vmmemory()
+    }
+
+    fn try_clone(&self) -> Result<Box<dyn LinearMemory + 'static>, MemoryError> {
+        self.inner.try_clone().map(|m| self.rewrap(m))
+    }
+
+    fn copy(&mut self) -> Result<Box<dyn LinearMemory + 'static>, MemoryError> {
+        self.inner.copy().map(|m| self.rewrap(m))
+    }
+}
+
+impl LimitedMemory {
+    /// Wrap a clone or a copy of this memory, so that it keeps recording denied growths. Its
+    /// limit is the maximum of its type, that it inherits from this memory.
+    fn rewrap(&self, inner: Box<dyn LinearMemory + 'static>) -> Box<dyn LinearMemory + 'static> {
+        Box::new(LimitedMemory {
+            inner: VMMemory(inner),
+            grow_denied: self.grow_denied.clone(),
+        })
+    }
+}
+
+/// Tunables capping the memories and tables of a plugin instance.
+pub(crate) struct LimitingTunables {
+    base: BaseTunables,
+    limits: PluginLimits,
+    vmconfig: VMConfig,
+    /// Set when a memory growth got refused.
+    grow_denied: Arc<AtomicBool>,
+}
+
+impl LimitingTunables {
+    pub(crate) fn new(
+        base: BaseTunables,
+        limits: PluginLimits,
+        grow_denied: Arc<AtomicBool>,
+    ) -> Self {
+        Self {
+            base,
+            limits,
+            vmconfig: VMConfig {
+                wasm_stack_size: limits.stack_size,
+            },
+            grow_denied,
+        }
+    }
+
+    /// Cap the maximum size of the requested memory.
+    fn adjust_memory(&self, requested: &MemoryType) -> MemoryType {
+        let mut adjusted = *requested;
+        if let Some(limit) = self.limits.memory_pages.map(Pages) {
+            if !matches!(requested.maximum, Some(m) if m <= limit) {
+                adjusted.maximum = Some(limit);
+            }
+        }
+        adjusted
+    }
+
+    fn validate_memory(&self, ty: &MemoryType) -> Result<(), MemoryError> {
+        match self.limits.memory_pages.map(Pages) {
+            Some(limit) if ty.minimum > limit => Err(MemoryError::MinimumMemoryTooLarge {
+                min_requested: ty.minimum,
+                max_allowed: limit,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Cap the maximum size of the requested table.
+    fn adjust_table(&self, requested: &TableType) -> TableType {
+        let mut adjusted = *requested;
+        if let Some(limit) = self.limits.table_elements {
+            if !matches!(requested.maximum, Some(m) if m <= limit) {
+                adjusted.maximum = Some(limit);
+            }
+        }
+        adjusted
+    }
+
+    fn validate_table(&self, ty: &TableType) -> Result<(), String> {
+        match self.limits.table_elements {
+            Some(limit) if ty.minimum > limit => Err(format!(
+                "table minimum ({} elements) exceeds the limit ({} elements)",
+                ty.minimum, limit
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn wrap_memory(&self, inner: VMMemory) -> VMMemory {
+        VMMemory(Box::new(LimitedMemory {
+            inner,
+            grow_denied: self.grow_denied.clone(),
+        }))
+    }
+}
+
+impl Tunables for LimitingTunables {
+    fn memory_style(&self, memory: &MemoryType) -> MemoryStyle {
+        // Keep the style the module was compiled with.
+        self.base.memory_style(memory)
+    }
+
+    fn table_style(&self, table: &TableType) -> TableStyle {
+        self.base.table_style(table)
+    }
+
+    fn create_host_memory(
+        &self,
+        ty: &MemoryType,
+        style: &MemoryStyle,
+    ) -> Result<VMMemory, MemoryError> {
+        let adjusted = self.adjust_memory(ty);
+        self.validate_memory(&adjusted)?;
+        self.base
+            .create_host_memory(&adjusted, style)
+            .map(|m| self.wrap_memory(m))
+    }
+
+    unsafe fn create_vm_memory(
+        &self,
+        ty: &MemoryType,
+        style: &MemoryStyle,
+        vm_definition_location: NonNull<VMMemoryDefinition>,
+    ) -> Result<VMMemory, MemoryError> {
+        let adjusted = self.adjust_memory(ty);
+        self.validate_memory(&adjusted)?;
+        self.base
+            .create_vm_memory(&adjusted, style, vm_definition_location)
+            .map(|m| self.wrap_memory(m))
+    }
+
+    fn create_host_table(&self, ty: &TableType, style: &TableStyle) -> Result<VMTable, String> {
+        let adjusted = self.adjust_table(ty);
+        self.validate_table(&adjusted)?;
+        self.base.create_host_table(&adjusted, style)
+    }
+
+    unsafe fn create_vm_table(
+        &self,
+        ty: &TableType,
+        style: &TableStyle,
+        vm_definition_location: NonNull<VMTableDefinition>,
+    ) -> Result<VMTable, String> {
+        let adjusted = self.adjust_table(ty);
+        self.validate_table(&adjusted)?;
+        self.base
+            .create_vm_table(&adjusted, style, vm_definition_location)
+    }
+
+    fn vmconfig(&self) -> &VMConfig {
+        &self.vmconfig
+    }
+}
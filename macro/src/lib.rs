@@ -100,8 +100,12 @@ fn get_param_block(
                 let pat = &pt.pat;
                 match has_octets(pt) {
                     (true, _, true) if !with_octets => None,
-                    (true, false, true) => Some(quote!( OctetsPtr::from(#pat).into_with_ph(ph) )),
-                    (true, true, true) => Some(quote!( OctetsMutPtr::from(#pat).into_with_ph(ph) )),
+                    (true, false, true) => {
+                        Some(quote!( OctetsPtr::from(&mut * #pat).into_with_ph(ph) ))
+                    }
+                    (true, true, true) => {
+                        Some(quote!( OctetsMutPtr::from(&mut * #pat).into_with_ph(ph) ))
+                    }
                     (true, _, false) => panic!("Octets argument must be mutable"),
                     _ => {
                         if let Some(ign) = &ignore {
@@ -126,87 +130,419 @@ fn get_param_block(
     )
 }
 
+/// Converts the plugin outputs `res` into the output of a non-faillible operation. Evaluates to
+/// a `Result`, whose error denotes a plugin failure.
 fn get_ret_block(fn_output_type: &ReturnType) -> proc_macro2::TokenStream {
     match fn_output_type {
-        syn::ReturnType::Default => quote!({
-            if let Err(err) = res {
-                panic!("plugin execution error: {:?}", err);
-            }
-        }),
+        syn::ReturnType::Default => quote!(res.map(|_| ())),
         syn::ReturnType::Type(_, t) => {
             if let Type::Tuple(tu) = *t.clone() {
                 let elems = tu.elems.into_iter();
                 quote! {
-                    let mut it = match res {
-                        Ok(r) => r.into_iter(),
-                        Err(pluginop::Error::OperationError(e)) => todo!("operation error {:?}; should you use pluginop_result?", e),
-                        Err(err) => panic!("plugin execution error: {:?}", err),
-                    };
-                    (
-                        #(
-                            #elems :: try_from(it.next().unwrap()).unwrap(),
-                        )*
-                    )
+                    res.and_then(|r| {
+                        let mut it = r.into_iter();
+                        Ok((
+                            #(
+                                #elems :: try_from(it.next().ok_or(pluginop::Error::InvalidOutputs)?)
+                                    .map_err(|_| pluginop::Error::InvalidOutputs)?,
+                            )*
+                        ))
+                    })
                 }
             } else {
-                quote!(
-                    let mut it = match res {
-                        Ok(r) => r.into_iter(),
-                        Err(pluginop::Error::OperationError(e)) => todo!("operation error {:?}; should you use pluginop_result?", e),
-                        Err(err) => panic!("plugin execution error: {:?}", err),
-                    };
-                    { it.next().unwrap().try_into().unwrap() }
-                )
+                quote! {
+                    res.and_then(|r| {
+                        r.into_iter()
+                            .next()
+                            .ok_or(pluginop::Error::InvalidOutputs)?
+                            .try_into()
+                            .map_err(|_| pluginop::Error::InvalidOutputs)
+                    })
+                }
             }
         }
     }
 }
 
+/// Converts the plugin outputs `res` into the output of an operation returning a [`Result`].
+/// Evaluates to a `Result`, whose error denotes a plugin failure. Operation errors returned by
+/// the plugin are not failures, and get converted into the error of the operation.
 fn get_ret_result_block(fn_output_type: &ReturnType) -> proc_macro2::TokenStream {
     match fn_output_type {
-        syn::ReturnType::Default => quote!({
-            if let Err(err) = res {
-                panic!("plugin execution error: {:?}", err);
-            }
-        }),
+        syn::ReturnType::Default => quote!(res.map(|_| ())),
         syn::ReturnType::Type(_, t) => {
             if let Type::Tuple(tu) = *t.clone() {
                 let elems = tu.elems.into_iter();
                 quote! {
-                    let mut it = match res {
-                        Ok(r) => r.into_iter(),
-                        Err(pluginop::Error::OperationError(e)) => return Err(e.into()),
-                        Err(err) => panic!("plugin execution error: {:?}", err),
-                    };
-                    Ok((
-                        #(
-                            #elems :: try_from_with_ph(it.next().unwrap(), ph).unwrap(),
-                        )*
-                    ))
+                    match res {
+                        Ok(r) => {
+                            let mut it = r.into_iter();
+                            (|| Ok(Ok((
+                                #(
+                                    #elems :: try_from_with_ph(it.next().ok_or(pluginop::Error::InvalidOutputs)?, ph)
+                                        .map_err(|_| pluginop::Error::InvalidOutputs)?,
+                                )*
+                            ))))()
+                        }
+                        Err(pluginop::Error::OperationError(e)) => Ok(Err(e.into())),
+                        Err(err) => Err(err),
+                    }
                 }
-            } else {
+            } else if is_result_unit(t) {
                 // We need to check if this is the unit type.
-                if is_result_unit(t) {
-                    quote!(match res {
-                        Ok(r) => Ok(()),
-                        Err(pluginop::Error::OperationError(e)) => Err(e.into()),
-                        Err(err) => panic!("plugin execution error: {:?}", err),
-                    })
+                quote! {
+                    match res {
+                        Ok(_) => Ok(Ok(())),
+                        Err(pluginop::Error::OperationError(e)) => Ok(Err(e.into())),
+                        Err(err) => Err(err),
+                    }
+                }
+            } else {
+                quote! {
+                    match res {
+                        Ok(r) => match r.into_iter().next() {
+                            Some(r) => r
+                                .try_into_with_ph(ph)
+                                .map(Ok)
+                                .map_err(|_| pluginop::Error::InvalidOutputs),
+                            None => Err(pluginop::Error::InvalidOutputs),
+                        },
+                        Err(pluginop::Error::OperationError(e)) => Ok(Err(e.into())),
+                        Err(err) => Err(err),
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Generates the code applying the failure policy of the plugin handler when the plugins did
+/// not provide the output `ret` of the operation, falling back to the host implementation if
+/// the policy allows it.
+fn get_failure_block(
+    base_fn: &ItemFn,
+    po_code: &proc_macro2::TokenStream,
+    fn_args: &[Pat],
+) -> proc_macro2::TokenStream {
+    let fn_name_internal = format_ident!("__{}__", base_fn.sig.ident);
+    quote! {
+        let ret: Result<_, pluginop::Error> = ret;
+        match ret {
+            Ok(ret) => ret,
+            Err(err) => {
+                ph.handle_failure(& #po_code, &err);
+                self.#fn_name_internal(#(#fn_args,)*)
+            }
+        }
+    }
+}
+
+/// Generates the code running the `Before` anchors of an operation defined by the host. Their
+/// failures are only considered if the plugin handler is in strict mode, in which case they
+/// abort an operation returning a [`Result`] with the error code of the plugin, or
+/// `BEFORE_ANCHOR_FAILED` if it did not return any. Other operations cannot be aborted, so
+/// their failures are handled according to the failure policy of the plugin handler.
+fn get_before_block(po_code: &proc_macro2::TokenStream, result: bool) -> proc_macro2::TokenStream {
+    let failure = if result {
+        quote! {
+            Err(pluginop::Error::OperationError(e)) if ph.strict_anchors() => return Err(e.into()),
+            Err(_) if ph.strict_anchors() => {
+                return Err(pluginop::handler::BEFORE_ANCHOR_FAILED.into())
+            }
+        }
+    } else {
+        quote! {
+            Err(err) if ph.strict_anchors() => ph.handle_failure(& #po_code, &err),
+        }
+    };
+    quote! {
+        match ph.call_direct(
+            & #po_code,
+            pluginop::common::Anchor::Before,
+            params.as_ref().unwrap(),
+        ) {
+            Ok(()) => {}
+            #failure
+            // Otherwise, failures are only reported.
+            Err(_) => {}
+        }
+    }
+}
+
+/// Returns whether the type is the host `Instant`, which is exchanged with plugins as a UNIX
+/// instant.
+fn is_instant(ty: &Type) -> bool {
+    match ty {
+        Type::Path(tp) => {
+            tp.path.segments.last().map(|s| s.ident.to_string()) == Some("Instant".to_string())
+                && !tp
+                    .path
+                    .segments
+                    .iter()
+                    .any(|s| &s.ident.to_string() == "unix_time")
+        }
+        _ => false,
+    }
+}
+
+/// Returns whether the type is `Vec<u8>`, which is given to plugins as a `Bytes` token.
+fn is_byte_vec(ty: &Type) -> bool {
+    match ty {
+        Type::Path(tp) => match tp.path.segments.last() {
+            Some(ps) if ps.ident == "Vec" => match &ps.arguments {
+                syn::PathArguments::AngleBracketed(ab) => matches!(
+                    ab.args.first(),
+                    Some(GenericArgument::Type(Type::Path(t))) if t.path.is_ident("u8")
+                ),
+                _ => false,
+            },
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Returns the type of the `Ok` variant of a `Result` type.
+fn result_ok_type(ty: &Type) -> Option<Type> {
+    match ty {
+        Type::Path(tp) => {
+            let ps = tp.path.segments.last()?;
+            match &ps.arguments {
+                syn::PathArguments::AngleBracketed(ab) => match ab.args.first() {
+                    Some(GenericArgument::Type(t)) => Some(t.clone()),
+                    _ => None,
+                },
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Converts `value`, of type `ty`, into the outputs of the operation given to the plugins.
+fn get_outputs_block(
+    ty: Option<&Type>,
+    value: proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    match ty {
+        None => quote!(Vec::new()),
+        Some(Type::Tuple(tu)) if tu.elems.is_empty() => quote!(Vec::new()),
+        Some(Type::Tuple(tu)) => {
+            let rets: Vec<Ident> = (0..tu.elems.len())
+                .map(|i| format_ident!("__ret_{}", i))
+                .collect();
+            quote!({
+                let ( #(#rets,)* ) = #value;
+                vec![ #(#rets.into_with_ph(ph),)* ]
+            })
+        }
+        Some(_) => quote!(vec![#value.into_with_ph(ph)]),
+    }
+}
+
+/// Converts back the `outputs` provided by the plugins into `*value`, of type `ty`, and
+/// evaluates to whether they could be. `*value` is left untouched if the outputs do not match
+/// the type.
+fn get_from_outputs_block(ty: Option<&Type>) -> proc_macro2::TokenStream {
+    match ty {
+        None => quote!({
+            let _ = value;
+            true
+        }),
+        Some(Type::Tuple(tu)) if tu.elems.is_empty() => quote!({
+            let _ = value;
+            true
+        }),
+        Some(Type::Tuple(tu)) => {
+            let elems = tu.elems.iter();
+            let vars: Vec<Ident> = (0..tu.elems.len())
+                .map(|i| format_ident!("__out_{}", i))
+                .collect();
+            let indexes = 0..tu.elems.len();
+            quote! {
+                if let ( #(Some(Ok(#vars)),)* ) = ( #(
+                    outputs.get(#indexes).cloned().map(|pv| <#elems as pluginop::TryFromWithPH<_, _>>::try_from_with_ph(pv, ph)),
+                )* ) {
+                    *value = ( #(#vars,)* );
+                    true
                 } else {
-                    quote!(
-                        let mut it = match res {
-                            Ok(r) => r.into_iter(),
-                            Err(pluginop::Error::OperationError(e)) => return Err(e.into()),
-                            Err(err) => panic!("plugin execution error: {:?}", err),
-                        };
-                        match it.next() {
-                            Some(r) => Ok(r.try_into_with_ph(ph).unwrap()),
-                            None => panic!("Missing output from the plugin"),
-                        }
-                    )
+                    false
                 }
             }
         }
+        Some(t) => quote! {
+            if let Some(Ok(v)) = outputs.first().cloned().map(|pv| <#t as pluginop::TryFromWithPH<_, _>>::try_from_with_ph(pv, ph)) {
+                *value = v;
+                true
+            } else {
+                false
+            }
+        },
+    }
+}
+
+/// Returns the type of the value of the operation outputs, i.e., the `Ok` type of a
+/// `Result`-returning operation.
+fn get_value_type(output: &ReturnType, result: bool) -> Option<Type> {
+    let ty = match output {
+        ReturnType::Default => return None,
+        ReturnType::Type(_, t) => &**t,
+    };
+    if result {
+        result_ok_type(ty)
+    } else {
+        Some(ty.clone())
+    }
+}
+
+/// Generates the code running the `Wrap` and `After` anchors once the host implementation
+/// returned `ret`. The `Wrap` anchors may replace the value of `ret`. Outputs of the `Wrap`
+/// anchors that do not match the value are reported and discarded, so that the `After` anchors
+/// observe the actual value of the operation.
+fn get_result_hooks_block(
+    base_fn: &ItemFn,
+    po_code: &proc_macro2::TokenStream,
+    result: bool,
+) -> proc_macro2::TokenStream {
+    let value_ty = get_value_type(&base_fn.sig.output, result);
+    let value = if result {
+        quote!(let value = ret.as_mut().ok();)
+    } else {
+        quote!(let value = Some(&mut ret);)
+    };
+    let outputs = get_outputs_block(value_ty.as_ref(), quote!((**v).clone()));
+    let from_outputs = get_from_outputs_block(value_ty.as_ref());
+    quote! {
+        let params = params.as_ref().unwrap();
+        #value
+        let outputs = match &value {
+            Some(v) => #outputs,
+            None => Vec::new(),
+        };
+        let outputs = match value {
+            Some(value) if has_wrap => match ph.call_wrap(& #po_code, params, outputs.clone()) {
+                Ok(wrapped) => {
+                    let converted = {
+                        let outputs = &wrapped;
+                        #from_outputs
+                    };
+                    if converted {
+                        wrapped
+                    } else {
+                        ph.anchor_failed(
+                            & #po_code,
+                            pluginop::common::Anchor::Wrap,
+                            &pluginop::Error::InvalidOutputs,
+                        );
+                        outputs
+                    }
+                }
+                Err(err) => {
+                    ph.anchor_failed(& #po_code, pluginop::common::Anchor::Wrap, &err);
+                    outputs
+                }
+            },
+            _ => outputs,
+        };
+        if has_after {
+            // Failures are reported by the handler and do not affect the operation.
+            let _ = ph.call_direct(
+                & #po_code,
+                pluginop::common::Anchor::After,
+                &[params.as_slice(), outputs.as_slice()].concat(),
+            );
+        }
+    }
+}
+
+/// Generates the closure running the host implementation of the plugin operation with the
+/// inputs provided by the plugin, see `PluginHandler::call_default`. The inputs follow the
+/// order of the parameters given to the plugin, and `Octets` ones are taken as is. `Vec<u8>`
+/// ones can be provided as the `Bytes` token given to the plugin, or by value.
+fn get_default_block(
+    base_fn: &ItemFn,
+    ignore: Option<&Ident>,
+    result: bool,
+) -> proc_macro2::TokenStream {
+    let fn_name_internal = format_ident!("__{}__", base_fn.sig.ident);
+    let mut bindings = Vec::new();
+    let mut args = Vec::new();
+    let mut index = 0usize;
+    for a in base_fn.sig.inputs.iter() {
+        let pt = match a {
+            FnArg::Typed(pt) => pt,
+            _ => continue,
+        };
+        let pat = &pt.pat;
+        let ty = &pt.ty;
+        if let (Some(ign), Pat::Ident(pi)) = (ignore, &*pt.pat) {
+            if pi.ident == *ign {
+                args.push(quote!( #pat.clone() ));
+                continue;
+            }
+        }
+        let arg = format_ident!("__arg_{}", index);
+        if has_octets(pt).0 {
+            args.push(quote!( &mut * #pat ));
+        } else if is_instant(ty) {
+            bindings.push(quote! {
+                let #arg: #ty = match inputs.get(#index) {
+                    Some(pluginop::common::PluginVal::UNIXInstant(i)) => ph.get_instant_from_unix_instant(*i),
+                    _ => return Err(pluginop::Error::InvalidDefaultInput(#index)),
+                };
+            });
+            args.push(quote!( #arg ));
+        } else if is_byte_vec(ty) {
+            bindings.push(quote! {
+                let #arg: #ty = match inputs.get(#index) {
+                    Some(pluginop::common::PluginVal::Bytes(b)) => match ph.get_copied_bytes(b) {
+                        Some(v) => v,
+                        None => return Err(pluginop::Error::InvalidDefaultInput(#index)),
+                    },
+                    Some(pv) => match pv.clone().try_into_with_ph(ph) {
+                        Ok(v) => v,
+                        Err(_) => return Err(pluginop::Error::InvalidDefaultInput(#index)),
+                    },
+                    None => return Err(pluginop::Error::InvalidDefaultInput(#index)),
+                };
+            });
+            args.push(quote!( #arg ));
+        } else {
+            bindings.push(quote! {
+                let #arg: #ty = match inputs.get(#index).map(|pv| pv.clone().try_into_with_ph(ph)) {
+                    Some(Ok(v)) => v,
+                    _ => return Err(pluginop::Error::InvalidDefaultInput(#index)),
+                };
+            });
+            args.push(quote!( #arg ));
+        }
+        index += 1;
+    }
+
+    let outputs = get_outputs_block(
+        get_value_type(&base_fn.sig.output, result).as_ref(),
+        quote!(ret),
+    );
+    let outputs = if result {
+        quote! {
+            match ret {
+                Ok(ret) => Ok(#outputs),
+                Err(_) => Err(pluginop::Error::DefaultFailed),
+            }
+        }
+    } else {
+        quote!(Ok(#outputs))
+    };
+
+    quote! {
+        #[allow(unused_variables)]
+        let mut default = |ph: &mut pluginop::handler::PluginHandler<Self>, inputs: &[pluginop::common::PluginVal]| -> Result<Vec<pluginop::common::PluginVal>, pluginop::Error> {
+            #(#bindings)*
+            // SAFETY: the connection is pinned and single-threaded, as the plugin handler.
+            let this = unsafe { &mut *self_ptr };
+            let ret = this.#fn_name_internal(#(#args,)*);
+            #outputs
+        };
     }
 }
 
@@ -215,6 +551,7 @@ fn get_out_block(
     po: &Path,
     value: Option<Expr>,
     ret_block: &proc_macro2::TokenStream,
+    result: bool,
 ) -> proc_macro2::TokenStream {
     let fn_args = extract_arg_idents(base_fn.sig.inputs.clone());
     let fn_inputs = &base_fn.sig.inputs;
@@ -227,12 +564,16 @@ fn get_out_block(
     let fn_name_internal = format_ident!("__{}__", fn_name);
     let param_code = get_param_block(fn_inputs, None, true);
     let param_code_prepost = get_param_block(fn_inputs, None, false);
+    let default_code = get_default_block(base_fn, None, result);
 
     let po_code = if let Some(v) = value {
         quote! { #po ( #v ) }
     } else {
         quote! { #po }
     };
+    let result_hooks_code = get_result_hooks_block(base_fn, &po_code, result);
+    let failure_code = get_failure_block(base_fn, &po_code, &fn_args);
+    let before_code = get_before_block(&po_code, result);
 
     quote! {
         #[allow(unused_variables)]
@@ -247,36 +588,33 @@ fn get_out_block(
             use pluginop::TryIntoWithPH;
             use pluginop::octets::OctetsMutPtr;
             use pluginop::octets::OctetsPtr;
+            let self_ptr: *mut Self = self;
             let ph = self.get_pluginizable_connection().map(|pc| pc.get_ph_mut());
             if let Some(ph) = ph {
                 if ph.provides(& #po_code, pluginop::common::Anchor::Define) {
                     let params = & #param_code;
-                    let res = ph.call(
+                    #default_code
+                    let res = ph.call_with_default(
                         & #po_code,
                         params,
+                        &mut default,
                     );
                     ph.clear_bytes_content();
 
-                    #ret_block
+                    let ret = { #ret_block };
+                    #failure_code
                 } else {
                     let has_before = ph.provides(& #po_code, pluginop::common::Anchor::Before);
+                    let has_wrap = ph.provides(& #po_code, pluginop::common::Anchor::Wrap);
                     let has_after = ph.provides(& #po_code, pluginop::common::Anchor::After);
-                    let params = if has_before || has_after { Some(#param_code_prepost) } else { None };
+                    let params = if has_before || has_wrap || has_after { Some(#param_code_prepost) } else { None };
                     if has_before {
-                        ph.call_direct(
-                            & #po_code,
-                            pluginop::common::Anchor::Before,
-                            params.as_ref().unwrap(),
-                        ).ok();
+                        #before_code
                     }
-                    let ret = self.#fn_name_internal(#(#fn_args,)*);
-                    if has_after {
+                    let mut ret = self.#fn_name_internal(#(#fn_args,)*);
+                    if has_wrap || has_after {
                         if let Some(ph) = self.get_pluginizable_connection().map(|pc| pc.get_ph_mut()) {
-                            ph.call_direct(
-                                & #po_code,
-                                pluginop::common::Anchor::After,
-                                params.as_ref().unwrap(),
-                            ).ok();
+                            #result_hooks_code
                         }
                     }
                     ret
@@ -293,6 +631,7 @@ fn get_out_param_block(
     base_fn: &ItemFn,
     po: &Path,
     ret_block: &proc_macro2::TokenStream,
+    result: bool,
 ) -> proc_macro2::TokenStream {
     let fn_output = &base_fn.sig.output;
     let fn_inputs = &base_fn.sig.inputs;
@@ -305,6 +644,10 @@ fn get_out_param_block(
     let fn_name_internal = format_ident!("__{}__", fn_name);
     let param_code = get_param_block(fn_inputs, Some(param.clone()), true);
     let param_code_prepost = get_param_block(fn_inputs, Some(param.clone()), false);
+    let default_code = get_default_block(base_fn, Some(&param), result);
+    let result_hooks_code = get_result_hooks_block(base_fn, &quote!(#po(#param)), result);
+    let failure_code = get_failure_block(base_fn, &quote!(#po(#param)), &fn_args);
+    let before_code = get_before_block(&quote!(#po(#param)), result);
 
     quote! {
         #[allow(unused_variables)]
@@ -318,36 +661,33 @@ fn get_out_param_block(
             use pluginop::TryIntoWithPH;
             use pluginop::octets::OctetsMutPtr;
             use pluginop::octets::OctetsPtr;
+            let self_ptr: *mut Self = self;
             let ph = self.get_pluginizable_connection().map(|pc| pc.get_ph_mut());
             if let Some(ph) = ph {
                 if ph.provides(& #po(#param), pluginop::common::Anchor::Define) {
                     let params = & #param_code;
-                    let res = ph.call(
+                    #default_code
+                    let res = ph.call_with_default(
                         & #po(#param),
                         params,
+                        &mut default,
                     );
                     ph.clear_bytes_content();
 
-                    #ret_block
+                    let ret = { #ret_block };
+                    #failure_code
                 } else {
                     let has_before = ph.provides(& #po(#param), pluginop::common::Anchor::Before);
+                    let has_wrap = ph.provides(& #po(#param), pluginop::common::Anchor::Wrap);
                     let has_after = ph.provides(& #po(#param), pluginop::common::Anchor::After);
-                    let params = if has_before || has_after { Some(#param_code_prepost) } else { None };
+                    let params = if has_before || has_wrap || has_after { Some(#param_code_prepost) } else { None };
                     if has_before {
-                        ph.call_direct(
-                            & #po(#param),
-                            pluginop::common::Anchor::Before,
-                            params.as_ref().unwrap(),
-                        ).ok();
+                        #before_code
                     }
-                    let ret = self.#fn_name_internal(#(#fn_args,)*);
-                    if has_after {
+                    let mut ret = self.#fn_name_internal(#(#fn_args,)*);
+                    if has_wrap || has_after {
                         if let Some(ph) = self.get_pluginizable_connection().map(|pc| pc.get_ph_mut()) {
-                            ph.call_direct(
-                                & #po(#param),
-                                pluginop::common::Anchor::After,
-                                params.as_ref().unwrap(),
-                            ).ok();
+                            #result_hooks_code
                         }
                     }
                     ret
@@ -382,7 +722,7 @@ pub fn pluginop(attr: TokenStream, item: TokenStream) -> TokenStream {
     let base_fn = parse_macro_input!(item as ItemFn);
 
     let ret_block = get_ret_block(&base_fn.sig.output);
-    let out = get_out_block(&base_fn, &po, value, &ret_block);
+    let out = get_out_block(&base_fn, &po, value, &ret_block, false);
 
     // println!("output is\n{}", out);
 
@@ -404,7 +744,7 @@ pub fn pluginop_result(attr: TokenStream, item: TokenStream) -> TokenStream {
     let base_fn = parse_macro_input!(item as ItemFn);
 
     let ret_block = get_ret_result_block(&base_fn.sig.output);
-    let out = get_out_block(&base_fn, &po, value, &ret_block);
+    let out = get_out_block(&base_fn, &po, value, &ret_block, true);
 
     // println!("output is\n{}", out);
 
@@ -436,7 +776,7 @@ pub fn pluginop_param(attr: TokenStream, item: TokenStream) -> TokenStream {
     let base_fn = parse_macro_input!(item as ItemFn);
 
     let ret_block = get_ret_block(&base_fn.sig.output);
-    get_out_param_block(param, &base_fn, &po, &ret_block).into()
+    get_out_param_block(param, &base_fn, &po, &ret_block, false).into()
 }
 
 /// An attribute macro to transform a function returning a [`Result`] into a
@@ -456,7 +796,7 @@ pub fn pluginop_result_param(attr: TokenStream, item: TokenStream) -> TokenStrea
     let base_fn = parse_macro_input!(item as ItemFn);
 
     let ret_block = get_ret_result_block(&base_fn.sig.output);
-    let out = get_out_param_block(param, &base_fn, &po, &ret_block);
+    let out = get_out_param_block(param, &base_fn, &po, &ret_block, true);
 
     // println!("output is\n{}", out);
 
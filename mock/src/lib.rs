@@ -1,4 +1,5 @@
 use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use pluginop::api::{ConnectionToPlugin, ToPluginizableConnection};
@@ -11,9 +12,12 @@ use pluginop::common::{Bytes, PluginOp};
 use pluginop::octets::{Octets, OctetsMut};
 use pluginop::plugin::Env;
 use pluginop::pluginop_macro::{pluginop, pluginop_param, pluginop_result, pluginop_result_param};
+use pluginop::registry::PluginRegistry;
 use pluginop::{api::CTPError, ParentReferencer, PluginizableConnection};
 use pluginop::{Exports, FunctionEnv, Store};
 
+pub mod replay;
+
 /// Dummy object
 pub struct ConnectionDummy {
     pc: Option<ParentReferencer<PluginizableConnection<Self>>>,
@@ -24,14 +28,17 @@ pub struct ConnectionDummy {
 impl ConnectionToPlugin for ConnectionDummy {
     fn get_recovery<'a>(
         &self,
-        _: RecoveryField,
-        _: &'a mut [u8],
+        field: RecoveryField,
+        w: &'a mut [u8],
     ) -> postcard::Result<&'a mut [u8]> {
-        todo!()
+        match field {
+            RecoveryField::SmoothedRtt => postcard::to_slice(&self.srtt, w),
+            _ => Err(postcard::Error::WontImplement),
+        }
     }
 
     fn set_recovery(&mut self, _: RecoveryField, _: &[u8]) -> std::result::Result<(), CTPError> {
-        todo!()
+        Err(CTPError::UnsupportedField)
     }
 
     fn get_connection<'a>(
@@ -41,7 +48,7 @@ impl ConnectionToPlugin for ConnectionDummy {
     ) -> postcard::Result<&'a mut [u8]> {
         let pv: PluginVal = match field {
             ConnectionField::MaxTxData => self.max_tx_data.into(),
-            _ => todo!(),
+            _ => return Err(postcard::Error::WontImplement),
         };
         postcard::to_slice(&pv, w)
     }
@@ -52,7 +59,7 @@ impl ConnectionToPlugin for ConnectionDummy {
             ConnectionField::MaxTxData => {
                 self.max_tx_data = pv.try_into().map_err(|_| CTPError::BadType)?
             }
-            _ => todo!(),
+            _ => return Err(CTPError::UnsupportedField),
         };
         Ok(())
     }
@@ -198,6 +205,11 @@ impl ConnectionDummy {
         }
     }
 
+    #[pluginop_param(po = "PluginOp::LogFrame", param = "ty")]
+    fn log_frame(&mut self, ty: u64, content: Vec<u8>) -> usize {
+        content.len()
+    }
+
     #[pluginop_param(po = "PluginOp::WireLen", param = "ty")]
     fn wire_len(&mut self, ty: u64, f: quic::Frame) -> usize {
         if ty == 0x10 {
@@ -326,6 +338,16 @@ pub struct PluginizableConnectionDummy(Box<PluginizableConnection<ConnectionDumm
 impl PluginizableConnectionDummy {
     pub fn new_pluginizable_connection(
         exports_func: fn(&mut Store, &FunctionEnv<Env<ConnectionDummy>>) -> Exports,
+    ) -> PluginizableConnectionDummy {
+        Self::new_pluginizable_connection_with_registry(
+            exports_func,
+            Arc::new(PluginRegistry::new()),
+        )
+    }
+
+    pub fn new_pluginizable_connection_with_registry(
+        exports_func: fn(&mut Store, &FunctionEnv<Env<ConnectionDummy>>) -> Exports,
+        registry: Arc<PluginRegistry>,
     ) -> PluginizableConnectionDummy {
         let conn = ConnectionDummy {
             pc: None,
@@ -333,7 +355,11 @@ impl PluginizableConnectionDummy {
             srtt: Duration::from_millis(333),
         };
         let mut ret = PluginizableConnectionDummy(
-            PluginizableConnection::new_pluginizable_connection(exports_func, conn),
+            PluginizableConnection::new_pluginizable_connection_with_registry(
+                exports_func,
+                conn,
+                registry,
+            ),
         );
         let pc_ptr = ret.0.as_mut() as *mut _;
         ret.0.get_conn_mut().set_pluginizable_connection(pc_ptr);
@@ -375,20 +401,36 @@ impl DerefMut for PluginizableConnectionDummy {
 
 #[cfg(test)]
 mod tests {
-    use std::time::{Duration, Instant};
+    use std::{
+        cell::RefCell,
+        collections::HashSet,
+        rc::Rc,
+        sync::Arc,
+        time::{Duration, Instant},
+    };
 
     use pluginop::{
+        clock::VirtualClock,
         common::{
+            manifest::{PluginManifest, MANIFEST_SECTION},
             quic::{self, Frame, MaxDataFrame, QVal},
-            Anchor, PluginOp, PluginVal,
+            Anchor, ApiErrorCode, Bytes, PluginOp, PluginOpNameError, PluginVal, API_VERSION,
         },
+        config::PluginConfig,
+        handler::FailurePolicy,
         octets::{Octets, OctetsMut},
         plugin::Env,
-        Error, IntoWithPH,
+        record::{read_trace, NestedCall},
+        registry::PluginRegistry,
+        stats::CallKey,
+        Error, IntoWithPH, Permission, TryIntoWithPH,
     };
     use pluginop::{Exports, Function, FunctionEnv, FunctionEnvMut, Store};
 
-    use crate::{ConnectionDummy, PluginizableConnectionDummy};
+    use crate::{
+        replay::{ReplayConnection, Replayer},
+        ConnectionDummy, PluginizableConnectionDummy,
+    };
 
     fn add_one(_: FunctionEnvMut<Env<ConnectionDummy>>, x: u64) -> u64 {
         x + 1
@@ -683,7 +725,10 @@ mod tests {
         let (po, a) = PluginOp::from_name("launch_timers");
         assert!(pcd.0.get_ph().provides(&po, a));
         let ph = pcd.0.get_ph_mut();
-        let now = Instant::now();
+        // Use virtual time.
+        let clock = VirtualClock::new();
+        ph.set_clock(Arc::new(clock.clone()));
+        let now = ph.now();
         let pv = now.into_with_ph(ph);
         let res = ph.call(&po, &[pv]);
         assert!(res.is_ok());
@@ -692,9 +737,10 @@ mod tests {
         ph.on_timeout(now).unwrap();
         let first_timeout = now + Duration::from_millis(20);
         assert_eq!(ph.timeout(), Some(first_timeout));
-        // Use true time.
-        std::thread::sleep(first_timeout - Instant::now());
-        let now = Instant::now();
+        clock.advance_to(first_timeout);
+        let now = ph.now();
+        assert_eq!(now, first_timeout);
+        assert_eq!(now.into_with_ph(ph), PluginVal::UNIXInstant(ph.unix_now()));
         // Process timers.
         ph.on_timeout(now).unwrap();
         let (po, a) = PluginOp::from_name("check_success");
@@ -704,8 +750,8 @@ mod tests {
         assert!(res.is_ok());
         assert_eq!(*res.unwrap(), [PluginVal::Bool(false)]);
         // Wait for some success time.
-        std::thread::sleep(Duration::from_millis(40));
-        let now = Instant::now();
+        clock.advance(Duration::from_millis(40));
+        let now = ph.now();
         assert_eq!(ph.timeout(), None);
         // Should trigger nothing.
         ph.on_timeout(now).unwrap();
@@ -715,6 +761,299 @@ mod tests {
         assert_eq!(*res.unwrap(), [PluginVal::Bool(true)]);
     }
 
+    #[test]
+    fn periodic_timers() {
+        let mut pcd =
+            PluginizableConnectionDummy::new_pluginizable_connection(exports_func_external_test);
+        let clock = VirtualClock::new();
+        let ph = pcd.get_ph_mut();
+        ph.set_clock(Arc::new(clock.clone()));
+        let mut config = PluginConfig::default();
+        config.limits.timers = Some(2);
+        let path = "../tests/periodic-timers/periodic_timers.wasm".to_string();
+        assert!(ph.insert_plugin_with_config(&path.into(), &config).is_ok());
+        let path = "../tests/timer-usage/timer_usage.wasm".to_string();
+        assert!(ph.insert_plugin_testing(&path.into()).is_ok());
+        let start = ph.now();
+        let (arm, _) = PluginOp::from_name("arm_timers");
+        assert!(ph.call(&arm, &[]).is_ok());
+        let (launch, _) = PluginOp::from_name("launch_timers");
+        let pv = start.into_with_ph(ph);
+        assert!(ph.call(&launch, &[pv]).is_ok());
+        let (fired, _) = PluginOp::from_name("periodic_fired");
+
+        assert_eq!(ph.timeout(), Some(start + Duration::from_millis(10)));
+        clock.advance(Duration::from_millis(10));
+        assert!(ph.on_timeout(ph.now()).is_ok());
+        assert_eq!(*ph.call(&fired, &[]).unwrap(), [PluginVal::U64(1)]);
+        // The failing timer does not prevent the other plugin from being serviced.
+        clock.advance(Duration::from_millis(10));
+        assert!(ph.on_timeout(ph.now()).is_err());
+        assert_eq!(*ph.call(&fired, &[]).unwrap(), [PluginVal::U64(2)]);
+        // Missed periods are skipped, and the third firing cancels the periodic timer.
+        clock.advance(Duration::from_millis(25));
+        assert!(ph.on_timeout(ph.now()).is_ok());
+        assert_eq!(*ph.call(&fired, &[]).unwrap(), [PluginVal::U64(3)]);
+        clock.advance(Duration::from_millis(15));
+        assert!(ph.on_timeout(ph.now()).is_ok());
+        assert_eq!(ph.timeout(), None);
+        assert_eq!(*ph.call(&fired, &[]).unwrap(), [PluginVal::U64(3)]);
+        let (check, _) = PluginOp::from_name("check_success");
+        let pv = ph.now().into_with_ph(ph);
+        assert_eq!(*ph.call(&check, &[pv]).unwrap(), [PluginVal::Bool(true)]);
+    }
+
+    #[test]
+    fn bad_pointers() {
+        let mut pcd =
+            PluginizableConnectionDummy::new_pluginizable_connection(exports_func_external_test);
+        let path = "../tests/bad-pointers/bad_pointers.wasm".to_string();
+        assert!(pcd.get_ph_mut().insert_plugin_testing(&path.into()).is_ok());
+        let (po, _) = PluginOp::from_name("hammer");
+        let ph = pcd.get_ph_mut();
+        // Every API call with out-of-bounds memory fails without panicking the host.
+        assert_eq!(*ph.call(&po, &[PluginVal::U64(0)]).unwrap(), []);
+    }
+
+    #[test]
+    fn api_error_codes() {
+        let mut codes = HashSet::new();
+        for e in ApiErrorCode::ALL {
+            assert!(e.code() < 0);
+            assert!(codes.insert(e.code()));
+            assert_eq!(ApiErrorCode::from_code(e.code()), Some(e));
+        }
+        assert_eq!(ApiErrorCode::from_code(0), None);
+        // The refusals due to the policy of the plugin have their own range.
+        for e in [
+            ApiErrorCode::PermissionDenied,
+            ApiErrorCode::TooManyTimers,
+            ApiErrorCode::BadPointer,
+        ] {
+            assert!(e.code() <= -100);
+        }
+    }
+
+    #[test]
+    fn many_inputs() {
+        let mut pcd =
+            PluginizableConnectionDummy::new_pluginizable_connection(exports_func_external_test);
+        let path = "../tests/many-inputs/many_inputs.wasm".to_string();
+        assert!(pcd.get_ph_mut().insert_plugin_testing(&path.into()).is_ok());
+        let (po, _) = PluginOp::from_name("sum_inputs");
+        // The serialized inputs are much larger than the initial buffer of the plugin.
+        let inputs = vec![PluginVal::U64(u64::MAX); 200];
+        let res = pcd.get_ph_mut().call(&po, &inputs);
+        assert_eq!(
+            *res.unwrap(),
+            [
+                PluginVal::U64(200),
+                PluginVal::U64(u64::MAX.wrapping_mul(200))
+            ]
+        );
+        // So are the outputs of the nested call, fetched without running it again.
+        let (po, _) = PluginOp::from_name("sum_nested");
+        let res = pcd.get_ph_mut().call(&po, &inputs);
+        assert_eq!(
+            *res.unwrap(),
+            [
+                PluginVal::U64(200),
+                PluginVal::U64(u64::MAX.wrapping_mul(200)),
+                PluginVal::U64(1)
+            ]
+        );
+        // A buffer that cannot even hold the length of the inputs gets its own error.
+        let (po, _) = PluginOp::from_name("tiny_buffer");
+        assert!(pcd.get_ph_mut().call(&po, &inputs).is_ok());
+    }
+
+    #[test]
+    fn recovery_fields() {
+        let mut pcd =
+            PluginizableConnectionDummy::new_pluginizable_connection(exports_func_external_test);
+        pcd.conn.srtt = Duration::from_millis(42);
+        let path = "../tests/recovery-fields/recovery_fields.wasm".to_string();
+        assert!(pcd.get_ph_mut().insert_plugin_testing(&path.into()).is_ok());
+        let (po, _) = PluginOp::from_name("read_recovery");
+        // Accesses to fields that the connection does not provide are reported to the plugin as
+        // not found.
+        let res = pcd.get_ph_mut().call(&po, &[]);
+        assert_eq!(
+            *res.unwrap(),
+            [
+                PluginVal::Duration(Duration::from_millis(42)),
+                PluginVal::U64(7)
+            ]
+        );
+    }
+
+    #[test]
+    fn structured_values() {
+        let mut pcd =
+            PluginizableConnectionDummy::new_pluginizable_connection(exports_func_external_test);
+        let path = "../tests/structured-values/structured_values.wasm".to_string();
+        assert!(pcd.get_ph_mut().insert_plugin_testing(&path.into()).is_ok());
+        let (po, _) = PluginOp::from_name("describe");
+        let ph = pcd.get_ph_mut();
+        let inputs = [
+            String::from("pns").into_with_ph(ph),
+            PluginVal::from(vec![1u64, 5, 3]),
+            Some(4u64).into_with_ph(ph),
+            PluginVal::ByteVec(vec![1, 2, 3]),
+        ];
+        let outputs = ph.call(&po, &inputs).unwrap().to_vec();
+        let reason: String = outputs[0].clone().try_into_with_ph(ph).unwrap();
+        assert_eq!(reason, "pns: 3 packets");
+        let largest: Option<u64> = outputs[1].clone().try_into_with_ph(ph).unwrap();
+        assert_eq!(largest, Some(5));
+        assert_eq!(outputs[2], PluginVal::ByteVec(vec![3, 2, 1]));
+        let none: Option<u64> = PluginVal::Option(None).try_into_with_ph(ph).unwrap();
+        assert_eq!(none, None);
+        // A `Bytes` token is not its content.
+        let token = PluginVal::Bytes(Bytes {
+            tag: 0,
+            max_read_len: 0,
+            max_write_len: 0,
+        });
+        let res: Result<Option<Vec<u8>>, _> = token.try_into_with_ph(ph);
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn insert_from_bytes() {
+        let wasm = std::fs::read("../tests/increase-max-data/increase_max_data.wasm").unwrap();
+        let mut pcd =
+            PluginizableConnectionDummy::new_pluginizable_connection(exports_func_external_test);
+        assert!(pcd.get_ph_mut().insert_plugin_from_bytes(&wasm).is_ok());
+        let po = PluginOp::ProcessFrame(0x10);
+        // Not enabled yet, but the plugin is there.
+        assert!(!pcd.get_ph().provides(&po, Anchor::Define));
+        assert!(pcd
+            .get_ph_mut()
+            .insert_plugin_from_bytes(&[0, 1, 2])
+            .is_err());
+
+        // Now with a pre-compiled artifact.
+        let mut pcd =
+            PluginizableConnectionDummy::new_pluginizable_connection(exports_func_external_test);
+        let artifact = pcd.get_ph().precompile_plugin(&wasm).unwrap();
+        // SAFETY: the artifact was just produced by the same engine.
+        let ok = unsafe { pcd.get_ph_mut().insert_plugin_from_serialized(&artifact) };
+        assert!(ok.is_ok());
+        assert_eq!(pcd.get_ph().plugin_ids(), vec![ok.unwrap()]);
+    }
+
+    #[test]
+    fn shared_registry() {
+        let registry = Arc::new(PluginRegistry::new());
+        let path = "../tests/increase-max-data/increase_max_data.wasm".to_string();
+        let mut pcds = Vec::new();
+        for _ in 0..3 {
+            let mut pcd = PluginizableConnectionDummy::new_pluginizable_connection_with_registry(
+                exports_func_external_test,
+                registry.clone(),
+            );
+            let ok = pcd.get_ph_mut().insert_plugin_testing(&path.clone().into());
+            assert!(ok.is_ok());
+            pcds.push(pcd);
+        }
+        // The plugin was compiled only once.
+        assert_eq!(registry.len(), 1);
+        // But each connection has its own instance.
+        for (i, pcd) in pcds.iter_mut().enumerate() {
+            let maximum_data = 4000 + i as u64;
+            pcd.recv_frame(Frame::MaxData(MaxDataFrame { maximum_data }));
+            assert_eq!(pcd.conn.max_tx_data, maximum_data);
+        }
+        registry.clear();
+        assert!(registry.is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "cranelift")]
+    fn tiered_registry() {
+        use pluginop::registry::{Compiler, RegistryConfig, Tiering};
+
+        let registry = Arc::new(PluginRegistry::with_config(RegistryConfig {
+            compiler: Compiler::Singlepass,
+            tiering: Some(Tiering {
+                compiler: Compiler::Cranelift,
+                instantiation_threshold: 2,
+            }),
+        }));
+        let wasm = std::fs::read("../tests/increase-max-data/increase_max_data.wasm").unwrap();
+        let mut pcds = Vec::new();
+        for i in 0..3 {
+            if i == 2 {
+                // The plugin got hot at its second insertion.
+                registry.wait_tier_ups();
+                assert_eq!(
+                    registry.get_or_compile(&wasm).unwrap().compiler(),
+                    Compiler::Cranelift
+                );
+            }
+            let mut pcd = PluginizableConnectionDummy::new_pluginizable_connection_with_registry(
+                exports_func_external_test,
+                registry.clone(),
+            );
+            let ok = pcd.get_ph_mut().insert_plugin_from_bytes(&wasm);
+            assert!(ok.is_ok());
+            pcds.push(pcd);
+        }
+        assert_eq!(registry.len(), 1);
+        // Both the singlepass and the cranelift instances behave the same.
+        for (i, pcd) in pcds.iter_mut().enumerate() {
+            let maximum_data = 4000 + i as u64;
+            pcd.recv_frame(Frame::MaxData(MaxDataFrame { maximum_data }));
+            assert_eq!(pcd.conn.max_tx_data, maximum_data);
+        }
+    }
+
+    #[test]
+    fn remove_plugin() {
+        let mut pcd =
+            PluginizableConnectionDummy::new_pluginizable_connection(exports_func_external_test);
+        let path = "../tests/max-data-frame/max_data_frame.wasm".to_string();
+        let md_id = pcd
+            .get_ph_mut()
+            .insert_plugin_testing(&path.into())
+            .unwrap();
+        let path = "../tests/timer-usage/timer_usage.wasm".to_string();
+        let timer_id = pcd
+            .get_ph_mut()
+            .insert_plugin_testing(&path.into())
+            .unwrap();
+        assert_eq!(pcd.get_ph().plugin_ids(), vec![md_id, timer_id]);
+        assert_eq!(pcd.get_ph().get_registrations().len(), 1);
+        let po = PluginOp::ShouldSendFrame(0x10);
+        assert!(pcd.get_ph().provides(&po, Anchor::Define));
+
+        // Arm some timers in the second plugin.
+        let (launch, a) = PluginOp::from_name("launch_timers");
+        let ph = pcd.get_ph_mut();
+        let pv = Instant::now().into_with_ph(ph);
+        assert!(ph.call(&launch, &[pv]).is_ok());
+        assert!(ph.provides(&launch, a));
+        assert!(ph.timeout().is_some());
+
+        // Removing the first plugin withdraws its registration and its operations.
+        assert!(ph.remove_plugin(md_id).is_ok());
+        assert_eq!(ph.plugin_ids(), vec![timer_id]);
+        assert!(ph.get_registrations().is_empty());
+        assert!(!ph.provides(&po, Anchor::Define));
+        assert!(matches!(ph.remove_plugin(md_id), Err(Error::UnknownPlugin(id)) if id == md_id));
+
+        // Removing the second one cancels its timers.
+        assert!(ph.remove_plugin(timer_id).is_ok());
+        assert!(ph.plugin_ids().is_empty());
+        assert!(!ph.provides(&launch, a));
+        assert_eq!(ph.timeout(), None);
+
+        // The host implementation takes over again.
+        pcd.recv_frame(Frame::MaxData(MaxDataFrame { maximum_data: 1000 }));
+        assert_eq!(pcd.conn.max_tx_data, 1000);
+    }
+
     #[test]
     fn poctl() {
         let mut pcd =
@@ -724,7 +1063,7 @@ mod tests {
         assert!(ok.is_ok());
         let ph = pcd.0.get_ph_mut();
         let (one, two) = (1_i64.into_with_ph(ph), 2_i64.into_with_ph(ph));
-        let res = ph.poctl(1, &[one, two]);
+        let res = ph.poctl(1, &[one.clone(), two.clone()]);
         assert!(res.is_ok());
         assert_eq!(*res.unwrap(), [PluginVal::I64(3)]);
         let res = ph.poctl(2, &[one, two]);
@@ -736,6 +1075,801 @@ mod tests {
         assert!(res.is_ok());
     }
 
+    #[test]
+    fn budget_exceeded() {
+        let mut pcd =
+            PluginizableConnectionDummy::new_pluginizable_connection(exports_func_external_test);
+        let path = "../tests/infinite-loop/infinite_loop.wasm".to_string();
+        let ok = pcd.get_ph_mut().insert_plugin_testing(&path.into());
+        assert!(ok.is_ok());
+        let ph = pcd.0.get_ph_mut();
+        ph.set_call_budget(Some(100_000));
+        ph.set_timer_budget(Some(100_000));
+        assert!(matches!(ph.poctl(1, &[]), Err(Error::BudgetExceeded)));
+        // The budget is restored for the next call.
+        assert!(ph.poctl(2, &[]).is_ok());
+        // Same for timer callbacks.
+        let now = Instant::now();
+        let pv = now.into_with_ph(ph);
+        assert!(ph.poctl(3, &[pv]).is_ok());
+        assert!(matches!(ph.on_timeout(now), Err(Error::BudgetExceeded)));
+        assert_eq!(ph.timeout(), None);
+        assert!(ph.poctl(2, &[]).is_ok());
+        // A budget too small for a legitimate call.
+        ph.set_call_budget(Some(10));
+        assert!(matches!(ph.poctl(2, &[]), Err(Error::BudgetExceeded)));
+    }
+
+    #[test]
+    fn failing_init() {
+        let mut pcd =
+            PluginizableConnectionDummy::new_pluginizable_connection(exports_func_external_test);
+        let path = "../tests/memory-hog/memory_hog.wasm".to_string();
+        // The budget does not allow the `init` function to complete.
+        let ph = pcd.get_ph_mut();
+        ph.set_call_budget(Some(1));
+        let res = ph.insert_plugin(&path.clone().into());
+        assert!(matches!(res, Err(Error::PluginLoadingError(_))));
+        // The plugin is not kept around.
+        assert!(ph.plugin_ids().is_empty());
+        // But can be inserted again once its `init` succeeds.
+        ph.set_call_budget(None);
+        let id = ph.insert_plugin(&path.into()).unwrap();
+        assert_eq!(ph.plugin_ids(), vec![id]);
+    }
+
+    #[test]
+    fn memory_limits() {
+        let mut pcd =
+            PluginizableConnectionDummy::new_pluginizable_connection(exports_func_external_test);
+        let path = "../tests/memory-hog/memory_hog.wasm".to_string();
+        // The plugin needs more than a single page to start.
+        let mut config = PluginConfig::default();
+        config.limits.memory_pages = Some(1);
+        let res = pcd
+            .get_ph_mut()
+            .insert_plugin_with_config(&path.clone().into(), &config);
+        assert!(matches!(res, Err(Error::PluginLoadingError(_))));
+        // With 2 MiB, small allocations succeed, but not larger ones.
+        config.limits.memory_pages = Some(32);
+        let res = pcd
+            .get_ph_mut()
+            .insert_plugin_with_config(&path.into(), &config);
+        assert!(res.is_ok());
+        let ph = pcd.0.get_ph_mut();
+        let small = (64 * 1024_u64).into_with_ph(ph);
+        assert!(ph.poctl(1, &[small]).is_ok());
+        let large = (4 * 1024 * 1024_u64).into_with_ph(ph);
+        assert!(matches!(
+            ph.poctl(1, &[large]),
+            Err(Error::MemoryLimitExceeded)
+        ));
+    }
+
+    #[test]
+    fn stack_limit() {
+        let mut pcd =
+            PluginizableConnectionDummy::new_pluginizable_connection(exports_func_external_test);
+        let path = "../tests/deep-recursion/deep_recursion.wasm".to_string();
+        let mut config = PluginConfig::default();
+        config.limits.stack_size = Some(256 * 1024);
+        let res = pcd
+            .get_ph_mut()
+            .insert_plugin_with_config(&path.into(), &config);
+        assert!(res.is_ok());
+        let ph = pcd.0.get_ph_mut();
+        let shallow = 16_u64.into_with_ph(ph);
+        assert!(ph.poctl(1, &[shallow.clone()]).is_ok());
+        // Exhausting the stack fails the call instead of crashing the host.
+        let deep = 1_000_000_u64.into_with_ph(ph);
+        assert!(matches!(ph.poctl(1, &[deep]), Err(Error::RuntimeError(_))));
+        assert!(ph.poctl(1, &[shallow]).is_ok());
+    }
+
+    #[test]
+    fn table_limit() {
+        let mut pcd =
+            PluginizableConnectionDummy::new_pluginizable_connection(exports_func_external_test);
+        let path = "../tests/deep-recursion/deep_recursion.wasm".to_string();
+        // The plugin needs a table of 5 elements.
+        let mut config = PluginConfig::default();
+        config.limits.table_elements = Some(4);
+        let res = pcd
+            .get_ph_mut()
+            .insert_plugin_with_config(&path.clone().into(), &config);
+        assert!(matches!(res, Err(Error::PluginLoadingError(_))));
+        config.limits.table_elements = Some(5);
+        let res = pcd
+            .get_ph_mut()
+            .insert_plugin_with_config(&path.into(), &config);
+        assert!(res.is_ok());
+        let ph = pcd.0.get_ph_mut();
+        let depth = 16_u64.into_with_ph(ph);
+        assert!(ph.poctl(1, &[depth]).is_ok());
+    }
+
+    #[test]
+    fn permissions() {
+        let mut pcd =
+            PluginizableConnectionDummy::new_pluginizable_connection(exports_func_external_test);
+        let path = "../tests/permissions/permissions.wasm".to_string();
+        let mut config = PluginConfig::default();
+        config.permissions.remove(&Permission::ConnectionAccess);
+        config.permissions.remove(&Permission::FileSystem);
+        config.permissions.remove(&Permission::Timers);
+        config.permissions.remove(&Permission::NestedCalls);
+        let res = pcd
+            .get_ph_mut()
+            .insert_plugin_with_config(&path.clone().into(), &config);
+        assert!(res.is_ok());
+        let ph = pcd.0.get_ph_mut();
+        assert!(matches!(ph.poctl(1, &[]), Err(Error::OperationError(-1))));
+        assert!(matches!(ph.poctl(2, &[]), Err(Error::OperationError(-1))));
+        assert!(matches!(ph.poctl(3, &[]), Err(Error::OperationError(-1))));
+        assert!(matches!(ph.poctl(4, &[]), Err(Error::OperationError(-1))));
+        assert_eq!(pcd.conn.max_tx_data, 2000);
+        // Once granted, the connection can be modified.
+        let mut pcd =
+            PluginizableConnectionDummy::new_pluginizable_connection(exports_func_external_test);
+        config.permissions.insert(Permission::ConnectionAccess);
+        config.permissions.insert(Permission::Timers);
+        config.permissions.insert(Permission::NestedCalls);
+        let res = pcd
+            .get_ph_mut()
+            .insert_plugin_with_config(&path.into(), &config);
+        assert!(res.is_ok());
+        assert!(pcd.0.get_ph_mut().poctl(1, &[]).is_ok());
+        assert_eq!(pcd.conn.max_tx_data, 5000);
+        assert!(pcd.0.get_ph_mut().poctl(3, &[]).is_ok());
+        assert!(pcd.0.get_ph_mut().poctl(4, &[]).is_ok());
+    }
+
+    /// Append a custom section with the provided name and content to a WebAssembly module.
+    fn append_custom_section(wasm: &mut Vec<u8>, name: &str, content: &[u8]) {
+        // Only handles sections shorter than 128 bytes, to keep LEB128 encoding trivial.
+        let len = 1 + name.len() + content.len();
+        assert!(len < 128);
+        wasm.extend_from_slice(&[0, len as u8, name.len() as u8]);
+        wasm.extend_from_slice(name.as_bytes());
+        wasm.extend_from_slice(content);
+    }
+
+    #[test]
+    fn manifest() {
+        let mut pcd =
+            PluginizableConnectionDummy::new_pluginizable_connection(exports_func_external_test);
+        let path = "../tests/manifest/manifest.wasm".to_string();
+        let id = pcd.get_ph_mut().insert_plugin(&path.into()).unwrap();
+        let ph = pcd.0.get_ph_mut();
+        let manifest = ph.get_manifest(id).unwrap();
+        assert_eq!(manifest.name, "manifest");
+        assert_eq!(manifest.version, "0.1.0");
+        assert_eq!(manifest.author, "pluginop");
+        assert_eq!(manifest.api_version, API_VERSION);
+        assert_eq!(
+            manifest.permissions,
+            Some(vec![Permission::Output, Permission::Opaque])
+        );
+        // The plugin did not request to access the connection.
+        assert!(matches!(ph.poctl(1, &[]), Err(Error::OperationError(-1))));
+
+        // Plugins without manifest can still be loaded.
+        let mut wasm = std::fs::read("../tests/increase-max-data/increase_max_data.wasm").unwrap();
+        let id = ph.insert_plugin_from_bytes(&wasm).unwrap();
+        assert!(ph.get_manifest(id).is_none());
+
+        // But not plugins targeting another API version.
+        let manifest = PluginManifest {
+            name: "future".to_string(),
+            api_version: API_VERSION + 1,
+            ..Default::default()
+        };
+        append_custom_section(&mut wasm, MANIFEST_SECTION, manifest.to_string().as_bytes());
+        assert!(matches!(
+            ph.insert_plugin_from_bytes(&wasm),
+            Err(Error::IncompatibleApiVersion(v)) if v == API_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn invalid_export_names() {
+        assert_eq!(
+            PluginOp::try_from_name("pre_parse_frame_1f"),
+            Ok((PluginOp::ParseFrame(0x1f), Anchor::Before))
+        );
+        assert_eq!(
+            PluginOp::try_from_name("parse_frame_zz"),
+            Err(PluginOpNameError::InvalidParameter(
+                "parse_frame_zz".to_string()
+            ))
+        );
+        let long_name = "a_very_long_export_name_exceeding_32_bytes";
+        assert_eq!(
+            PluginOp::try_from_name(long_name),
+            Err(PluginOpNameError::NameTooLong(long_name.to_string()))
+        );
+
+        // The host rejects such plugins instead of crashing.
+        let mut pcd =
+            PluginizableConnectionDummy::new_pluginizable_connection(exports_func_external_test);
+        let path = "../tests/bad-export/bad_export.wasm".to_string();
+        let res = pcd.get_ph_mut().insert_plugin(&path.into());
+        assert!(matches!(res, Err(Error::PluginLoadingError(_))));
+        assert!(pcd.get_ph().plugin_ids().is_empty());
+    }
+
+    #[test]
+    fn priorities() {
+        let mut pcd =
+            PluginizableConnectionDummy::new_pluginizable_connection(exports_func_external_test);
+        let static_memory = std::fs::read("../tests/static-memory/static_memory.wasm").unwrap();
+        let inputs_support = std::fs::read("../tests/inputs-support/inputs_support.wasm").unwrap();
+        let ph = pcd.get_ph_mut();
+        let id1 = ph.insert_plugin_from_bytes(&static_memory).unwrap();
+        let id2 = ph.insert_plugin_from_bytes(&inputs_support).unwrap();
+        // Same priority, so insertion order.
+        assert_eq!(ph.plugin_ids(), vec![id1, id2]);
+        let config = PluginConfig {
+            priority: Some(1),
+            ..Default::default()
+        };
+        let id3 = ph
+            .insert_plugin_from_bytes_with_config(&inputs_support, &config)
+            .unwrap();
+        assert_eq!(ph.plugin_ids(), vec![id3, id1, id2]);
+
+        // The manifest can state a priority, that the host configuration overrides.
+        let manifest = PluginManifest {
+            name: "static-memory".to_string(),
+            api_version: API_VERSION,
+            priority: Some(2),
+            ..Default::default()
+        };
+        let mut wasm = static_memory.clone();
+        append_custom_section(&mut wasm, MANIFEST_SECTION, manifest.to_string().as_bytes());
+        let id4 = ph.insert_plugin_from_bytes(&wasm).unwrap();
+        let config = PluginConfig {
+            priority: Some(-1),
+            ..Default::default()
+        };
+        let id5 = ph
+            .insert_plugin_from_bytes_with_config(&wasm, &config)
+            .unwrap();
+        assert_eq!(ph.plugin_ids(), vec![id4, id3, id1, id2, id5]);
+
+        let conflicts = ph.conflicts();
+        assert_eq!(conflicts.len(), 2);
+        for (_, ids) in &conflicts {
+            assert_eq!(ids, &vec![id4, id3, id1, id2, id5]);
+        }
+        ph.remove_plugin(id1).unwrap();
+        ph.remove_plugin(id4).unwrap();
+        ph.remove_plugin(id5).unwrap();
+        ph.remove_plugin(id2).unwrap();
+        assert!(ph.conflicts().is_empty());
+    }
+
+    #[test]
+    fn call_default() {
+        let mut pcd =
+            PluginizableConnectionDummy::new_pluginizable_connection(exports_func_external_test);
+        let path = "../tests/call-default/call_default.wasm".to_string();
+        let id = pcd
+            .get_ph_mut()
+            .insert_plugin_testing(&path.into())
+            .unwrap();
+        // The host sets the RTT, with the input modified by the plugin.
+        pcd.update_rtt(
+            Duration::from_millis(125),
+            Duration::from_millis(10),
+            Instant::now(),
+        );
+        assert_eq!(pcd.conn.srtt, Duration::from_millis(250));
+
+        // The host parses the frame from its buffer, and the plugin tweaks the result.
+        let mut orig_buf = [0; 8];
+        let mut buf = OctetsMut::with_slice(&mut orig_buf);
+        buf.put_varint(0x2000).unwrap();
+        let mut buf = Octets::with_slice(&orig_buf);
+        let res = pcd
+            .conn
+            .parse_frame(0x10, &mut buf, quic::PacketType::Short);
+        assert!(matches!(
+            res,
+            Ok(Frame::MaxData(MaxDataFrame {
+                maximum_data: 0x2001
+            }))
+        ));
+        assert_eq!(buf.off(), 2);
+        // Host errors are reported to the plugin.
+        let mut buf = Octets::with_slice(&orig_buf);
+        let res = pcd
+            .conn
+            .parse_frame(0x10, &mut buf, quic::PacketType::Initial);
+        assert!(res.is_err());
+
+        // Byte vectors given to the plugin can be passed back to the host.
+        assert_eq!(pcd.conn.log_frame(0x10, vec![1, 2, 3]), 3);
+
+        // Outside of a host invocation, there is nothing to fall back to.
+        let ph = pcd.get_ph_mut();
+        assert!(matches!(
+            ph.call_default(&[]),
+            Err(Error::NoDefaultImplementation)
+        ));
+        assert_eq!(ph.poctl(1, &[]).unwrap(), vec![PluginVal::Bool(false)]);
+
+        // Nor once a panicking host implementation unwound.
+        let params = [PluginVal::Duration(Duration::from_millis(125))];
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            ph.call_with_default(&PluginOp::UpdateRtt, &params, &mut |_, _| {
+                panic!("buggy host")
+            })
+        }));
+        assert!(res.is_err());
+        assert!(matches!(
+            ph.call_default(&[]),
+            Err(Error::NoDefaultImplementation)
+        ));
+
+        // And the plugin gets its budget again.
+        ph.set_call_budget(Some(1));
+        assert!(matches!(
+            ph.call_plugin(id, &PluginOp::UpdateRtt, Anchor::Define, &params),
+            Err(Error::BudgetExceeded)
+        ));
+        ph.set_call_budget(Some(1_000_000));
+        pcd.update_rtt(
+            Duration::from_millis(100),
+            Duration::from_millis(10),
+            Instant::now(),
+        );
+        assert_eq!(pcd.conn.srtt, Duration::from_millis(200));
+    }
+
+    #[test]
+    fn wrap_and_after() {
+        let mut pcd =
+            PluginizableConnectionDummy::new_pluginizable_connection(exports_func_external_test);
+        let path = "../tests/wrap/wrap.wasm".to_string();
+        let ok = pcd.get_ph_mut().insert_plugin_testing(&path.into());
+        assert!(ok.is_ok());
+        // The wrap anchor rewrites the result of the plugin, and the after one observes it.
+        let res = pcd.get_ph_mut().poctl(1, &[]);
+        assert_eq!(*res.unwrap(), [PluginVal::U64(42)]);
+        assert_eq!(pcd.conn.max_tx_data, 42);
+
+        // Same for host implementations.
+        let (pkt_type, epoch) = (
+            quic::PacketType::Short,
+            quic::KPacketNumberSpace::ApplicationData,
+        );
+        assert!(pcd
+            .conn
+            .should_send_frame(0x10, pkt_type, epoch, false, 1000));
+        assert_eq!(pcd.conn.max_tx_data, 1001);
+        assert!(pcd
+            .conn
+            .should_send_frame(0x11, pkt_type, epoch, false, 1000));
+        assert!(!pcd
+            .conn
+            .should_send_frame(0x12, pkt_type, epoch, false, 1000));
+
+        // Outputs not matching the result are discarded and reported.
+        let failures = Rc::new(RefCell::new(Vec::new()));
+        let failures_cb = failures.clone();
+        pcd.get_ph_mut().set_anchor_failure_callback(move |f| {
+            failures_cb
+                .borrow_mut()
+                .push((f.po, f.anchor, format!("{:?}", f.error)))
+        });
+        assert!(!pcd
+            .conn
+            .should_send_frame(0x13, pkt_type, epoch, false, 1000));
+        assert_eq!(pcd.conn.max_tx_data, 1000);
+        assert_eq!(
+            *failures.borrow(),
+            [(
+                PluginOp::ShouldSendFrame(0x13),
+                Anchor::Wrap,
+                "InvalidOutputs".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn failure_policy() {
+        let (pkt_type, epoch) = (
+            quic::PacketType::Short,
+            quic::KPacketNumberSpace::ApplicationData,
+        );
+        let frame = Frame::MaxData(MaxDataFrame {
+            maximum_data: 0x2000,
+        });
+        let path = "../tests/trap/trap.wasm".to_string();
+
+        // By default, a failing plugin brings down the host.
+        let mut pcd =
+            PluginizableConnectionDummy::new_pluginizable_connection(exports_func_external_test);
+        assert!(pcd
+            .get_ph_mut()
+            .insert_plugin_testing(&path.clone().into())
+            .is_ok());
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            pcd.conn
+                .should_send_frame(0x12, pkt_type, epoch, false, 1000)
+        }));
+        assert!(res.is_err());
+
+        // But the host implementation can take over.
+        let mut pcd =
+            PluginizableConnectionDummy::new_pluginizable_connection(exports_func_external_test);
+        let id = pcd
+            .get_ph_mut()
+            .insert_plugin_testing(&path.clone().into())
+            .unwrap();
+        let failures = Rc::new(RefCell::new(Vec::new()));
+        let failures_cb = failures.clone();
+        let ph = pcd.get_ph_mut();
+        ph.set_failure_policy(FailurePolicy::Fallback);
+        ph.set_failure_callback(move |f| {
+            failures_cb
+                .borrow_mut()
+                .push((f.po, f.plugin, format!("{:?}", f.error)))
+        });
+        assert!(!pcd
+            .conn
+            .should_send_frame(0x12, pkt_type, epoch, false, 1000));
+        assert_eq!(pcd.conn.wire_len(0x10, frame), 3);
+        {
+            let failures = failures.borrow();
+            assert_eq!(failures.len(), 2);
+            assert_eq!(failures[0].0, PluginOp::ShouldSendFrame(0x12));
+            assert_eq!(failures[0].1, Some(id));
+            assert!(failures[0].2.starts_with("RuntimeError"));
+            assert_eq!(failures[1].0, PluginOp::WireLen(0x10));
+            assert_eq!(failures[1].1, Some(id));
+            assert_eq!(failures[1].2, "InvalidOutputs");
+        }
+        assert!(!pcd.get_ph().is_quarantined(id));
+
+        // Or the failing plugin can be put aside.
+        pcd.get_ph_mut()
+            .set_failure_policy(FailurePolicy::Quarantine);
+        assert!(!pcd
+            .conn
+            .should_send_frame(0x12, pkt_type, epoch, false, 1000));
+        assert!(pcd.get_ph().is_quarantined(id));
+        assert!(!pcd
+            .get_ph()
+            .provides(&PluginOp::WireLen(0x10), Anchor::Define));
+        assert_eq!(pcd.conn.wire_len(0x10, frame), 3);
+        assert_eq!(failures.borrow().len(), 3);
+    }
+
+    #[test]
+    fn anchor_failures() {
+        let epoch = quic::KPacketNumberSpace::ApplicationData;
+        let mut pcd =
+            PluginizableConnectionDummy::new_pluginizable_connection(exports_func_external_test);
+        let path = "../tests/failing-anchors/failing_anchors.wasm".to_string();
+        let id = pcd
+            .get_ph_mut()
+            .insert_plugin_testing(&path.into())
+            .unwrap();
+        let failures = Rc::new(RefCell::new(Vec::new()));
+        let failures_cb = failures.clone();
+        pcd.get_ph_mut().set_anchor_failure_callback(move |f| {
+            failures_cb
+                .borrow_mut()
+                .push((f.po, f.anchor, f.plugin, format!("{:?}", f.error)))
+        });
+
+        // By default, failing anchors do not affect the operation.
+        assert!(pcd.conn.prepare_frame(0x10, epoch, 1000).is_ok());
+        {
+            let failures = failures.borrow();
+            assert_eq!(failures.len(), 2);
+            assert_eq!(
+                failures[0],
+                (
+                    PluginOp::PrepareFrame(0x10),
+                    Anchor::Before,
+                    Some(id),
+                    "OperationError(7)".to_string()
+                )
+            );
+            assert_eq!(failures[1].1, Anchor::After);
+            assert_eq!(failures[1].2, Some(id));
+            assert!(failures[1].3.starts_with("RuntimeError"));
+        }
+
+        // In strict mode, a failing `Before` anchor aborts the operation.
+        pcd.get_ph_mut().set_strict_anchors(true);
+        assert!(pcd.conn.prepare_frame(0x10, epoch, 1000).is_err());
+        assert_eq!(failures.borrow().len(), 3);
+        assert_eq!(failures.borrow()[2].1, Anchor::Before);
+
+        // Even if it traps, regardless of the failure policy.
+        let mut orig_buf = [0; 8];
+        let mut buf = OctetsMut::with_slice(&mut orig_buf);
+        let frame = Frame::MaxData(MaxDataFrame {
+            maximum_data: 0x2000,
+        });
+        assert!(pcd.conn.write_frame(0x10, frame, &mut buf).is_err());
+        assert_eq!(buf.off(), 0);
+        {
+            let failures = failures.borrow();
+            assert_eq!(failures.len(), 4);
+            assert_eq!(failures[3].0, PluginOp::WriteFrame(0x10));
+            assert_eq!(failures[3].1, Anchor::Before);
+            assert!(failures[3].3.starts_with("RuntimeError"));
+        }
+        pcd.get_ph_mut().set_failure_policy(FailurePolicy::Fallback);
+        assert!(pcd.conn.write_frame(0x10, frame, &mut buf).is_err());
+        assert_eq!(failures.borrow().len(), 5);
+
+        // All the anchors run, and the first failure is returned, whatever the mode.
+        pcd.get_ph_mut().set_strict_anchors(false);
+        let path = "../tests/failing-anchors/failing_anchors.wasm".to_string();
+        let id2 = pcd
+            .get_ph_mut()
+            .insert_plugin_testing(&path.into())
+            .unwrap();
+        let res = pcd
+            .get_ph_mut()
+            .call_direct(&PluginOp::PrepareFrame(0x10), Anchor::Before, &[]);
+        assert!(matches!(res, Err(Error::OperationError(7))));
+        {
+            let failures = failures.borrow();
+            assert_eq!(failures.len(), 7);
+            assert_eq!(failures[5].2, Some(id));
+            assert_eq!(failures[6].2, Some(id2));
+        }
+        assert!(pcd.conn.prepare_frame(0x10, epoch, 1000).is_ok());
+        assert_eq!(failures.borrow().len(), 11);
+
+        // Only `Before` and `After` anchors can be called directly.
+        let res = pcd
+            .get_ph_mut()
+            .call_direct(&PluginOp::PrepareFrame(0x10), Anchor::Define, &[]);
+        assert!(matches!(res, Err(Error::InternalError(_))));
+    }
+
+    #[test]
+    fn stats() {
+        let epoch = quic::KPacketNumberSpace::ApplicationData;
+        let mut pcd =
+            PluginizableConnectionDummy::new_pluginizable_connection(exports_func_external_test);
+        pcd.get_ph_mut().set_stats_enabled(true);
+        let path = "../tests/failing-anchors/failing_anchors.wasm".to_string();
+        let id = pcd
+            .get_ph_mut()
+            .insert_plugin_testing(&path.into())
+            .unwrap();
+        for _ in 0..3 {
+            assert!(pcd.conn.prepare_frame(0x10, epoch, 1000).is_ok());
+        }
+
+        let stats = pcd.get_ph().stats();
+        assert_eq!(stats.len(), 2);
+        let key = |anchor| CallKey {
+            plugin: id,
+            po: PluginOp::PrepareFrame(0x10),
+            anchor,
+        };
+        let before = &stats[&key(Anchor::Before)];
+        assert_eq!(
+            (before.calls, before.operation_errors, before.failures),
+            (3, 3, 0)
+        );
+        let after = &stats[&key(Anchor::After)];
+        assert_eq!(
+            (after.calls, after.operation_errors, after.failures),
+            (3, 0, 3)
+        );
+        assert_eq!(after.latencies.count(), 3);
+        assert!(after.mean_time().unwrap() <= after.total_time);
+        assert!(after.latencies.quantile(0.5).unwrap() <= after.latencies.quantile(1.0).unwrap());
+
+        pcd.get_ph_mut().reset_stats();
+        assert!(pcd.get_ph().stats().is_empty());
+        pcd.get_ph_mut().set_stats_enabled(false);
+        assert!(pcd.conn.prepare_frame(0x10, epoch, 1000).is_ok());
+        assert!(pcd.get_ph().stats().is_empty());
+    }
+
+    /// The level, target, message and key-values of a logged message.
+    type LoggedMessage = (log::Level, String, String, Vec<(String, String)>);
+
+    /// Captures the messages logged by the `logger` plugin.
+    struct PluginLogs(std::sync::Mutex<Vec<LoggedMessage>>);
+
+    /// Collects the key-values of a logged message.
+    struct KeyValues(Vec<(String, String)>);
+
+    impl<'kvs> log::kv::VisitSource<'kvs> for KeyValues {
+        fn visit_pair(
+            &mut self,
+            key: log::kv::Key<'kvs>,
+            value: log::kv::Value<'kvs>,
+        ) -> Result<(), log::kv::Error> {
+            self.0.push((key.to_string(), value.to_string()));
+            Ok(())
+        }
+    }
+
+    impl log::Log for PluginLogs {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            if record.target().starts_with("plugin::logger") {
+                let mut kvs = KeyValues(Vec::new());
+                record.key_values().visit(&mut kvs).unwrap();
+                self.0.lock().unwrap().push((
+                    record.level(),
+                    record.target().to_string(),
+                    record.args().to_string(),
+                    kvs.0,
+                ));
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    static PLUGIN_LOGS: PluginLogs = PluginLogs(std::sync::Mutex::new(Vec::new()));
+
+    #[test]
+    fn plugin_logging() {
+        log::set_logger(&PLUGIN_LOGS).unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+        let mut pcd =
+            PluginizableConnectionDummy::new_pluginizable_connection(exports_func_external_test);
+        let path = "../tests/plugin-log/plugin_log.wasm".to_string();
+        let id = pcd
+            .get_ph_mut()
+            .insert_plugin_testing(&path.into())
+            .unwrap();
+        let ph = pcd.get_ph_mut();
+        assert!(ph.poctl(1, &[]).is_ok());
+        assert_eq!(
+            *PLUGIN_LOGS.0.lock().unwrap(),
+            [
+                (
+                    log::Level::Info,
+                    "plugin::logger".to_string(),
+                    "[PluginControl(1)] hello host".to_string(),
+                    vec![]
+                ),
+                (
+                    log::Level::Debug,
+                    "plugin::logger".to_string(),
+                    "[PluginControl(1)] with fields".to_string(),
+                    vec![
+                        ("answer".to_string(), "42".to_string()),
+                        ("ok".to_string(), "true".to_string())
+                    ]
+                ),
+                (
+                    log::Level::Error,
+                    "plugin::logger::cc".to_string(),
+                    "[PluginControl(1)] something went wrong".to_string(),
+                    vec![]
+                ),
+            ]
+        );
+
+        // Less severe messages get filtered out.
+        PLUGIN_LOGS.0.lock().unwrap().clear();
+        assert!(ph.set_log_level(id, log::LevelFilter::Warn).is_ok());
+        assert!(ph.poctl(1, &[]).is_ok());
+        assert_eq!(PLUGIN_LOGS.0.lock().unwrap().len(), 1);
+        assert!(matches!(
+            ph.set_log_level(id + 1, log::LevelFilter::Off),
+            Err(Error::UnknownPlugin(_))
+        ));
+    }
+
+    fn exports_func_replay(_: &mut Store, _: &FunctionEnv<Env<ReplayConnection>>) -> Exports {
+        Exports::new()
+    }
+
+    #[test]
+    fn record_and_replay() {
+        let trace = std::env::temp_dir().join(format!("pluginop-trace-{}", std::process::id()));
+        let md_path = "../tests/max-data-frame/max_data_frame.wasm".to_string();
+        let imd_path = "../tests/increase-max-data/increase_max_data.wasm".to_string();
+        let mut pcd =
+            PluginizableConnectionDummy::new_pluginizable_connection(exports_func_external_test);
+        let ph = pcd.get_ph_mut();
+        let md_id = ph.insert_plugin_testing(&md_path.clone().into()).unwrap();
+        let imd_id = ph.insert_plugin_testing(&imd_path.clone().into()).unwrap();
+
+        // Record packets going back and forth, and a connection read.
+        assert!(ph.start_recording(&trace).is_ok());
+        assert!(ph.is_recording());
+        let mut orig_buf = [0; 1350];
+        let mut buf = OctetsMut::with_slice(&mut orig_buf);
+        assert_eq!(pcd.send_pkt(&mut buf, Some(false)), 3);
+        let mut buf = Octets::with_slice(&orig_buf[..3]);
+        assert!(pcd.recv_pkt(&mut buf, Instant::now()).is_ok());
+        let md_frame = Frame::MaxData(MaxDataFrame { maximum_data: 4000 });
+        let ph = pcd.get_ph_mut();
+        let po = PluginOp::ProcessFrame(0x10);
+        assert!(ph
+            .call_plugin(imd_id, &po, Anchor::Define, &[QVal::Frame(md_frame).into()])
+            .is_ok());
+        assert!(ph.stop_recording().is_ok());
+        assert!(!ph.is_recording());
+
+        let records = read_trace(&trace).unwrap();
+        std::fs::remove_file(&trace).unwrap();
+        let parse = records
+            .iter()
+            .find(|r| r.po == PluginOp::ParseFrame(0x10))
+            .unwrap();
+        assert_eq!(parse.plugin, md_id);
+        assert_eq!(parse.bytes.len(), 1);
+        assert_eq!(parse.bytes[0].1, [0x60, 0x00]);
+        let write = records
+            .iter()
+            .find(|r| r.po == PluginOp::WriteFrame(0x10))
+            .unwrap();
+        assert_eq!(write.bytes_written.len(), 1);
+        assert_eq!(write.bytes_written[0].1, [0x10, 0x60, 0x00]);
+        let process = records.last().unwrap();
+        assert_eq!((process.plugin, process.po), (imd_id, po));
+        assert_eq!(process.connection_reads.len(), 1);
+
+        // Replaying the plugins gives the same results.
+        let mut replayer = Replayer::new(exports_func_replay);
+        let md_replay_id = replayer.insert_plugin(&md_path.into()).unwrap();
+        let imd_replay_id = replayer.insert_plugin(&imd_path.into()).unwrap();
+        let md_calls = replayer.replay_trace(md_replay_id, &records, md_id);
+        assert_eq!(
+            md_calls.len(),
+            records.iter().filter(|r| r.plugin == md_id).count()
+        );
+        assert!(md_calls.iter().all(|c| c.matches()));
+        let imd_calls = replayer.replay_trace(imd_replay_id, &records, imd_id);
+        assert_eq!(imd_calls.len(), 1);
+        assert!(imd_calls[0].matches());
+
+        // The times and the outputs of the nested calls are replayed too.
+        let cd_path = "../tests/call-default/call_default.wasm".to_string();
+        let mut pcd =
+            PluginizableConnectionDummy::new_pluginizable_connection(exports_func_external_test);
+        let ph = pcd.get_ph_mut();
+        let cd_id = ph.insert_plugin_testing(&cd_path.clone().into()).unwrap();
+        assert!(ph.start_recording(&trace).is_ok());
+        pcd.update_rtt(
+            Duration::from_millis(125),
+            Duration::from_millis(10),
+            Instant::now(),
+        );
+        assert!(pcd.get_ph_mut().stop_recording().is_ok());
+        let records = read_trace(&trace).unwrap();
+        std::fs::remove_file(&trace).unwrap();
+        let update = records.last().unwrap();
+        assert_eq!((update.po, update.depth), (PluginOp::UpdateRtt, 0));
+        assert_eq!(update.clock_reads.len(), 1);
+        assert_eq!(
+            update.nested_calls,
+            [
+                (NestedCall::Poctl(1), Ok(vec![PluginVal::Bool(false)])),
+                (NestedCall::Default, Err(ApiErrorCode::InvalidArgument)),
+                (NestedCall::Default, Ok(vec![]))
+            ]
+        );
+        let cd_replay_id = replayer.insert_plugin(&cd_path.into()).unwrap();
+        let cd_calls = replayer.replay_trace(cd_replay_id, &records, cd_id);
+        assert_eq!(cd_calls.len(), 1);
+        assert!(cd_calls[0].matches());
+    }
+
     #[test]
     fn enable() {
         let mut pcd =
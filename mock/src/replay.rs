@@ -0,0 +1,201 @@
+//! Deterministic replay of plugin calls recorded by a
+//! [`PluginHandler`](pluginop::handler::PluginHandler).
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::path::PathBuf;
+
+use pluginop::api::{CTPError, ConnectionToPlugin, ToPluginizableConnection};
+use pluginop::common::quic::{ConnectionField, RecoveryField};
+use pluginop::common::{Bytes, PluginVal};
+use pluginop::handler::PluginId;
+use pluginop::plugin::Env;
+use pluginop::record::{CallRecord, RecordedError, ReplayedAnswers};
+use pluginop::{BytesContent, Error, ParentReferencer, PluginizableConnection};
+use pluginop::{Exports, FunctionEnv, Store};
+
+/// A connection answering the reads of the plugin with the values of a recorded call. Writes are
+/// accepted and ignored. The other answers of the host, i.e., the times and the outputs of the
+/// nested calls, are served by the plugin handler, see [`Replayer::replay`].
+pub struct ReplayConnection {
+    pc: Option<ParentReferencer<PluginizableConnection<Self>>>,
+    connection_reads: RefCell<VecDeque<(ConnectionField, Vec<u8>)>>,
+    recovery_reads: RefCell<VecDeque<(RecoveryField, Vec<u8>)>>,
+}
+
+/// Copy the next recorded value into `w`. The value is only consumed if `w` is long enough.
+fn next_read<'a, F>(
+    reads: &mut VecDeque<(F, Vec<u8>)>,
+    w: &'a mut [u8],
+) -> postcard::Result<&'a mut [u8]> {
+    let (_, value) = reads
+        .front()
+        .ok_or(postcard::Error::DeserializeUnexpectedEnd)?;
+    let w = w
+        .get_mut(..value.len())
+        .ok_or(postcard::Error::SerializeBufferFull)?;
+    w.copy_from_slice(value);
+    reads.pop_front();
+    Ok(w)
+}
+
+impl ConnectionToPlugin for ReplayConnection {
+    fn get_recovery<'a>(
+        &self,
+        _: RecoveryField,
+        w: &'a mut [u8],
+    ) -> postcard::Result<&'a mut [u8]> {
+        next_read(&mut self.recovery_reads.borrow_mut(), w)
+    }
+
+    fn set_recovery(&mut self, _: RecoveryField, _: &[u8]) -> Result<(), CTPError> {
+        Ok(())
+    }
+
+    fn get_connection<'a>(
+        &self,
+        _: ConnectionField,
+        w: &'a mut [u8],
+    ) -> postcard::Result<&'a mut [u8]> {
+        next_read(&mut self.connection_reads.borrow_mut(), w)
+    }
+
+    fn set_connection(&mut self, _: ConnectionField, _: &[u8]) -> Result<(), CTPError> {
+        Ok(())
+    }
+}
+
+impl ToPluginizableConnection<ReplayConnection> for ReplayConnection {
+    fn set_pluginizable_connection(&mut self, pc: *mut PluginizableConnection<Self>) {
+        self.pc = Some(ParentReferencer::new(pc));
+    }
+
+    fn get_pluginizable_connection(&mut self) -> Option<&mut PluginizableConnection<Self>> {
+        self.pc.as_deref_mut()
+    }
+}
+
+/// The outcome of a replayed call.
+#[derive(Debug)]
+pub struct ReplayedCall {
+    /// The recorded call.
+    pub record: CallRecord,
+    /// The result of the replayed call.
+    pub result: Result<Vec<PluginVal>, Error>,
+    /// The bytes written by the plugin to each `Bytes` tag of the recorded call.
+    pub bytes_written: Vec<(u64, Vec<u8>)>,
+}
+
+impl ReplayedCall {
+    /// Whether the replayed call wrote the same bytes and ended as the recorded one did.
+    /// Failures other than operation errors only need to happen in both calls.
+    pub fn matches(&self) -> bool {
+        let mut recorded_written = self.record.bytes_written.clone();
+        recorded_written.sort();
+        if recorded_written != self.bytes_written {
+            return false;
+        }
+        match (&self.record.outputs, &self.result) {
+            (Ok(recorded), Ok(replayed)) => recorded == replayed,
+            (Err(recorded), Err(replayed)) => match (recorded, RecordedError::from(replayed)) {
+                (RecordedError::Failure(_), RecordedError::Failure(_)) => true,
+                (recorded, replayed) => *recorded == replayed,
+            },
+            _ => false,
+        }
+    }
+}
+
+/// Re-executes recorded calls on a plugin, serving its reads from the records.
+pub struct Replayer(Box<PluginizableConnection<ReplayConnection>>);
+
+impl Replayer {
+    pub fn new(
+        exports_func: fn(&mut Store, &FunctionEnv<Env<ReplayConnection>>) -> Exports,
+    ) -> Replayer {
+        let conn = ReplayConnection {
+            pc: None,
+            connection_reads: RefCell::new(VecDeque::new()),
+            recovery_reads: RefCell::new(VecDeque::new()),
+        };
+        let mut ret = Replayer(PluginizableConnection::new_pluginizable_connection(
+            exports_func,
+            conn,
+        ));
+        let pc_ptr = ret.0.as_mut() as *mut _;
+        ret.0.get_conn_mut().set_pluginizable_connection(pc_ptr);
+        ret.0.get_ph_mut().set_pluginizable_connection(pc_ptr);
+        ret
+    }
+
+    /// Insert the plugin to replay, and return its identifier.
+    pub fn insert_plugin(&mut self, path: &PathBuf) -> Result<PluginId, Error> {
+        self.0.get_ph_mut().insert_plugin_testing(path)
+    }
+
+    /// Re-execute the recorded call on the plugin with the provided identifier. The `Bytes`
+    /// given as inputs hold the bytes that the plugin read during the recorded call, and the
+    /// plugin gets the recorded times and outputs of its nested calls, which are not run.
+    pub fn replay(&mut self, id: PluginId, record: &CallRecord) -> ReplayedCall {
+        let conn = self.0.get_conn_mut();
+        *conn.connection_reads.get_mut() = record.connection_reads.iter().cloned().collect();
+        *conn.recovery_reads.get_mut() = record.recovery_reads.iter().cloned().collect();
+        let ph = self.0.get_ph_mut();
+        ph.set_replayed_answers(Some(ReplayedAnswers::from_record(record)));
+        let mut tags = Vec::new();
+        let inputs: Vec<PluginVal> = record
+            .inputs
+            .iter()
+            .map(|pv| match pv {
+                PluginVal::Bytes(b) => {
+                    let read = record
+                        .bytes
+                        .iter()
+                        .find(|(tag, _)| *tag == b.tag)
+                        .map(|(_, data)| data.as_slice())
+                        .unwrap_or_default();
+                    let mut content = Vec::with_capacity(read.len() + b.max_write_len as usize);
+                    content.extend_from_slice(read);
+                    let replayed = Bytes {
+                        tag: ph.add_bytes_content(BytesContent::Copied(content)).tag,
+                        ..*b
+                    };
+                    tags.push((b.tag, replayed));
+                    PluginVal::Bytes(replayed)
+                }
+                pv => pv.clone(),
+            })
+            .collect();
+        let result = ph.call_plugin(id, &record.po, record.anchor, &inputs);
+        // The plugin consumed the bytes it read, so only the ones it wrote are left.
+        let mut bytes_written: Vec<(u64, Vec<u8>)> = tags
+            .into_iter()
+            .filter_map(|(tag, b)| Some((tag, ph.get_copied_bytes(&b)?)))
+            .filter(|(_, written)| !written.is_empty())
+            .collect();
+        bytes_written.sort();
+        bytes_written.dedup();
+        ph.clear_bytes_content();
+        ph.set_replayed_answers(None);
+        ReplayedCall {
+            record: record.clone(),
+            result,
+            bytes_written,
+        }
+    }
+
+    /// Re-execute on the plugin with the provided identifier all the calls that the host made
+    /// to the plugin `recorded` in the `trace`. Nested calls are not replayed on their own.
+    pub fn replay_trace(
+        &mut self,
+        id: PluginId,
+        trace: &[CallRecord],
+        recorded: PluginId,
+    ) -> Vec<ReplayedCall> {
+        trace
+            .iter()
+            .filter(|r| r.plugin == recorded && r.depth == 0)
+            .map(|r| self.replay(id, r))
+            .collect()
+    }
+}
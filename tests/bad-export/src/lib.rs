@@ -0,0 +1,7 @@
+use pluginop_wasm::PluginEnv;
+
+// The frame type is not a valid hexadecimal number.
+#[no_mangle]
+pub extern fn parse_frame_zz(_: &mut PluginEnv) -> i64 {
+    0
+}
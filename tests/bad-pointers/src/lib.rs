@@ -0,0 +1,119 @@
+//! Calls every host API function with pointers that do not lie within the plugin memory. The
+//! host must reject each of them with `ApiErrorCode::BadPointer` instead of panicking.
+
+use pluginop_wasm::quic::{ConnectionField, RecoveryField};
+use pluginop_wasm::{ApiErrorCode, PluginEnv, PluginVal};
+
+const BAD_POINTER: i64 = ApiErrorCode::BadPointer.code();
+
+extern "C" {
+    fn save_output_from_plugin(ptr: u32, len: u32) -> i64;
+    fn save_outputs_from_plugin(ptr: u32, len: u32) -> i64;
+    fn print_from_plugin(ptr: u32, len: u32);
+    fn log_from_plugin(level: u32, ptr: u32, len: u32) -> i64;
+    fn get_connection_from_plugin(fp: u32, fl: u32, rp: u32, rl: u32) -> i64;
+    fn set_connection_from_plugin(fp: u32, fl: u32, vp: u32, vl: u32) -> i64;
+    fn get_input_from_plugin(index: u32, rp: u32, rl: u32) -> i64;
+    fn get_inputs_from_plugin(rp: u32, rl: u32) -> i64;
+    fn get_bytes_from_plugin(tag: u64, len: u64, rp: u32, rl: u32) -> i64;
+    fn put_bytes_from_plugin(tag: u64, ptr: u32, len: u32) -> i64;
+    fn register_from_plugin(ptr: u32, len: u32) -> i64;
+    fn set_timer_from_plugin(ptr: u32, len: u32, id: u64, timer_id: u64) -> i64;
+    fn get_unix_instant_from_plugin(rp: u32, rl: u32) -> i64;
+    fn create_file_from_plugin(ptr: u32, len: u32) -> i64;
+    fn write_file_from_plugin(fd: i64, ptr: u32, len: u32) -> i64;
+    fn get_recovery_from_plugin(fp: u32, fl: u32, rp: u32, rl: u32) -> i64;
+    fn set_recovery_from_plugin(fp: u32, fl: u32, vp: u32, vl: u32) -> i64;
+    fn poctl_from_plugin(id: u64, ip: u32, il: u32, rp: u32, rl: u32) -> i64;
+    fn call_default_from_plugin(ip: u32, il: u32, rp: u32, rl: u32) -> i64;
+    fn get_call_result_from_plugin(rp: u32, rl: u32) -> i64;
+}
+
+/// The current size of the plugin memory, in bytes.
+fn memory_end() -> u32 {
+    (core::arch::wasm32::memory_size(0) * 65536) as u32
+}
+
+/// A pseudo-random generator, to hammer the host with many ranges.
+struct Lcg(u64);
+
+impl Lcg {
+    fn next(&mut self) -> u32 {
+        self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (self.0 >> 32) as u32
+    }
+
+    /// A range of `(ptr, len)` that does not fit in the plugin memory.
+    fn bad_range(&mut self, case: u32) -> (u32, u32) {
+        let end = memory_end();
+        match case {
+            0 => (u32::MAX, 1),
+            1 => (end, 1),
+            2 => (end - 1, 2),
+            3 => (0, u32::MAX),
+            4 => (u32::MAX, u32::MAX),
+            5 => (end / 2, end),
+            c if c % 2 == 0 => (end + self.next() % (u32::MAX - end), 1 + self.next() % 4096),
+            _ => {
+                let ptr = self.next() % end;
+                (ptr, end - ptr + 1 + self.next() % 4096)
+            }
+        }
+    }
+}
+
+const CASES: u32 = 64;
+
+#[no_mangle]
+pub extern fn hammer(_penv: &mut PluginEnv) -> i64 {
+    let field = postcard::to_allocvec(&ConnectionField::MaxTxData).unwrap();
+    let rfield = postcard::to_allocvec(&RecoveryField::LatestRtt).unwrap();
+    let inputs = postcard::to_allocvec(&Vec::<PluginVal>::new()).unwrap();
+    let (fp, fl) = (field.as_ptr() as u32, field.len() as u32);
+    let (rfp, rfl) = (rfield.as_ptr() as u32, rfield.len() as u32);
+    let (ip, il) = (inputs.as_ptr() as u32, inputs.len() as u32);
+    let mut out = vec![0u8; 64];
+    let (op, ol) = (out.as_mut_ptr() as u32, out.len() as u32);
+    let mut rng = Lcg(42);
+    for case in 0..CASES {
+        let (p, l) = rng.bad_range(case);
+        let results: [i64; 25] = unsafe {
+            print_from_plugin(p, l);
+            [
+                save_output_from_plugin(p, l),
+                save_outputs_from_plugin(p, l),
+                // Messages may be filtered out before reading them.
+                match log_from_plugin(1, p, l) {
+                    0 => BAD_POINTER,
+                    r => r,
+                },
+                get_connection_from_plugin(p, l, op, ol),
+                get_connection_from_plugin(fp, fl, p, l),
+                set_connection_from_plugin(p, l, op, ol),
+                set_connection_from_plugin(fp, fl, p, l),
+                get_input_from_plugin(0, p, l),
+                get_inputs_from_plugin(p, l),
+                get_bytes_from_plugin(0, 8, p, l),
+                put_bytes_from_plugin(0, p, l),
+                register_from_plugin(p, l),
+                set_timer_from_plugin(p, l, 1, 1),
+                get_unix_instant_from_plugin(p, l),
+                create_file_from_plugin(p, l),
+                write_file_from_plugin(0, p, l),
+                get_recovery_from_plugin(p, l, op, ol),
+                get_recovery_from_plugin(rfp, rfl, p, l),
+                set_recovery_from_plugin(p, l, op, ol),
+                set_recovery_from_plugin(rfp, rfl, p, l),
+                poctl_from_plugin(0, p, l, op, ol),
+                poctl_from_plugin(0, ip, il, p, l),
+                call_default_from_plugin(p, l, op, ol),
+                call_default_from_plugin(ip, il, p, l),
+                get_call_result_from_plugin(p, l),
+            ]
+        };
+        if let Some(idx) = results.iter().position(|r| *r != BAD_POINTER) {
+            return 1000 * (case as i64 + 1) + idx as i64;
+        }
+    }
+    0
+}
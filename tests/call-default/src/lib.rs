@@ -0,0 +1,90 @@
+use pluginop_wasm::{quic::{Frame, QVal}, ApiErrorCode, Error, PluginEnv, PluginVal};
+
+// Let the host update the RTT, but with a doubled latest RTT, and report when it did.
+#[no_mangle]
+pub extern fn update_rtt(penv: &mut PluginEnv) -> i64 {
+    let mut inputs = match penv.get_inputs() {
+        Ok(i) => i,
+        Err(_) => return -1,
+    };
+    inputs[0] = match inputs[0] {
+        PluginVal::Duration(d) => PluginVal::Duration(d * 2),
+        _ => return -2,
+    };
+    // A nested call cannot reach the host implementation of this operation.
+    match penv.poctl(1, &inputs).as_deref() {
+        Ok([PluginVal::Bool(false)]) => {}
+        _ => return -4,
+    }
+    // The host implementation checks its inputs.
+    match penv.call_default(&[]) {
+        Err(Error::Api(ApiErrorCode::InvalidArgument)) => {}
+        _ => return -7,
+    }
+    if penv.call_default(&inputs).is_err() {
+        return -3;
+    }
+    let now = match penv.get_unix_instant() {
+        Ok(now) => now,
+        Err(_) => return -5,
+    };
+    match penv.save_output(PluginVal::UNIXInstant(now)) {
+        Ok(()) => 0,
+        Err(_) => -6,
+    }
+}
+
+// Tells whether a host implementation can be invoked from here.
+#[no_mangle]
+pub extern fn plugin_control_1(penv: &mut PluginEnv) -> i64 {
+    let inputs = match penv.get_inputs() {
+        Ok(i) => i,
+        Err(_) => return -1,
+    };
+    let reached = match penv.call_default(&inputs) {
+        Ok(_) => true,
+        Err(Error::Api(ApiErrorCode::NotFound)) => false,
+        Err(_) => return -3,
+    };
+    match penv.save_output(reached.into()) {
+        Ok(()) => 0,
+        Err(_) => -2,
+    }
+}
+
+// Let the host parse the frame, but announce one more byte.
+#[no_mangle]
+pub extern fn parse_frame_10(penv: &mut PluginEnv) -> i64 {
+    let inputs = match penv.get_inputs() {
+        Ok(i) => i,
+        Err(_) => return -1,
+    };
+    let mut md = match penv.call_default(&inputs).as_deref() {
+        Ok([PluginVal::QUIC(QVal::Frame(Frame::MaxData(md)))]) => *md,
+        Ok(_) => return -2,
+        Err(_) => return -3,
+    };
+    md.maximum_data += 1;
+    match penv.save_output(Frame::MaxData(md).into()) {
+        Ok(()) => 0,
+        Err(_) => -4,
+    }
+}
+
+// Let the host log the frame, with the content it provided.
+#[no_mangle]
+pub extern fn log_frame_10(penv: &mut PluginEnv) -> i64 {
+    let inputs = match penv.get_inputs() {
+        Ok(i) => i,
+        Err(_) => return -1,
+    };
+    let len = match penv.call_default(&inputs).as_deref() {
+        Ok([len]) => len.clone(),
+        Ok(_) => return -2,
+        Err(_) => return -3,
+    };
+    match penv.save_output(len) {
+        Ok(()) => 0,
+        Err(_) => -4,
+    }
+}
@@ -0,0 +1,30 @@
+use pluginop_wasm::PluginEnv;
+
+fn recurse(depth: u64) -> u64 {
+    if depth == 0 {
+        return 0;
+    }
+    // Called through a function pointer, so that the module needs a table.
+    let next: fn(u64) -> u64 = std::hint::black_box(recurse);
+    1 + next(depth - 1)
+}
+
+// Recurse as deep as the input tells.
+#[no_mangle]
+pub extern fn plugin_control_1(penv: &mut PluginEnv) -> i64 {
+    let depth = match penv.get_input::<u64>(0) {
+        Ok(d) => d,
+        _ => return -1,
+    };
+    if recurse(depth) == depth {
+        0
+    } else {
+        -2
+    }
+}
+
+#[no_mangle]
+pub extern fn init(penv: &mut PluginEnv) -> i64 {
+    penv.enable();
+    0
+}
@@ -0,0 +1,19 @@
+use pluginop_wasm::PluginEnv;
+
+// Refuses the operation.
+#[no_mangle]
+pub extern fn pre_prepare_frame_10(_: &mut PluginEnv) -> i64 {
+    7
+}
+
+// Traps.
+#[no_mangle]
+pub extern fn post_prepare_frame_10(_: &mut PluginEnv) -> i64 {
+    panic!("buggy plugin")
+}
+
+// Traps before writing the frame.
+#[no_mangle]
+pub extern fn pre_write_frame_10(_: &mut PluginEnv) -> i64 {
+    panic!("buggy plugin")
+}
@@ -1,4 +1,4 @@
-use pluginop_wasm::{PluginEnv, Error, quic::{QVal, ConnectionField, Frame}};
+use pluginop_wasm::{PluginEnv, ApiErrorCode, Error, quic::{QVal, ConnectionField, Frame}};
 
 #[no_mangle]
 pub extern fn process_frame_10(penv: &mut PluginEnv) -> i64 {
@@ -14,7 +14,7 @@ pub extern fn process_frame_10(penv: &mut PluginEnv) -> i64 {
     }
     let hdr = match penv.get_input::<QVal>(1) {
         Ok(QVal::Header(hdr)) => hdr,
-        Err(Error::ShortInternalBuffer) => return 0,
+        Err(Error::Api(ApiErrorCode::NotFound)) => return 0,
         _ => return -4,
     };
     let _bytes = match penv.get_bytes(hdr.destination_cid.tag, hdr.destination_cid.max_read_len) {
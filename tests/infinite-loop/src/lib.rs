@@ -0,0 +1,43 @@
+use pluginop_wasm::{PluginEnv, UnixInstant};
+
+fn spin() -> i64 {
+    let mut i: u64 = 0;
+    loop {
+        i = std::hint::black_box(i.wrapping_add(1));
+    }
+}
+
+#[no_mangle]
+pub extern fn plugin_control_1(_: &mut PluginEnv) -> i64 {
+    spin()
+}
+
+#[no_mangle]
+pub extern fn plugin_control_2(_: &mut PluginEnv) -> i64 {
+    let mut sum: u64 = 0;
+    for i in 0..100 {
+        sum = std::hint::black_box(sum + i);
+    }
+    if sum == 4950 {
+        0
+    } else {
+        -1
+    }
+}
+
+#[no_mangle]
+pub extern fn plugin_control_3(penv: &mut PluginEnv) -> i64 {
+    let now = match penv.get_input::<UnixInstant>(0) {
+        Ok(i) => i,
+        _ => return -1,
+    };
+    if penv.set_timer(now, 1, 1).is_err() {
+        return -2;
+    }
+    0
+}
+
+#[no_mangle]
+pub extern fn on_plugin_timeout_1(_: &mut PluginEnv) -> i64 {
+    spin()
+}
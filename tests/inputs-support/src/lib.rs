@@ -21,7 +21,7 @@ pub extern fn set_values(penv: &mut PluginEnv) -> i64 {
         Ok(i) => i,
         Err(_) => return -1,
     };
-    let (val1, val2) = if let (Ok(v1), Ok(v2)) = (inputs[0].try_into(), inputs[1].try_into()) {
+    let (val1, val2) = if let (Ok(v1), Ok(v2)) = (inputs[0].clone().try_into(), inputs[1].clone().try_into()) {
         (v1, v2)
     } else {
         return -2;
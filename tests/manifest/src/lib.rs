@@ -0,0 +1,23 @@
+use pluginop_wasm::{quic::ConnectionField, Error, PluginEnv};
+
+pluginop_wasm::plugin_manifest! {
+    name: "manifest",
+    version: "0.1.0",
+    author: "pluginop",
+    permissions: ["output", "opaque"],
+}
+
+#[no_mangle]
+pub extern fn init(penv: &mut PluginEnv) -> i64 {
+    penv.enable();
+    0
+}
+
+#[no_mangle]
+pub extern fn plugin_control_1(penv: &mut PluginEnv) -> i64 {
+    match penv.set_connection(ConnectionField::MaxTxData, 5000_u64) {
+        Ok(()) => 0,
+        Err(Error::PermissionDenied) => -1,
+        Err(_) => -2,
+    }
+}
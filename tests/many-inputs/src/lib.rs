@@ -0,0 +1,74 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use pluginop_wasm::{ApiErrorCode, PluginEnv, PluginVal};
+
+extern "C" {
+    fn get_inputs_from_plugin(rp: u32, rl: u32) -> i64;
+}
+
+/// The number of times the nested operation ran.
+static NESTED_CALLS: AtomicU64 = AtomicU64::new(0);
+
+fn sum(values: &[PluginVal]) -> u64 {
+    values.iter().fold(0u64, |acc, v| match v {
+        PluginVal::U64(v) => acc.wrapping_add(*v),
+        _ => acc,
+    })
+}
+
+// Returns the number of inputs and the wrapping sum of the `U64` ones.
+#[no_mangle]
+pub extern fn sum_inputs(penv: &mut PluginEnv) -> i64 {
+    let inputs = match penv.get_inputs() {
+        Ok(i) => i,
+        Err(_) => return -1,
+    };
+    match penv.save_outputs(&[(inputs.len() as u64).into(), sum(&inputs).into()]) {
+        Ok(()) => 0,
+        Err(_) => -1,
+    }
+}
+
+// Echoes its inputs as outputs.
+#[no_mangle]
+pub extern fn plugin_control_1(penv: &mut PluginEnv) -> i64 {
+    NESTED_CALLS.fetch_add(1, Ordering::Relaxed);
+    let inputs = match penv.get_inputs() {
+        Ok(i) => i,
+        Err(_) => return -1,
+    };
+    match penv.save_outputs(&inputs) {
+        Ok(()) => 0,
+        Err(_) => -1,
+    }
+}
+
+// Same as `sum_inputs`, but on the outputs of `plugin_control_1` run with the inputs. Also
+// returns the number of times `plugin_control_1` ran.
+#[no_mangle]
+pub extern fn sum_nested(penv: &mut PluginEnv) -> i64 {
+    let inputs = match penv.get_inputs() {
+        Ok(i) => i,
+        Err(_) => return -1,
+    };
+    let outputs = match penv.poctl(1, &inputs) {
+        Ok(o) => o,
+        Err(_) => return -2,
+    };
+    let calls = NESTED_CALLS.load(Ordering::Relaxed);
+    match penv.save_outputs(&[(outputs.len() as u64).into(), sum(&outputs).into(), calls.into()]) {
+        Ok(()) => 0,
+        Err(_) => -3,
+    }
+}
+
+// Returns the error code of fetching the inputs in a buffer too short to hold their length.
+#[no_mangle]
+pub extern fn tiny_buffer(_penv: &mut PluginEnv) -> i64 {
+    let mut buf = [0u8; 2];
+    let code = unsafe { get_inputs_from_plugin(buf.as_mut_ptr() as u32, buf.len() as u32) };
+    match ApiErrorCode::from_code(code) {
+        Some(ApiErrorCode::BufferTooShortForLength) => 0,
+        _ => -1,
+    }
+}
@@ -0,0 +1,22 @@
+use pluginop_wasm::PluginEnv;
+
+// Allocate the number of bytes given as input.
+#[no_mangle]
+pub extern fn plugin_control_1(penv: &mut PluginEnv) -> i64 {
+    let len = match penv.get_input::<u64>(0) {
+        Ok(l) => l as usize,
+        _ => return -1,
+    };
+    let v = std::hint::black_box(vec![1u8; len]);
+    if v.len() == len {
+        0
+    } else {
+        -2
+    }
+}
+
+#[no_mangle]
+pub extern fn init(penv: &mut PluginEnv) -> i64 {
+    penv.enable();
+    0
+}
@@ -0,0 +1,60 @@
+use pluginop_wasm::{Duration, Error, PluginCell, PluginEnv};
+
+use lazy_static::lazy_static;
+
+struct Data {
+    periodic_fired: u64,
+}
+
+lazy_static! {
+    static ref DATA: PluginCell<Data> = PluginCell::new(Data { periodic_fired: 0 });
+}
+
+#[no_mangle]
+pub extern fn init(penv: &mut PluginEnv) -> i64 {
+    penv.enable();
+    0
+}
+
+#[no_mangle]
+pub extern fn arm_timers(penv: &mut PluginEnv) -> i64 {
+    // A periodic timer, and a timer whose callback fails.
+    if penv.set_periodic_timer(Duration::from_millis(10), 1, 1).is_err() {
+        return -1;
+    }
+    if penv.set_timer_after(Duration::from_millis(20), 2, 2).is_err() {
+        return -2;
+    }
+    // The host only allows two pending timers.
+    match penv.set_timer_after(Duration::from_millis(100), 3, 3) {
+        Err(Error::TooManyTimers) => {}
+        _ => return -3,
+    }
+    // But re-arming an existing one is fine.
+    if penv.set_timer_after(Duration::from_millis(20), 2, 2).is_err() {
+        return -4;
+    }
+    0
+}
+
+#[no_mangle]
+pub extern fn on_plugin_timeout_1(penv: &mut PluginEnv) -> i64 {
+    DATA.get_mut().periodic_fired += 1;
+    if DATA.periodic_fired == 3 && penv.cancel_timer(1).is_err() {
+        return -1;
+    }
+    0
+}
+
+#[no_mangle]
+pub extern fn on_plugin_timeout_2(_: &mut PluginEnv) -> i64 {
+    -1
+}
+
+#[no_mangle]
+pub extern fn periodic_fired(penv: &mut PluginEnv) -> i64 {
+    match penv.save_output(DATA.periodic_fired.into()) {
+        Ok(()) => 0,
+        _ => -1,
+    }
+}
@@ -0,0 +1,43 @@
+use pluginop_wasm::{fd::FileDescriptor, quic::ConnectionField, Duration, Error, PluginEnv};
+
+#[no_mangle]
+pub extern fn init(penv: &mut PluginEnv) -> i64 {
+    penv.enable();
+    0
+}
+
+#[no_mangle]
+pub extern fn plugin_control_1(penv: &mut PluginEnv) -> i64 {
+    match penv.set_connection(ConnectionField::MaxTxData, 5000_u64) {
+        Ok(()) => 0,
+        Err(Error::PermissionDenied) => -1,
+        Err(_) => -2,
+    }
+}
+
+#[no_mangle]
+pub extern fn plugin_control_2(_: &mut PluginEnv) -> i64 {
+    match FileDescriptor::create("permissions.txt") {
+        Ok(_) => 0,
+        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => -1,
+        Err(_) => -2,
+    }
+}
+
+#[no_mangle]
+pub extern fn plugin_control_3(penv: &mut PluginEnv) -> i64 {
+    match penv.set_timer_after(Duration::from_secs(1), 1, 1) {
+        Ok(()) => 0,
+        Err(Error::PermissionDenied) => -1,
+        Err(_) => -2,
+    }
+}
+
+#[no_mangle]
+pub extern fn plugin_control_4(penv: &mut PluginEnv) -> i64 {
+    match penv.poctl(1, &[]) {
+        Ok(_) => 0,
+        Err(Error::PermissionDenied) => -1,
+        Err(_) => -2,
+    }
+}
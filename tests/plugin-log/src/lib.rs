@@ -0,0 +1,15 @@
+use pluginop_wasm::{plog, PluginEnv};
+
+pluginop_wasm::plugin_manifest! {
+    name: "logger",
+    version: "0.1.0",
+}
+
+#[no_mangle]
+pub extern fn plugin_control_1(_: &mut PluginEnv) -> i64 {
+    let answer = 42;
+    plog::info!("hello {}", "host");
+    plog::debug!(answer = answer, ok = true; "with fields");
+    plog::error!(target: "cc", "something went wrong");
+    0
+}
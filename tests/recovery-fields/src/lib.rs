@@ -0,0 +1,42 @@
+use pluginop_wasm::{
+    quic::{ConnectionField, KPacketNumberSpace, RecoveryField},
+    ApiErrorCode, Duration, Error, PluginEnv,
+};
+
+// Returns the smoothed RTT and the number of accesses to unsupported fields reported as not
+// found.
+#[no_mangle]
+pub extern fn read_recovery(penv: &mut PluginEnv) -> i64 {
+    let srtt: Duration = match penv.get_recovery(RecoveryField::SmoothedRtt) {
+        Ok(d) => d,
+        Err(_) => return -1,
+    };
+    let unsupported = [
+        RecoveryField::LatestRtt,
+        RecoveryField::MinRtt,
+        RecoveryField::PtoCount(KPacketNumberSpace::ApplicationData),
+        RecoveryField::CongestionWindow,
+    ];
+    let mut not_found = 0u64;
+    for field in unsupported {
+        match penv.get_recovery::<u64>(field) {
+            Err(Error::Api(ApiErrorCode::NotFound)) => not_found += 1,
+            _ => return -2,
+        }
+    }
+    let results = [
+        penv.set_recovery(RecoveryField::LatestRtt, Duration::from_millis(1)),
+        penv.get_connection::<bool>(ConnectionField::IsServer).map(|_| ()),
+        penv.set_connection(ConnectionField::IsServer, true),
+    ];
+    for res in results {
+        match res {
+            Err(Error::Api(ApiErrorCode::NotFound)) => not_found += 1,
+            _ => return -2,
+        }
+    }
+    match penv.save_outputs(&[srtt.into(), not_found.into()]) {
+        Ok(()) => 0,
+        Err(_) => -3,
+    }
+}
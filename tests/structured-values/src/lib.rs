@@ -0,0 +1,23 @@
+use pluginop_wasm::PluginEnv;
+
+// Takes a name, a list of packet numbers, the largest packet number seen so far and some bytes,
+// and returns a description, the new largest packet number and the reversed bytes.
+#[no_mangle]
+pub extern fn describe(penv: &mut PluginEnv) -> i64 {
+    let (name, pns, largest, data) = match (
+        penv.get_input::<String>(0),
+        penv.get_input::<Vec<u64>>(1),
+        penv.get_input::<Option<u64>>(2),
+        penv.get_input::<Vec<u8>>(3),
+    ) {
+        (Ok(n), Ok(p), Ok(l), Ok(d)) => (n, p, l, d),
+        _ => return -1,
+    };
+    let reason = format!("{}: {} packets", name, pns.len());
+    let largest = pns.iter().copied().max().max(largest);
+    let reversed: Vec<u8> = data.into_iter().rev().collect();
+    match penv.save_outputs(&[reason.into(), largest.into(), reversed.into()]) {
+        Ok(()) => 0,
+        Err(_) => -2,
+    }
+}
@@ -0,0 +1,13 @@
+use pluginop_wasm::PluginEnv;
+
+// Traps.
+#[no_mangle]
+pub extern fn should_send_frame_12(_: &mut PluginEnv) -> i64 {
+    panic!("buggy plugin")
+}
+
+// Forgets to provide its output.
+#[no_mangle]
+pub extern fn wire_len_10(_: &mut PluginEnv) -> i64 {
+    0
+}
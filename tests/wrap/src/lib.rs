@@ -0,0 +1,85 @@
+use pluginop_wasm::{quic::ConnectionField, PluginEnv, PluginVal};
+
+#[no_mangle]
+pub extern fn plugin_control_1(penv: &mut PluginEnv) -> i64 {
+    match penv.save_output(PluginVal::U64(41)) {
+        Ok(()) => 0,
+        Err(_) => -1,
+    }
+}
+
+// Increments the result of the operation.
+#[no_mangle]
+pub extern fn wrap_plugin_control_1(penv: &mut PluginEnv) -> i64 {
+    let res = match penv.get_inputs().as_deref() {
+        Ok([.., PluginVal::U64(v)]) => *v,
+        _ => return -1,
+    };
+    match penv.save_output(PluginVal::U64(res + 1)) {
+        Ok(()) => 0,
+        Err(_) => -2,
+    }
+}
+
+// Records the result of the operation.
+#[no_mangle]
+pub extern fn post_plugin_control_1(penv: &mut PluginEnv) -> i64 {
+    let res = match penv.get_inputs().as_deref() {
+        Ok([.., PluginVal::U64(v)]) => *v,
+        _ => return -1,
+    };
+    match penv.set_connection(ConnectionField::MaxTxData, res) {
+        Ok(()) => 0,
+        Err(_) => -2,
+    }
+}
+
+// Observes without replacing the result.
+#[no_mangle]
+pub extern fn wrap_should_send_frame_10(_: &mut PluginEnv) -> i64 {
+    0
+}
+
+// Records the decision of the host.
+#[no_mangle]
+pub extern fn post_should_send_frame_10(penv: &mut PluginEnv) -> i64 {
+    let res = match penv.get_inputs().as_deref() {
+        Ok([.., PluginVal::Bool(b)]) => *b,
+        _ => return -1,
+    };
+    match penv.set_connection(ConnectionField::MaxTxData, 1000 + res as u64) {
+        Ok(()) => 0,
+        Err(_) => -2,
+    }
+}
+
+// Overrides the decision of the host.
+#[no_mangle]
+pub extern fn wrap_should_send_frame_11(penv: &mut PluginEnv) -> i64 {
+    match penv.save_output(PluginVal::Bool(true)) {
+        Ok(()) => 0,
+        Err(_) => -1,
+    }
+}
+
+// Returns an output that does not match the decision of the host.
+#[no_mangle]
+pub extern fn wrap_should_send_frame_13(penv: &mut PluginEnv) -> i64 {
+    match penv.save_output(PluginVal::U64(1)) {
+        Ok(()) => 0,
+        Err(_) => -1,
+    }
+}
+
+// Records the decision of the host.
+#[no_mangle]
+pub extern fn post_should_send_frame_13(penv: &mut PluginEnv) -> i64 {
+    let res = match penv.get_inputs().as_deref() {
+        Ok([.., PluginVal::Bool(b)]) => *b,
+        _ => return -1,
+    };
+    match penv.set_connection(ConnectionField::MaxTxData, 1000 + res as u64) {
+        Ok(()) => 0,
+        Err(_) => -2,
+    }
+}
@@ -5,7 +5,7 @@ use std::{
     path::Path,
 };
 
-use pluginop_common::{WASMLen, WASMPtr};
+use pluginop_common::{ApiErrorCode, WASMLen, WASMPtr};
 use std::convert::TryFrom;
 
 extern "C" {
@@ -34,6 +34,10 @@ impl FileDescriptor {
             fd if fd >= 0 => Ok(FileDescriptor {
                 fd: FileDescriptorType::File(fd),
             }),
+            fd if fd == ApiErrorCode::PermissionDenied.code() => Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "Cannot create file",
+            )),
             _ => Err(std::io::Error::new(
                 std::io::ErrorKind::Other,
                 "Cannot create file",
@@ -52,9 +56,15 @@ impl Write for FileDescriptor {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
         match self.fd {
             FileDescriptorType::File(fd) => {
-                match u32::try_from(unsafe {
-                    write_file_from_plugin(fd, buf.as_ptr() as u32, buf.len() as u32)
-                }) {
+                let written =
+                    unsafe { write_file_from_plugin(fd, buf.as_ptr() as u32, buf.len() as u32) };
+                if written == ApiErrorCode::PermissionDenied.code() {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::PermissionDenied,
+                        "error when writing",
+                    ));
+                }
+                match u32::try_from(written) {
                     Ok(written) => Ok(written as usize),
                     Err(_) => Err(std::io::Error::new(
                         std::io::ErrorKind::Other,
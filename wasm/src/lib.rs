@@ -6,11 +6,11 @@
 
 use std::cell::UnsafeCell;
 use std::convert::TryInto;
-use std::mem;
 use std::ops::Deref;
 
 pub use pluginop_common::quic;
 use pluginop_common::APIResult;
+pub use pluginop_common::ApiErrorCode;
 pub use pluginop_common::PluginOp;
 use pluginop_common::WASMLen;
 use pluginop_common::WASMPtr;
@@ -18,18 +18,19 @@ use pluginop_common::WASMPtr;
 use pluginop_common::quic::Registration;
 pub use pluginop_common::Bytes;
 pub use pluginop_common::PluginVal;
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::convert::TryFrom;
 pub use std::time::Duration;
 pub use unix_time::Instant as UnixInstant;
 
-/// The maximum size of a result, may be subject to future changes.
-const SIZE: usize = 1500;
+/// The initial size of the buffer receiving a result from the host. The buffer grows when the
+/// host reports that it is too short.
+const INITIAL_RESULT_LEN: usize = 256;
 
 /// Errors that may occur when interacting with the Plugin API.
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub enum Error {
-    /// An error occurred in the host-side API function.
+    /// An error occurred in the host-side API function, or the plugin misused it.
     APICallError,
     /// Requested operation on [`Bytes`] is invalid.
     BadBytes,
@@ -39,10 +40,72 @@ pub enum Error {
     ShortInternalBuffer,
     /// An error occurred during the (de)serialization process.
     SerializeError,
+    /// The plugin was not granted the permission required by the operation.
+    PermissionDenied,
+    /// The plugin reached its maximum number of pending timers.
+    TooManyTimers,
+    /// The host API function failed for another reason.
+    Api(ApiErrorCode),
+}
+
+impl Error {
+    /// The error corresponding to the `code` returned by a host API function.
+    pub fn from_code(code: APIResult) -> Error {
+        match ApiErrorCode::from_code(code) {
+            Some(ApiErrorCode::PermissionDenied) => Error::PermissionDenied,
+            Some(ApiErrorCode::TooManyTimers) => Error::TooManyTimers,
+            Some(ApiErrorCode::BufferTooShort) => Error::ShortInternalBuffer,
+            Some(ApiErrorCode::Serialize) => Error::SerializeError,
+            Some(ApiErrorCode::BadBytes) => Error::BadBytes,
+            Some(e) => Error::Api(e),
+            None => Error::APICallError,
+        }
+    }
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Turn the value returned by a host API function into a [`Result`].
+fn check(res: APIResult) -> Result<APIResult> {
+    if res >= 0 {
+        Ok(res)
+    } else {
+        Err(Error::from_code(res))
+    }
+}
+
+/// Get a result from the host with `get`, that writes it in the provided buffer. If the buffer is
+/// too short, the host writes the required length in its first four bytes, as a little-endian
+/// `u32`, and `get` is called again with a large enough buffer.
+fn read_result(mut get: impl FnMut(WASMPtr, WASMLen) -> APIResult) -> Result<Vec<u8>> {
+    let mut res = vec![0u8; INITIAL_RESULT_LEN];
+    loop {
+        match check(get(res.as_mut_ptr() as WASMPtr, res.len() as WASMLen)) {
+            Ok(_) => return Ok(res),
+            Err(Error::ShortInternalBuffer) => {
+                let required = u32::from_le_bytes([res[0], res[1], res[2], res[3]]) as usize;
+                if required <= res.len() {
+                    return Err(Error::ShortInternalBuffer);
+                }
+                res = vec![0u8; required];
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Get the outputs of a nested call with `call`, that runs it and writes its outputs in the
+/// provided buffer. If the buffer is too short, the host keeps the outputs, and they are fetched
+/// as in [`read_result`] without running the call again.
+fn read_call_result(call: impl FnOnce(WASMPtr, WASMLen) -> APIResult) -> Result<Vec<PluginVal>> {
+    let mut call = Some(call);
+    let res = read_result(|ptr, len| match call.take() {
+        Some(call) => call(ptr, len),
+        None => unsafe { get_call_result_from_plugin(ptr, len) },
+    })?;
+    postcard::from_bytes(&res).map_err(|_| Error::SerializeError)
+}
+
 extern "C" {
     /* General output function */
     fn save_output_from_plugin(ptr: WASMPtr, len: WASMLen) -> APIResult;
@@ -78,6 +141,13 @@ extern "C" {
     /* Set a custom timer */
     fn set_timer_from_plugin(ts_ptr: WASMPtr, ts_len: WASMLen, id: u64, timer_id: u64)
         -> APIResult;
+    /* Set a custom timer relative to the current time, possibly periodic */
+    fn set_relative_timer_from_plugin(
+        delay_ns: u64,
+        period_ns: u64,
+        id: u64,
+        timer_id: u64,
+    ) -> APIResult;
     /* Cancel the timer with the given id */
     fn cancel_timer_from_plugin(id: u64) -> APIResult;
     /* Gets the current UNIX time */
@@ -106,6 +176,15 @@ extern "C" {
         res_ptr: WASMPtr,
         res_len: WASMLen,
     ) -> APIResult;
+    /* Calls the host implementation of the defined operation */
+    fn call_default_from_plugin(
+        input_ptr: WASMPtr,
+        input_len: WASMLen,
+        res_ptr: WASMPtr,
+        res_len: WASMLen,
+    ) -> APIResult;
+    /* Gets the outputs of the last nested call, when they did not fit in its buffer */
+    fn get_call_result_from_plugin(res_ptr: WASMPtr, res_len: WASMLen) -> APIResult;
 }
 
 /// A companion structure, always passed as first argument of any plugin operation function,
@@ -117,29 +196,25 @@ impl PluginEnv {
     /// Store a new plugin output.
     pub fn save_output(&self, v: PluginVal) -> Result<()> {
         let serialized_value = postcard::to_allocvec(&v).map_err(|_| Error::SerializeError)?;
-        match unsafe {
+        check(unsafe {
             save_output_from_plugin(
                 serialized_value.as_ptr() as WASMPtr,
                 serialized_value.len() as WASMLen,
             )
-        } {
-            0 => Ok(()),
-            _ => Err(Error::APICallError),
-        }
+        })
+        .map(|_| ())
     }
 
     /// Store all the plugin outputs.
     pub fn save_outputs(&self, v: &[PluginVal]) -> Result<()> {
         let serialized_value = postcard::to_allocvec(&v).map_err(|_| Error::SerializeError)?;
-        match unsafe {
+        check(unsafe {
             save_outputs_from_plugin(
                 serialized_value.as_ptr() as WASMPtr,
                 serialized_value.len() as WASMLen,
             )
-        } {
-            0 => Ok(()),
-            _ => Err(Error::APICallError),
-        }
+        })
+        .map(|_| ())
     }
 
     /// Print the provided string on the standard output.
@@ -153,21 +228,16 @@ impl PluginEnv {
         T: TryFrom<PluginVal>,
     {
         let serialized_field = postcard::to_allocvec(&field).map_err(|_| Error::SerializeError)?;
-        let mut res = Vec::<u8>::with_capacity(SIZE).into_boxed_slice();
-        let err = unsafe {
+        let res = read_result(|ptr, len| unsafe {
             get_connection_from_plugin(
                 serialized_field.as_ptr() as WASMPtr,
                 serialized_field.len() as WASMLen,
-                res.as_mut_ptr() as WASMPtr,
-                SIZE as WASMLen,
+                ptr,
+                len,
             )
-        };
-        if err != 0 {
-            return Err(Error::APICallError);
-        }
-        let slice = unsafe { std::slice::from_raw_parts(res.as_ptr(), SIZE) };
+        })?;
         let plugin_val: PluginVal =
-            postcard::from_bytes(slice).map_err(|_| Error::SerializeError)?;
+            postcard::from_bytes(&res).map_err(|_| Error::SerializeError)?;
         plugin_val.try_into().map_err(|_| Error::BadType)
     }
 
@@ -179,36 +249,32 @@ impl PluginEnv {
         let serialized_field = postcard::to_allocvec(&field).map_err(|_| Error::SerializeError)?;
         let serialized_value =
             postcard::to_allocvec(&v.into()).map_err(|_| Error::SerializeError)?;
-        match unsafe {
+        check(unsafe {
             set_connection_from_plugin(
                 serialized_field.as_ptr() as WASMPtr,
                 serialized_field.len() as WASMLen,
                 serialized_value.as_ptr() as WASMPtr,
                 serialized_value.len() as WASMLen,
             )
-        } {
-            0 => Ok(()),
-            _ => Err(Error::APICallError),
-        }
+        })
+        .map(|_| ())
     }
 
     /// Get a recovery field.
-    pub fn get_recovery<'de, T>(&self, field: quic::RecoveryField) -> T
+    pub fn get_recovery<T>(&self, field: quic::RecoveryField) -> Result<T>
     where
-        T: Deserialize<'de>,
+        T: DeserializeOwned,
     {
-        let serialized_field = postcard::to_allocvec(&field).expect("serialized field");
-        let mut res = Vec::<u8>::with_capacity(SIZE).into_boxed_slice();
-        unsafe {
+        let serialized_field = postcard::to_allocvec(&field).map_err(|_| Error::SerializeError)?;
+        let res = read_result(|ptr, len| unsafe {
             get_recovery_from_plugin(
                 serialized_field.as_ptr() as WASMPtr,
                 serialized_field.len() as WASMLen,
-                res.as_mut_ptr() as WASMPtr,
-                SIZE as WASMLen,
-            );
-        }
-        let slice = unsafe { std::slice::from_raw_parts(res.as_ptr(), SIZE) };
-        postcard::from_bytes(slice).expect("no error")
+                ptr,
+                len,
+            )
+        })?;
+        postcard::from_bytes(&res).map_err(|_| Error::SerializeError)
     }
 
     /// Set a recovery field.
@@ -219,17 +285,15 @@ impl PluginEnv {
         let serialized_field = postcard::to_allocvec(&field).map_err(|_| Error::SerializeError)?;
         let serialized_value =
             postcard::to_allocvec(&v.into()).map_err(|_| Error::SerializeError)?;
-        match unsafe {
+        check(unsafe {
             set_recovery_from_plugin(
                 serialized_field.as_ptr() as WASMPtr,
                 serialized_field.len() as WASMLen,
                 serialized_value.as_ptr() as WASMPtr,
                 serialized_value.len() as WASMLen,
             )
-        } {
-            0 => Ok(()),
-            _ => Err(Error::APICallError),
-        }
+        })
+        .map(|_| ())
     }
 
     /// Get an input.
@@ -238,14 +302,8 @@ impl PluginEnv {
         T: TryFrom<PluginVal>,
         <T as TryFrom<PluginVal>>::Error: std::fmt::Debug,
     {
-        let mut res = Vec::<u8>::with_capacity(SIZE).into_boxed_slice();
-        if unsafe { get_input_from_plugin(index, res.as_mut_ptr() as WASMPtr, SIZE as WASMLen) }
-            != 0
-        {
-            return Err(Error::ShortInternalBuffer);
-        }
-        let slice = unsafe { std::slice::from_raw_parts(res.as_ptr(), SIZE) };
-        let input: PluginVal = match postcard::from_bytes(slice) {
+        let res = read_result(|ptr, len| unsafe { get_input_from_plugin(index, ptr, len) })?;
+        let input: PluginVal = match postcard::from_bytes(&res) {
             Ok(i) => i,
             Err(_) => return Err(Error::SerializeError),
         };
@@ -254,33 +312,25 @@ impl PluginEnv {
 
     /// Get the inputs.
     pub fn get_inputs(&self) -> Result<Vec<PluginVal>> {
-        let mut res = Vec::<u8>::with_capacity(SIZE).into_boxed_slice();
-        if unsafe { get_inputs_from_plugin(res.as_mut_ptr() as WASMPtr, SIZE as WASMLen) } != 0 {
-            return Err(Error::ShortInternalBuffer);
-        }
-        let slice = unsafe { std::slice::from_raw_parts(res.as_ptr(), SIZE) };
-        postcard::from_bytes(slice).map_err(|_| Error::SerializeError)
+        let res = read_result(|ptr, len| unsafe { get_inputs_from_plugin(ptr, len) })?;
+        postcard::from_bytes(&res).map_err(|_| Error::SerializeError)
     }
 
     /// Read some bytes and advances the related buffer (i.e., multiple calls give different results).
     pub fn get_bytes(&mut self, tag: u64, len: u64) -> Result<Vec<u8>> {
-        let mut res = Vec::<u8>::with_capacity(len as usize).into_boxed_slice();
-        let len =
-            unsafe { get_bytes_from_plugin(tag, len, res.as_mut_ptr() as WASMPtr, len as WASMLen) };
-        if len < 0 {
-            return Err(Error::BadBytes);
-        }
-        let slice = unsafe { std::slice::from_raw_parts(res.as_ptr(), len as usize) };
-        Ok(slice.to_vec())
+        let mut res = vec![0u8; len as usize];
+        let len = check(unsafe {
+            get_bytes_from_plugin(tag, len, res.as_mut_ptr() as WASMPtr, len as WASMLen)
+        })?;
+        res.truncate(len as usize);
+        Ok(res)
     }
 
     /// Write some bytes and advances the related buffer (i.e., multiple calls gives different results).
     pub fn put_bytes(&mut self, tag: u64, b: &[u8]) -> Result<usize> {
-        let written =
-            unsafe { put_bytes_from_plugin(tag, b.as_ptr() as WASMPtr, b.len() as WASMLen) };
-        if written < 0 {
-            return Err(Error::BadBytes);
-        }
+        let written = check(unsafe {
+            put_bytes_from_plugin(tag, b.as_ptr() as WASMPtr, b.len() as WASMLen)
+        })?;
         Ok(written as usize)
     }
 
@@ -288,12 +338,10 @@ impl PluginEnv {
     /// the initialization of the plugin.
     pub fn register(&mut self, r: Registration) -> Result<()> {
         let serialized = postcard::to_allocvec(&r).map_err(|_| Error::SerializeError)?;
-        match unsafe {
+        check(unsafe {
             register_from_plugin(serialized.as_ptr() as WASMPtr, serialized.len() as WASMLen)
-        } {
-            0 => Ok(()),
-            _ => Err(Error::APICallError),
-        }
+        })
+        .map(|_| ())
     }
 
     /// Set a timer at the provided time to call the given callback function with the
@@ -302,38 +350,55 @@ impl PluginEnv {
     /// Returns the identifier to the timer event, as provided as argument.
     pub fn set_timer(&mut self, ts: UnixInstant, id: u64, timer_id: u64) -> Result<()> {
         let serialized_ts = postcard::to_allocvec(&ts).map_err(|_| Error::SerializeError)?;
-        match unsafe {
+        check(unsafe {
             set_timer_from_plugin(
                 serialized_ts.as_ptr() as WASMPtr,
                 serialized_ts.len() as WASMLen,
                 id,
                 timer_id,
             )
-        } {
-            0 => Ok(()),
-            _ => Err(Error::APICallError),
+        })
+        .map(|_| ())
+    }
+
+    /// Same as [`PluginEnv::set_timer`], but the timer fires after `delay` from now.
+    pub fn set_timer_after(&mut self, delay: Duration, id: u64, timer_id: u64) -> Result<()> {
+        self.set_relative_timer(delay, Duration::ZERO, id, timer_id)
+    }
+
+    /// Same as [`PluginEnv::set_timer`], but the timer fires every `period` from now, until
+    /// cancelled. Periods missed by the host are skipped. `period` must not be zero.
+    pub fn set_periodic_timer(&mut self, period: Duration, id: u64, timer_id: u64) -> Result<()> {
+        if period.is_zero() {
+            return Err(Error::Api(ApiErrorCode::InvalidArgument));
         }
+        self.set_relative_timer(period, period, id, timer_id)
+    }
+
+    /// Arm a timer firing after `delay`, and then every `period` if it is not zero.
+    fn set_relative_timer(
+        &mut self,
+        delay: Duration,
+        period: Duration,
+        id: u64,
+        timer_id: u64,
+    ) -> Result<()> {
+        let as_nanos = |d: Duration| u64::try_from(d.as_nanos()).unwrap_or(u64::MAX);
+        check(unsafe {
+            set_relative_timer_from_plugin(as_nanos(delay), as_nanos(period), id, timer_id)
+        })
+        .map(|_| ())
     }
 
     /// Cancel the timer event having the identifier provided.
     pub fn cancel_timer(&mut self, id: u64) -> Result<()> {
-        match unsafe { cancel_timer_from_plugin(id) } {
-            0 => Ok(()),
-            _ => Err(Error::APICallError),
-        }
+        check(unsafe { cancel_timer_from_plugin(id) }).map(|_| ())
     }
 
     /// Get the current UNIX instant.
     pub fn get_unix_instant(&self) -> Result<UnixInstant> {
-        let size = mem::size_of::<UnixInstant>();
-        let mut res = Vec::<u8>::with_capacity(size).into_boxed_slice();
-        let err =
-            unsafe { get_unix_instant_from_plugin(res.as_mut_ptr() as WASMPtr, size as WASMLen) };
-        if err != 0 {
-            return Err(Error::APICallError);
-        }
-        let slice = unsafe { std::slice::from_raw_parts(res.as_ptr(), size) };
-        postcard::from_bytes(slice).map_err(|_| Error::SerializeError)
+        let res = read_result(|ptr, len| unsafe { get_unix_instant_from_plugin(ptr, len) })?;
+        postcard::from_bytes(&res).map_err(|_| Error::SerializeError)
     }
 
     /// Fully enable the plugin operations.
@@ -347,22 +412,102 @@ impl PluginEnv {
     pub fn poctl(&mut self, id: u64, params: &[PluginVal]) -> Result<Vec<PluginVal>> {
         let serialized_inputs =
             postcard::to_allocvec(&params).map_err(|_| Error::SerializeError)?;
-        let mut res = Vec::<u8>::with_capacity(SIZE).into_boxed_slice();
-        let err = unsafe {
+        read_call_result(|ptr, len| unsafe {
             poctl_from_plugin(
                 id,
                 serialized_inputs.as_ptr() as WASMPtr,
                 serialized_inputs.len() as WASMLen,
-                res.as_mut_ptr() as WASMPtr,
-                SIZE as WASMLen,
+                ptr,
+                len,
             )
-        };
-        if err != 0 {
-            return Err(Error::APICallError);
-        }
-        let slice = unsafe { std::slice::from_raw_parts(res.as_ptr(), SIZE) };
-        postcard::from_bytes(slice).map_err(|_| Error::SerializeError)
+        })
     }
+
+    /// Run the host implementation of the operation this plugin is defining with the provided
+    /// inputs, and return its outputs. The inputs are in the same order as the ones returned by
+    /// [`PluginEnv::get_inputs`], which can be modified before being passed. `Bytes` inputs
+    /// standing for host buffers are ignored, the host always uses its original buffers. The
+    /// ones standing for byte vectors provide their current content, and can be replaced by a
+    /// `PluginVal::ByteVec`.
+    ///
+    /// This enables a plugin to only handle some cases of an operation, and let the host handle
+    /// the other ones.
+    ///
+    /// Fails with [`ApiErrorCode::NotFound`] if there is no host implementation to call, e.g.,
+    /// from an anchor or a plugin control operation, with [`ApiErrorCode::InvalidArgument`] if
+    /// an input is missing or has an unexpected type, and with [`ApiErrorCode::HostRejected`] if
+    /// the host implementation failed.
+    pub fn call_default(&mut self, inputs: &[PluginVal]) -> Result<Vec<PluginVal>> {
+        let serialized_inputs =
+            postcard::to_allocvec(&inputs).map_err(|_| Error::SerializeError)?;
+        read_call_result(|ptr, len| unsafe {
+            call_default_from_plugin(
+                serialized_inputs.as_ptr() as WASMPtr,
+                serialized_inputs.len() as WASMLen,
+                ptr,
+                len,
+            )
+        })
+    }
+}
+
+#[doc(hidden)]
+pub use pluginop_common::api_version;
+
+/// Copy a string into an array of bytes, at compile time. Used by [`plugin_manifest`].
+#[doc(hidden)]
+pub const fn manifest_bytes<const N: usize>(s: &str) -> [u8; N] {
+    let bytes = s.as_bytes();
+    let mut res = [0; N];
+    let mut i = 0;
+    while i < N {
+        res[i] = bytes[i];
+        i += 1;
+    }
+    res
+}
+
+/// Embed in the plugin bytecode a manifest describing it, that the host reads before
+/// instantiating the plugin. This macro should be invoked once, at the root of the plugin crate.
+///
+/// The `author`, `priority` and `permissions` fields are optional. `priority` orders the plugin
+/// relative to the other ones defining the same operations, higher values coming first, unless
+/// the host sets a priority itself. When `permissions` is present, the host never grants the
+/// plugin a permission outside this list.
+///
+/// ```ignore
+/// pluginop_wasm::plugin_manifest! {
+///     name: "my-plugin",
+///     version: "0.1.0",
+///     author: "Jane Doe",
+///     priority: 10,
+///     permissions: ["output", "connection_access"],
+/// }
+/// ```
+#[macro_export]
+macro_rules! plugin_manifest {
+    (
+        name: $name:literal,
+        version: $version:literal
+        $(, author: $author:literal)?
+        $(, priority: $priority:literal)?
+        $(, permissions: [$($perm:literal),* $(,)?])?
+        $(,)?
+    ) => {
+        const _: () = {
+            const MANIFEST: &str = concat!(
+                "name=", $name, "\n",
+                "version=", $version, "\n",
+                $("author=", $author, "\n",)?
+                "api_version=", $crate::api_version!(), "\n",
+                $("priority=", $priority, "\n",)?
+                $("permissions=", $($perm, ",",)* "\n",)?
+            );
+            #[link_section = "pluginop.manifest"]
+            #[used]
+            static PLUGINOP_MANIFEST: [u8; MANIFEST.len()] = $crate::manifest_bytes(MANIFEST);
+        };
+    };
 }
 
 /// A cell structure to be used in single-threaded plugins.
@@ -397,3 +542,4 @@ unsafe impl<T: Send> Send for PluginCell<T> {}
 unsafe impl<T: Sync> Sync for PluginCell<T> {}
 
 pub mod fd;
+pub mod plog;
@@ -0,0 +1,119 @@
+//! Leveled logging through the `log` facade of the host.
+//!
+//! The macros of this module take the arguments of [`format!`], optionally preceded by a
+//! `target` and by key-value fields separated from the message by a `;`. Fields must implement
+//! [`Display`]. The host tags each message with the name of the plugin and the operation it is
+//! running, and drops the ones more verbose than the log level of the plugin. The fields are
+//! given to the host logger as key-values.
+//!
+//! Logging is best-effort: the macros ignore failures, e.g., when the plugin lacks the
+//! permission to produce output. Use [`log`] to handle them.
+//!
+//! ```ignore
+//! use pluginop_wasm::plog;
+//!
+//! plog::info!("plugin initialized");
+//! plog::debug!(target: "cc", cwnd = cwnd, rtt = rtt.as_millis(); "new sample {}", n);
+//! ```
+
+use std::fmt::Display;
+
+pub use pluginop_common::LogLevel;
+use pluginop_common::{APIResult, LogRecord, WASMLen, WASMPtr};
+
+use crate::{check, Error, Result};
+
+extern "C" {
+    fn log_from_plugin(level: u32, ptr: WASMPtr, len: WASMLen) -> APIResult;
+}
+
+/// Log `message` with the provided `level`, `target` and `fields`. Prefer the macros of this
+/// module, unless the failures need to be handled. A message dropped because of its level is
+/// not a failure.
+pub fn log(
+    level: LogLevel,
+    target: &str,
+    message: &str,
+    fields: &[(&str, &dyn Display)],
+) -> Result<()> {
+    let record = LogRecord {
+        target: target.to_string(),
+        message: message.to_string(),
+        fields: fields
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect(),
+    };
+    let serialized = postcard::to_allocvec(&record).map_err(|_| Error::SerializeError)?;
+    check(unsafe {
+        log_from_plugin(
+            level as u32,
+            serialized.as_ptr() as WASMPtr,
+            serialized.len() as WASMLen,
+        )
+    })?;
+    Ok(())
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __plog_log {
+    ($level:expr, target: $target:expr, $($key:ident = $value:expr),+; $($arg:tt)+) => {{
+        let _ = $crate::plog::log(
+            $level,
+            $target,
+            &format!($($arg)+),
+            &[$((stringify!($key), &$value as &dyn ::std::fmt::Display)),+],
+        );
+    }};
+    ($level:expr, target: $target:expr, $($arg:tt)+) => {{
+        let _ = $crate::plog::log($level, $target, &format!($($arg)+), &[]);
+    }};
+    ($level:expr, $($key:ident = $value:expr),+; $($arg:tt)+) => {
+        $crate::__plog_log!($level, target: "", $($key = $value),+; $($arg)+)
+    };
+    ($level:expr, $($arg:tt)+) => {
+        $crate::__plog_log!($level, target: "", $($arg)+)
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __plog_error {
+    ($($arg:tt)+) => { $crate::__plog_log!($crate::plog::LogLevel::Error, $($arg)+) };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __plog_warn {
+    ($($arg:tt)+) => { $crate::__plog_log!($crate::plog::LogLevel::Warn, $($arg)+) };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __plog_info {
+    ($($arg:tt)+) => { $crate::__plog_log!($crate::plog::LogLevel::Info, $($arg)+) };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __plog_debug {
+    ($($arg:tt)+) => { $crate::__plog_log!($crate::plog::LogLevel::Debug, $($arg)+) };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __plog_trace {
+    ($($arg:tt)+) => { $crate::__plog_log!($crate::plog::LogLevel::Trace, $($arg)+) };
+}
+
+/// Log a message with the debug level.
+pub use crate::__plog_debug as debug;
+/// Log a message with the error level.
+pub use crate::__plog_error as error;
+/// Log a message with the info level.
+pub use crate::__plog_info as info;
+/// Log a message with the trace level.
+pub use crate::__plog_trace as trace;
+/// Log a message with the warn level.
+pub use crate::__plog_warn as warn;